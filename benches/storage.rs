@@ -0,0 +1,73 @@
+//! Compares the `Rope` line storage against the plain `Vec<String>` the
+//! buffer used before. Run with `cargo bench --bench storage`.
+
+#[allow(dead_code)]
+#[path = "../src/rope.rs"]
+mod rope;
+
+use rope::Rope;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const LINES: usize = 1_000_000;
+const EDITS: usize = 10_000;
+
+fn sample_lines() -> Vec<String> {
+    (0..LINES).map(|i| format!("{:>8} INFO request handled in {} ms", i, i % 997)).collect()
+}
+
+fn time(label: &str, f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed();
+    println!("  {:<44} {:>10.2?}", label, elapsed);
+    elapsed
+}
+
+fn main() {
+    println!("{} lines, {} edits near the top of the file", LINES, EDITS);
+
+    println!("Vec<String>");
+    let mut vec = sample_lines();
+    let vec_insert = time("insert_newline/delete_newline at line 10", || {
+        for _ in 0..EDITS {
+            let tail = vec[10].split_off(4);
+            vec.insert(11, tail);
+            let line = vec.remove(11);
+            vec[10].push_str(&line);
+        }
+    });
+    time("line lookups", || {
+        let mut total = 0;
+        for i in (0..LINES).step_by(7) {
+            total += vec[i].len();
+        }
+        black_box(total);
+    });
+
+    println!("Rope<String>");
+    let mut rope = Rope::from_vec(sample_lines());
+    let rope_insert = time("insert_newline/delete_newline at line 10", || {
+        for _ in 0..EDITS {
+            let tail = rope.get_mut(10).unwrap().split_off(4);
+            rope.insert(11, tail);
+            let line = rope.remove(11);
+            rope.get_mut(10).unwrap().push_str(&line);
+        }
+    });
+    time("line lookups", || {
+        let mut total = 0;
+        for i in (0..LINES).step_by(7) {
+            total += rope.get(i).map_or(0, |l| l.len());
+        }
+        black_box(total);
+    });
+    time("full iteration (save)", || {
+        black_box(rope.iter().map(|l| l.len()).sum::<usize>());
+    });
+
+    println!(
+        "Rope edits are {:.1}x faster than Vec edits",
+        vec_insert.as_secs_f64() / rope_insert.as_secs_f64()
+    );
+}
@@ -0,0 +1,339 @@
+//! The file browser: a directory tree confined to one root directory, and
+//! the overlay that shows it.
+
+use crate::editor::Editor;
+use crate::list::{ListKey, ListNav};
+use crate::prompt::PromptKind;
+use crate::terminal::Terminal;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub name: String,
+    /// Nesting below the root; top-level entries are 0.
+    pub depth: usize,
+    pub is_dir: bool,
+    pub expanded: bool,
+}
+
+/// Flattened listing of `root` with expanded directories' contents shown
+/// below them. Directories come first, then files, each sorted by name.
+pub struct FileTree {
+    root: PathBuf,
+    show_hidden: bool,
+    expanded: HashSet<PathBuf>,
+    entries: Vec<Entry>,
+}
+
+impl FileTree {
+    pub fn new(root: &Path) -> io::Result<Self> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a directory", root.display())));
+        }
+
+        let mut tree = FileTree {
+            root,
+            show_hidden: false,
+            expanded: HashSet::new(),
+            entries: Vec::new(),
+        };
+        tree.refresh();
+        Ok(tree)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn shows_hidden(&self) -> bool {
+        self.show_hidden
+    }
+
+    pub fn toggle_hidden(&mut self) {
+        self.show_hidden = !self.show_hidden;
+        self.refresh();
+    }
+
+    /// Re-read the directories from disk.
+    pub fn refresh(&mut self) {
+        let mut entries = Vec::new();
+        self.list(&self.root.clone(), 0, &mut entries);
+        self.entries = entries;
+    }
+
+    fn list(&self, dir: &Path, depth: usize, entries: &mut Vec<Entry>) {
+        let Ok(read_dir) = fs::read_dir(dir) else { return };
+
+        let mut children: Vec<(bool, String, PathBuf)> = read_dir
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                (entry.path().is_dir(), name, entry.path())
+            })
+            .filter(|(_, name, _)| self.show_hidden || !name.starts_with('.'))
+            .collect();
+        children.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.to_lowercase().cmp(&b.1.to_lowercase())));
+
+        for (is_dir, name, path) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            entries.push(Entry { path: path.clone(), name, depth, is_dir, expanded });
+            // Don't follow links out of the root
+            if expanded && self.contains(&path) {
+                self.list(&path, depth + 1, entries);
+            }
+        }
+    }
+
+    /// Expand or collapse the directory at `index`.
+    pub fn toggle(&mut self, index: usize) {
+        let Some(entry) = self.entries.get(index).filter(|e| e.is_dir) else { return };
+        if !self.expanded.remove(&entry.path) {
+            self.expanded.insert(entry.path.clone());
+        }
+        self.refresh();
+    }
+
+    /// Index of the directory the entry at `index` is listed under, if it
+    /// isn't at the top level.
+    pub fn parent_index(&self, index: usize) -> Option<usize> {
+        let depth = self.entries.get(index)?.depth;
+        (0..index).rev().find(|&i| self.entries[i].depth + 1 == depth)
+    }
+
+    /// Whether `path` (following links) lies inside the root.
+    pub fn contains(&self, path: &Path) -> bool {
+        fs::canonicalize(path).is_ok_and(|p| p.starts_with(&self.root))
+    }
+
+    /// `dir/name`, provided `name` is a plain file name and `dir` is inside
+    /// the root.
+    fn child_path(&self, dir: &Path, name: &str) -> io::Result<PathBuf> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' {}", name, reason));
+
+        if name.trim().is_empty() {
+            return Err(invalid("is empty"));
+        }
+        let mut components = Path::new(name).components();
+        let plain = matches!((components.next(), components.next()), (Some(Component::Normal(_)), None));
+        if !plain || name.contains(['/', '\\']) {
+            return Err(invalid("must be a plain name without directories"));
+        }
+        if !self.contains(dir) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "outside the browser's root directory"));
+        }
+        Ok(dir.join(name))
+    }
+
+    pub fn create_file(&mut self, dir: &Path, name: &str) -> io::Result<PathBuf> {
+        let path = self.child_path(dir, name)?;
+        fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        self.expanded.insert(dir.to_path_buf());
+        self.refresh();
+        Ok(path)
+    }
+
+    pub fn create_dir(&mut self, dir: &Path, name: &str) -> io::Result<PathBuf> {
+        let path = self.child_path(dir, name)?;
+        fs::create_dir(&path)?;
+        self.expanded.insert(dir.to_path_buf());
+        self.refresh();
+        Ok(path)
+    }
+
+    /// Rename `path` within its directory.
+    pub fn rename(&mut self, path: &Path, new_name: &str) -> io::Result<PathBuf> {
+        let dir = path.parent().unwrap_or(&self.root);
+        let target = self.child_path(dir, new_name)?;
+        if target.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", new_name)));
+        }
+
+        fs::rename(path, &target)?;
+        if self.expanded.remove(path) {
+            self.expanded.insert(target.clone());
+        }
+        self.refresh();
+        Ok(target)
+    }
+
+    /// Delete a file, or a directory with everything in it. A symlink is
+    /// removed itself, never what it points to.
+    pub fn delete(&mut self, path: &Path) -> io::Result<()> {
+        let parent = path.parent().unwrap_or(&self.root);
+        if path == self.root || !self.contains(parent) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "outside the browser's root directory"));
+        }
+
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        self.expanded.remove(path);
+        self.refresh();
+        Ok(())
+    }
+}
+
+impl Editor {
+    /// Browse the save directory as a tree: open files, and create, rename
+    /// or delete files and directories. Nothing outside the directory can be
+    /// reached from here.
+    pub fn file_browser(&mut self) {
+        let mut tree = match self.project_root().map(|root| FileTree::new(&root)) {
+            Some(Ok(tree)) => tree,
+            Some(Err(e)) => {
+                self.show_message(format!("Cannot browse: {}", e));
+                return;
+            },
+            None => {
+                self.show_message(String::from("No directory to browse"));
+                return;
+            },
+        };
+
+        let hint = "Enter open | n new file | m new dir | r rename | d delete | . hidden | q close";
+        let mut list = ListNav::new(self.screen_rows as usize);
+        let mut note = String::new();
+
+        loop {
+            let entries = tree.entries();
+            let mut items: Vec<String> = entries.iter()
+                .map(|e| {
+                    let marker = if !e.is_dir { "  " } else if e.expanded { "▾ " } else { "▸ " };
+                    let suffix = if e.is_dir { "/" } else { "" };
+                    format!("{}{}{}{}", "  ".repeat(e.depth), marker, e.name, suffix)
+                })
+                .collect();
+            if items.is_empty() {
+                items.push(String::from("  (empty)"));
+            }
+            list.set_len(entries.len());
+            self.draw_list(&items, list.selected(), list.top());
+
+            let hidden = if tree.shows_hidden() { " (showing hidden)" } else { "" };
+            let status = format!(" {}{}", tree.root().display(), hidden);
+            self.draw_overlay_bars(&status, if note.is_empty() { hint } else { &note });
+            note.clear();
+
+            let Ok(byte) = self.read_byte() else { break };
+            let selected = list.selected();
+            let entry = tree.entries().get(selected);
+            let path = entry.map(|e| e.path.clone());
+            let is_dir = entry.is_some_and(|e| e.is_dir);
+            let expanded = entry.is_some_and(|e| e.expanded);
+            // New entries go inside the selected directory, or next to the
+            // selected file
+            let target_dir = match &path {
+                Some(p) if is_dir => p.clone(),
+                Some(p) => p.parent().unwrap_or(tree.root()).to_path_buf(),
+                None => tree.root().to_path_buf(),
+            };
+
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape | ListKey::Byte(b'q') => break,
+                ListKey::Enter if is_dir => tree.toggle(selected),
+                ListKey::Enter => {
+                    let Some(path) = path else { continue };
+                    if !tree.contains(&path) {
+                        note = format!("{} leads outside {}", path.display(), tree.root().display());
+                        continue;
+                    }
+                    if let Err(e) = self.open_file(&path.to_string_lossy()) {
+                        self.show_message(format!("Could not open {}: {}", path.display(), e));
+                    }
+                    break;
+                },
+                ListKey::Byte(b'.') => {
+                    tree.toggle_hidden();
+                    list.set_len(tree.entries().len());
+                    list.select(path.and_then(|p| tree.entries().iter().position(|e| e.path == p)).unwrap_or(0));
+                },
+                ListKey::Byte(byte @ (b'n' | b'm')) => {
+                    let label = if byte == b'n' { "New file: " } else { "New directory: " };
+                    let Some(name) = self.prompt(PromptKind::Name, label) else { continue };
+                    let created = if byte == b'n' {
+                        tree.create_file(&target_dir, name.trim())
+                    } else {
+                        tree.create_dir(&target_dir, name.trim())
+                    };
+                    match created {
+                        Ok(new) => {
+                            list.set_len(tree.entries().len());
+                            list.select(tree.entries().iter().position(|e| e.path == new).unwrap_or(selected));
+                            note = format!("Created {}", new.display());
+                        },
+                        Err(e) => note = format!("Could not create {}: {}", name.trim(), e),
+                    }
+                },
+                ListKey::Byte(b'r') => {
+                    let Some(old) = path else { continue };
+                    let Some(name) = self.prompt(PromptKind::Name, "Rename to: ") else { continue };
+                    match self.rename_in_browser(&mut tree, &old, name.trim()) {
+                        Ok(new) => {
+                            list.set_len(tree.entries().len());
+                            list.select(tree.entries().iter().position(|e| e.path == new).unwrap_or(selected));
+                            note = format!("Renamed to {}", new.display());
+                        },
+                        Err(e) => note = format!("Could not rename {}: {}", old.display(), e),
+                    }
+                },
+                ListKey::Byte(b'd') => {
+                    let Some(path) = path else { continue };
+                    let question = if is_dir {
+                        format!("Delete directory {} and everything in it? (y/n)", path.display())
+                    } else {
+                        format!("Delete {}? (y/n)", path.display())
+                    };
+                    if self.ask(&question) != Some(b'y') {
+                        continue;
+                    }
+                    note = match tree.delete(&path) {
+                        Ok(()) => format!("Deleted {}", path.display()),
+                        Err(e) => format!("Could not delete {}: {}", path.display(), e),
+                    };
+                },
+                ListKey::Sequence(params, final_byte) => match (params.as_str(), final_byte) {
+                    ("", b'C') if is_dir && !expanded => tree.toggle(selected),
+                    ("", b'C') if is_dir => list.step(1),
+                    ("", b'D') if expanded => tree.toggle(selected),
+                    ("", b'D') => list.select(tree.parent_index(selected).unwrap_or(selected)),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        let _ = Terminal::show_cursor();
+    }
+
+    /// Rename a file or directory from the browser, moving any open buffers
+    /// that live under it along with it.
+    fn rename_in_browser(&mut self, tree: &mut FileTree, old: &Path, name: &str) -> io::Result<PathBuf> {
+        // Buffer paths may be written differently from the tree's, so match
+        // them up before the old path stops existing
+        let moved: Vec<(usize, PathBuf)> = (0..self.buffer_count())
+            .filter_map(|i| {
+                let path = fs::canonicalize(self.buffer_at(i).filename()?).ok()?;
+                Some((i, path.strip_prefix(old).ok()?.to_path_buf()))
+            })
+            .collect();
+
+        let new = tree.rename(old, name)?;
+        for (i, rest) in moved {
+            let path = if rest.as_os_str().is_empty() { new.clone() } else { new.join(rest) };
+            self.buffer_at_mut(i).set_filename(path);
+        }
+        Ok(new)
+    }
+}
@@ -1,44 +1,426 @@
-use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::env;
+use std::fs::{self, File};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::encoding::Encoding;
+use crate::hex;
+use crate::loader::{BackgroundLoader, LAZY_LOAD_THRESHOLD, LoadProgress};
+use crate::rope::Rope;
+use crate::syntax::{Highlight, Highlighter, Syntax};
 
 pub struct Buffer {
-    lines: Vec<String>,
+    lines: Rope<String>,
     filename: Option<PathBuf>,
     save_directory: Option<PathBuf>,
     modified: bool,
+    highlighter: Highlighter,
+    loader: Option<BackgroundLoader>,
+    /// Set when a lazy load failed part way; the buffer then only holds the
+    /// start of the file and must not be written back over it.
+    load_error: Option<String>,
+    /// Raw contents of a binary file, edited in the hex view. `lines` is
+    /// unused while this is set.
+    bytes: Option<Vec<u8>>,
+    /// Encoding the file was read in and will be written back in.
+    encoding: Encoding,
+    /// A file we failed to read. Saving over it is refused until
+    /// `allow_overwriting_unreadable` is called.
+    unreadable_path: Option<PathBuf>,
+    /// What the file looked like when we last read or wrote it, so changes
+    /// made by other programs can be noticed.
+    disk_state: Option<DiskState>,
+    /// Edits are refused while set: requested with `--readonly`, or because
+    /// the file can't be written.
+    read_only: bool,
+    /// Changes made in one go (such as a project-wide replace) that can be
+    /// undone as a whole, most recent last.
+    undo: Vec<UndoRecord>,
+}
+
+/// Lines rewritten by one change: their row and text before and after.
+struct UndoRecord {
+    description: String,
+    lines: Vec<(usize, String, String)>,
+}
+
+/// Snapshot of a file on disk. The hash is only taken when the whole file
+/// passed through memory anyway, i.e. not for lazily loaded files.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DiskState {
+    modified: Option<SystemTime>,
+    size: u64,
+    hash: Option<u64>,
+}
+
+impl DiskState {
+    fn of(path: &Path, contents: Option<&[u8]>) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(DiskState {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            hash: contents.map(hash_bytes),
+        })
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
 }
 
 impl Buffer {
     pub fn new() -> Self {
+        Buffer::with_lines(vec![String::new()], None)
+    }
+
+    fn with_lines(lines: Vec<String>, filename: Option<PathBuf>) -> Self {
+        let syntax = filename.as_deref().and_then(Syntax::for_path);
+        let highlighter = Highlighter::new(syntax, lines.len());
+
         Buffer {
-            lines: vec![String::new()],
-            filename: None,
+            lines: Rope::from_vec(lines),
+            filename,
             save_directory: None,
             modified: false,
+            highlighter,
+            loader: None,
+            load_error: None,
+            bytes: None,
+            encoding: Encoding::Utf8,
+            unreadable_path: None,
+            disk_state: None,
+            read_only: false,
+            undo: Vec::new(),
         }
     }
 
     pub fn from_file(path: PathBuf) -> io::Result<Self> {
-        let content = fs::read_to_string(&path)?;
+        Buffer::open(path, None)
+    }
+
+    /// Whether we may write `path`. Opening for writing without truncating
+    /// tells us for sure, where permission bits alone don't (ACLs, root,
+    /// read-only mounts).
+    fn is_writable(path: &Path) -> bool {
+        fs::OpenOptions::new().write(true).open(path).is_ok()
+    }
+
+    /// Open `path`, decoding it as `forced` if given and detecting the
+    /// encoding (or that it is binary) otherwise. Files we can't write are
+    /// opened read-only.
+    fn open(path: PathBuf, forced: Option<Encoding>) -> io::Result<Self> {
+        let mut buffer = Buffer::read(path, forced)?;
+        buffer.read_only = buffer.filename.as_deref().is_some_and(|p| !Buffer::is_writable(p));
+        Ok(buffer)
+    }
+
+    fn read(path: PathBuf, forced: Option<Encoding>) -> io::Result<Self> {
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+
+        let encoding = match forced {
+            Some(encoding) => encoding,
+            None => {
+                let mut sample = Vec::new();
+                (&mut file).take(hex::SAMPLE_SIZE as u64).read_to_end(&mut sample)?;
+                match Encoding::detect(&sample) {
+                    Some(encoding) => encoding,
+                    None => return Buffer::from_binary_file(path),
+                }
+            }
+        };
+        file.seek(SeekFrom::Start(0))?;
+
+        if size >= LAZY_LOAD_THRESHOLD && encoding.is_byte_oriented() {
+            let fallback = if forced.is_none() { encoding.fallback() } else { None };
+            return Buffer::from_large_file(path, file, size, encoding, fallback);
+        }
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (content, encoding) = match forced {
+            Some(encoding) => (encoding.decode(&bytes)?, encoding),
+            None => encoding.decode_detected(&bytes)?,
+        };
         let lines: Vec<String> = if content.is_empty() {
             vec![String::new()]
         } else {
             content.lines().map(|s| s.to_string()).collect()
         };
 
-        Ok(Buffer {
-            lines,
-            filename: Some(path),
-            save_directory: None,
-            modified: false,
-        })
+        let disk_state = DiskState::of(&path, Some(&bytes));
+        let mut buffer = Buffer::with_lines(lines, Some(path));
+        buffer.encoding = encoding;
+        buffer.disk_state = disk_state;
+        Ok(buffer)
+    }
+
+    /// Throw away the in-memory text and read the file again as `encoding`.
+    pub fn reload_with_encoding(&mut self, encoding: Encoding) -> io::Result<()> {
+        self.reopen(Some(encoding))
+    }
+
+    /// Throw away the in-memory contents and read the file again, keeping
+    /// the current encoding (binary files stay binary).
+    pub fn reload(&mut self) -> io::Result<()> {
+        let encoding = if self.is_binary() { None } else { Some(self.encoding) };
+        self.reopen(encoding)
+    }
+
+    fn reopen(&mut self, encoding: Option<Encoding>) -> io::Result<()> {
+        let path = self.filename.clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No filename"))?;
+
+        let mut reloaded = Buffer::open(path, encoding)?;
+        reloaded.save_directory = self.save_directory.take();
+        reloaded.read_only |= self.read_only;
+        *self = reloaded;
+        Ok(())
+    }
+
+    /// Whether another program changed the file since we last read or wrote
+    /// it. A new timestamp alone doesn't count when the contents are the same.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let (Some(path), Some(known)) = (&self.filename, self.disk_state) else { return false };
+        if self.loader.is_some() {
+            return false;
+        }
+        // A deleted file is simply written again on save
+        let Ok(metadata) = fs::metadata(path) else { return false };
+
+        let modified = metadata.modified().ok();
+        if modified == known.modified && metadata.len() == known.size {
+            return false;
+        }
+        if let Some(hash) = known.hash
+            && let Ok(bytes) = fs::read(path)
+            && hash_bytes(&bytes) == hash
+        {
+            self.disk_state = Some(DiskState { modified, size: metadata.len(), hash: Some(hash) });
+            return false;
+        }
+        true
+    }
+
+    /// Keep the in-memory version after the file changed on disk. The buffer
+    /// counts as modified, and this disk version won't be reported again.
+    pub fn keep_ours(&mut self) {
+        if let Some(path) = &self.filename {
+            self.disk_state = DiskState::of(path, fs::read(path).ok().as_deref());
+            self.modified = true;
+        }
+    }
+
+    /// The file's current contents on disk, decoded as this buffer's
+    /// encoding, for comparing against the buffer.
+    pub fn disk_contents(&self) -> io::Result<String> {
+        let path = self.filename.as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No filename"))?;
+        self.encoding.decode(&fs::read(path)?)
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Write the file in `encoding` from now on.
+    pub fn set_encoding(&mut self, encoding: Encoding) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "buffer is read-only"));
+        }
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.modified = true;
+        }
+        Ok(())
+    }
+
+    /// Show the start of a big file right away and read the rest in the background.
+    /// A file that isn't valid `encoding` after all is read again as
+    /// `fallback`, if given.
+    fn from_large_file(
+        path: PathBuf,
+        file: File,
+        size: u64,
+        encoding: Encoding,
+        fallback: Option<Encoding>,
+    ) -> io::Result<Self> {
+        let (lines, loader, encoding) = BackgroundLoader::start(file, size, encoding, fallback)?;
+        let disk_state = DiskState::of(&path, None);
+        let mut buffer = Buffer::with_lines(lines, Some(path));
+        buffer.disk_state = disk_state;
+        buffer.loader = Some(loader);
+        buffer.encoding = encoding;
+        Ok(buffer)
+    }
+
+    fn from_binary_file(path: PathBuf) -> io::Result<Self> {
+        let bytes = fs::read(&path)?;
+        let disk_state = DiskState::of(&path, Some(&bytes));
+        let mut buffer = Buffer::with_lines(vec![String::new()], Some(path));
+        buffer.disk_state = disk_state;
+        buffer.highlighter.set_syntax(None);
+        buffer.bytes = Some(bytes);
+        Ok(buffer)
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.bytes.is_some()
+    }
+
+    /// Raw contents of a binary buffer.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        self.bytes.as_deref()
+    }
+
+    /// Overwrite the high or low half of the byte at `offset` with `value`.
+    pub fn set_nibble(&mut self, offset: usize, high: bool, value: u8) {
+        if self.read_only {
+            return;
+        }
+        if let Some(byte) = self.bytes.as_mut().and_then(|b| b.get_mut(offset)) {
+            *byte = if high {
+                (*byte & 0x0f) | (value << 4)
+            } else {
+                (*byte & 0xf0) | (value & 0x0f)
+            };
+            self.modified = true;
+        }
+    }
+
+    /// Append whatever the background loader has read since the last call.
+    /// Returns whether the buffer changed, or the error that stopped loading.
+    pub fn poll_loading(&mut self) -> io::Result<bool> {
+        let Some(loader) = &mut self.loader else { return Ok(false) };
+
+        match loader.poll() {
+            LoadProgress::Lines(lines) => {
+                for line in lines {
+                    self.lines.push(line);
+                    self.highlighter.line_inserted(self.lines.len() - 1);
+                }
+                Ok(true)
+            },
+            LoadProgress::Restarted { encoding, lines } if !self.modified => {
+                self.lines = Rope::from_vec(lines);
+                self.highlighter = Highlighter::new(self.highlighter.syntax().cloned(), self.lines.len());
+                self.encoding = encoding;
+                self.undo.clear();
+                Ok(true)
+            },
+            LoadProgress::Restarted { encoding, .. } => {
+                // Reading it again would throw the edits away
+                self.loader = None;
+                let error = format!("not valid {}; reopen it as {} with the encoding command", self.encoding.name(), encoding.name());
+                self.load_error = Some(error.clone());
+                Err(io::Error::new(io::ErrorKind::InvalidData, error))
+            },
+            LoadProgress::Finished => {
+                self.loader = None;
+                Ok(true)
+            },
+            LoadProgress::Failed(e) => {
+                self.loader = None;
+                self.load_error = Some(e.to_string());
+                Err(e)
+            },
+            LoadProgress::Pending => Ok(false),
+        }
+    }
+
+    /// Wait for the background loader to read the rest of the file.
+    pub fn finish_loading(&mut self) -> io::Result<()> {
+        while self.loader.is_some() {
+            if !self.poll_loading()? {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        Ok(())
+    }
+
+    /// Percentage read while a large file is still loading.
+    pub fn loading_progress(&self) -> Option<u64> {
+        self.loader.as_ref().map(|l| l.percent())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Whether saving could write the file: not one that failed to open,
+    /// and not an existing file we lack permission to write.
+    pub fn can_write_file(&self) -> bool {
+        match &self.filename {
+            Some(path) => !path.exists() || Buffer::is_writable(path),
+            None => self.unreadable_path.is_none(),
+        }
+    }
+
+    /// Refuse to write while the contents are incomplete or read-only.
+    fn check_saveable(&self) -> io::Result<()> {
+        if self.loader.is_some() {
+            return Err(io::Error::other("file is still loading"));
+        }
+        if let Some(error) = &self.load_error {
+            return Err(io::Error::other(format!("file was only partly loaded ({}); not overwriting it", error)));
+        }
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "buffer is read-only"));
+        }
+        Ok(())
+    }
+
+    /// The bytes `save` writes: raw bytes for binary files, otherwise the
+    /// text in the file's encoding.
+    pub fn encoded(&self) -> io::Result<Vec<u8>> {
+        match &self.bytes {
+            Some(bytes) => Ok(bytes.clone()),
+            None => self.encoding.encode(&self.contents()),
+        }
+    }
+
+    /// The bytes `save` would write once `changes` (as for `replace_lines`)
+    /// are made, refused where `save` would be.
+    pub fn encoded_with(&self, changes: &[(usize, String)]) -> io::Result<Vec<u8>> {
+        self.check_saveable()?;
+        if self.bytes.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "binary buffers can't be changed by line"));
+        }
+        let mut lines: Vec<&str> = self.lines.iter().map(String::as_str).collect();
+        for (row, text) in changes {
+            if let Some(line) = lines.get_mut(*row) {
+                *line = text;
+            }
+        }
+        self.encoding.encode(&lines.join("\n"))
+    }
+
+    /// The file was written with `bytes`, this buffer's contents, by
+    /// something other than `save`.
+    pub fn mark_saved(&mut self, bytes: &[u8]) {
+        if let Some(path) = &self.filename {
+            self.disk_state = DiskState::of(path, Some(bytes));
+            self.modified = false;
+        }
     }
 
     pub fn save(&mut self) -> io::Result<()> {
+        self.check_saveable()?;
+
         if let Some(path) = &self.filename {
-            let content = self.lines.join("\n");
-            fs::write(path, content)?;
+            let bytes = self.encoded()?;
+            fs::write(path, &bytes)?;
+            self.disk_state = DiskState::of(path, Some(&bytes));
             self.modified = false;
             Ok(())
         } else {
@@ -46,23 +428,92 @@ impl Buffer {
         }
     }
 
-    pub fn save_as(&mut self, path: PathBuf) -> io::Result<()> {
-        // If we're in "start coding" mode (only save_directory set), construct full path
-        let full_path = if self.filename.is_none() && self.save_directory.is_some() {
-            let dir = self.save_directory.as_ref().unwrap();
-            dir.join(&path)
-        } else {
-            path
+    /// Save through an external privileged `command` when we can't write
+    /// the file ourselves. The contents go to a temporary file only we can
+    /// read, in a new directory only we can enter, then
+    /// `{temp}` and `{file}` in the command are replaced by the two paths,
+    /// e.g. `sudo cp {temp} {file}`.
+    pub fn save_privileged(&mut self, command: &str) -> io::Result<()> {
+        self.check_saveable()?;
+        let path = self.filename.clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No filename"))?;
+
+        let bytes = self.encoded()?;
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let (directory, temp) = private_temp_file(&name, &bytes)?;
+
+        // Substitute after splitting so paths with spaces stay one argument
+        let mut args = command.split_whitespace().map(|arg| {
+            arg.replace("{temp}", &temp.to_string_lossy())
+                .replace("{file}", &path.to_string_lossy())
+        });
+        let result = match args.next() {
+            Some(program) => Command::new(&program).args(args).status().and_then(|status| {
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(format!("{} failed ({})", program, status)))
+                }
+            }),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "privileged save command is empty")),
         };
-        
-        self.filename = Some(full_path);
+        let _ = fs::remove_file(&temp);
+        let _ = fs::remove_dir(&directory);
+        result?;
+
+        self.disk_state = DiskState::of(&path, Some(&bytes));
+        self.modified = false;
+        Ok(())
+    }
+
+    pub fn save_as(&mut self, path: PathBuf) -> io::Result<()> {
+        let full_path = self.resolve_save_path(path);
+        if self.is_unreadable_path(&full_path) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} could not be read; not overwriting it without confirmation", full_path.display()),
+            ));
+        }
+
+        self.set_filename(full_path);
         self.save()
     }
 
+    /// Where `save_as(path)` would write. A relative `path` is taken from
+    /// the save directory, never from wherever the editor was started.
+    pub fn resolve_save_path(&self, path: PathBuf) -> PathBuf {
+        match &self.save_directory {
+            Some(dir) if path.is_relative() => dir.join(&path),
+            _ => path,
+        }
+    }
+
+    /// Remember a file that failed to open so it isn't clobbered by accident.
+    pub fn protect_unreadable_path(&mut self, path: PathBuf) {
+        self.unreadable_path = Some(path);
+    }
+
+    pub fn is_unreadable_path(&self, path: &Path) -> bool {
+        let Some(unreadable) = &self.unreadable_path else { return false };
+        match (fs::canonicalize(unreadable), fs::canonicalize(path)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => unreadable == path,
+        }
+    }
+
+    /// The user confirmed they want to replace the unreadable file.
+    pub fn allow_overwriting_unreadable(&mut self) {
+        self.unreadable_path = None;
+    }
+
     pub fn set_save_directory(&mut self, directory: PathBuf) {
         self.save_directory = Some(directory);
     }
 
+    pub fn save_directory(&self) -> Option<&Path> {
+        self.save_directory.as_deref()
+    }
+
     pub fn get_line(&self, index: usize) -> Option<&str> {
         self.lines.get(index).map(|s| s.as_str())
     }
@@ -71,40 +522,150 @@ impl Buffer {
         self.lines.len()
     }
 
-    pub fn insert_char(&mut self, row: usize, col: usize, ch: char) {
-        if row < self.lines.len() {
-            if col <= self.lines[row].len() {
-                self.lines[row].insert(col, ch);
-                self.modified = true;
+    /// The whole buffer as one string, lines joined with `\n`.
+    pub fn contents(&self) -> String {
+        let mut content = String::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                content.push('\n');
             }
+            content.push_str(line);
+        }
+        content
+    }
+
+    pub fn insert_char(&mut self, row: usize, col: usize, ch: char) {
+        if self.read_only {
+            return;
+        }
+        if let Some(line) = self.lines.get_mut(row)
+            && col <= line.len()
+        {
+            line.insert(col, ch);
+            self.highlighter.line_changed(row);
+            self.modified = true;
+        }
+    }
+
+    /// Insert `text` (which must not contain newlines) at `col`.
+    pub fn insert_str(&mut self, row: usize, col: usize, text: &str) {
+        if self.read_only {
+            return;
+        }
+        if let Some(line) = self.lines.get_mut(row)
+            && !text.is_empty()
+            && col <= line.len()
+        {
+            line.insert_str(col, text);
+            self.highlighter.line_changed(row);
+            self.modified = true;
+        }
+    }
+
+    /// Remove the bytes `start..end` of a line.
+    pub fn delete_range(&mut self, row: usize, start: usize, end: usize) {
+        if self.read_only {
+            return;
+        }
+        if let Some(line) = self.lines.get_mut(row)
+            && start < end
+            && end <= line.len()
+        {
+            line.replace_range(start..end, "");
+            self.highlighter.line_changed(row);
+            self.modified = true;
         }
     }
 
     pub fn delete_char(&mut self, row: usize, col: usize) {
-        if row < self.lines.len() && col < self.lines[row].len() {
-            self.lines[row].remove(col);
+        if self.read_only {
+            return;
+        }
+        if let Some(line) = self.lines.get_mut(row)
+            && col < line.len()
+        {
+            line.remove(col);
+            self.highlighter.line_changed(row);
             self.modified = true;
         }
     }
 
     pub fn insert_newline(&mut self, row: usize, col: usize) {
-        if row < self.lines.len() {
-            let current_line = &self.lines[row];
-            let new_line = current_line[col..].to_string();
-            self.lines[row].truncate(col);
+        if self.read_only {
+            return;
+        }
+        if let Some(current_line) = self.lines.get_mut(row) {
+            let new_line = current_line.split_off(col);
             self.lines.insert(row + 1, new_line);
+            self.highlighter.line_changed(row);
+            self.highlighter.line_inserted(row + 1);
             self.modified = true;
         }
     }
 
     pub fn delete_newline(&mut self, row: usize) {
+        if self.read_only {
+            return;
+        }
         if row > 0 && row < self.lines.len() {
             let current = self.lines.remove(row);
-            self.lines[row - 1].push_str(&current);
+            if let Some(previous) = self.lines.get_mut(row - 1) {
+                previous.push_str(&current);
+            }
+            self.highlighter.line_removed(row);
+            self.highlighter.line_changed(row - 1);
             self.modified = true;
         }
     }
 
+    /// Replace whole lines, given as `(row, new text)`, as one change that
+    /// `undo` can take back. Returns false if nothing could be changed.
+    pub fn replace_lines(&mut self, changes: Vec<(usize, String)>, description: &str) -> bool {
+        if self.read_only {
+            return false;
+        }
+
+        let mut lines = Vec::with_capacity(changes.len());
+        for (row, text) in changes {
+            if let Some(line) = self.lines.get_mut(row) {
+                let before = std::mem::replace(line, text.clone());
+                self.highlighter.line_changed(row);
+                lines.push((row, before, text));
+            }
+        }
+        if lines.is_empty() {
+            return false;
+        }
+        self.modified = true;
+        self.undo.push(UndoRecord { description: description.to_string(), lines });
+        true
+    }
+
+    /// Take back the most recent `replace_lines` change. Returns its
+    /// description, or `None` if there is nothing to undo. Refused if the
+    /// lines were edited since, as undoing would throw those edits away.
+    pub fn undo(&mut self) -> io::Result<Option<String>> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "buffer is read-only"));
+        }
+        let Some(record) = self.undo.last() else { return Ok(None) };
+
+        let unchanged = record.lines.iter().all(|(row, _, after)| self.get_line(*row) == Some(after.as_str()));
+        if !unchanged {
+            return Err(io::Error::other(format!("the lines changed by {} were edited since", record.description)));
+        }
+
+        let record = self.undo.pop().expect("checked above");
+        for (row, before, _) in record.lines {
+            if let Some(line) = self.lines.get_mut(row) {
+                *line = before;
+                self.highlighter.line_changed(row);
+            }
+        }
+        self.modified = true;
+        Ok(Some(record.description))
+    }
+
     pub fn is_modified(&self) -> bool {
         self.modified
     }
@@ -113,12 +674,213 @@ impl Buffer {
         self.filename.as_ref()
     }
 
+    /// File name for titles and lists.
+    pub fn display_name(&self) -> &str {
+        self.filename.as_ref()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .unwrap_or("[No Name]")
+    }
+
+    /// Directory this buffer's file lives in, or the save directory of an
+    /// untitled buffer.
+    pub fn directory(&self) -> Option<&Path> {
+        match &self.filename {
+            Some(path) => path.parent().filter(|p| !p.as_os_str().is_empty()),
+            None => self.save_directory.as_deref(),
+        }
+    }
+
+    /// Whether this buffer holds the file at `path`.
+    pub fn is_file(&self, path: &Path) -> bool {
+        let Some(filename) = &self.filename else { return false };
+        match (fs::canonicalize(filename), fs::canonicalize(path)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => filename == path,
+        }
+    }
+
     pub fn set_filename(&mut self, path: PathBuf) {
+        // Renaming can change the file type; binary files never get one
+        let syntax = Syntax::for_path(&path).filter(|_| !self.is_binary());
+        if syntax.as_ref().map(|s| &s.name) != self.highlighter.syntax().map(|s| &s.name) {
+            self.highlighter.set_syntax(syntax);
+        }
         self.filename = Some(path);
     }
 
+    pub fn syntax(&self) -> Option<&Syntax> {
+        self.highlighter.syntax()
+    }
+
+    /// Re-highlight whatever edits have invalidated among the first `up_to` lines.
+    pub fn update_highlight(&mut self, up_to: usize) {
+        let lines = &self.lines;
+        self.highlighter.update(up_to, |row| lines.get(row).map(|s| s.as_str()));
+    }
+
+    pub fn line_highlight(&self, row: usize) -> Option<&[Highlight]> {
+        self.highlighter.line(row)
+    }
+
+    /// Byte index of the character before byte `col` of a line.
+    pub fn prev_char_boundary(&self, row: usize, col: usize) -> usize {
+        self.get_line(row)
+            .and_then(|line| line.get(..col.min(line.len())))
+            .and_then(|before| before.char_indices().next_back())
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// Byte index just past the character at byte `col` of a line.
+    pub fn next_char_boundary(&self, row: usize, col: usize) -> usize {
+        let Some(line) = self.get_line(row) else { return 0 };
+        line.get(col..)
+            .and_then(|rest| rest.chars().next())
+            .map_or(line.len(), |c| col + c.len_utf8())
+    }
+
+    /// Largest character boundary at or before byte `col` of a line.
+    pub fn floor_char_boundary(&self, row: usize, col: usize) -> usize {
+        let Some(line) = self.get_line(row) else { return 0 };
+        let mut col = col.min(line.len());
+        while !line.is_char_boundary(col) {
+            col -= 1;
+        }
+        col
+    }
+
     pub fn line_len(&self, row: usize) -> usize {
         self.lines.get(row).map(|l| l.len()).unwrap_or(0)
     }
+
+    /// Row and byte column of the end of the text, reading the rest of a
+    /// file that is still loading first.
+    pub fn end_position(&mut self) -> io::Result<(usize, usize)> {
+        self.finish_loading()?;
+        let last = self.line_count() - 1;
+        Ok((last, self.line_len(last)))
+    }
 }
 
+/// Write `bytes` to a new file called `name` in a new directory under the
+/// temporary directory, both with a random name and private to this user,
+/// so nobody else can plant a link there or swap the file. Returns the
+/// directory and the file.
+fn private_temp_file(name: &str, bytes: &[u8]) -> io::Result<(PathBuf, PathBuf)> {
+    let mut attempts = 0;
+    let directory = loop {
+        let directory = env::temp_dir().join(format!("text_editor-{:016x}", random_u64()));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        match builder.create(&directory) {
+            Ok(()) => break directory,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    };
+
+    let file = directory.join(if name.is_empty() { "contents" } else { name });
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options.open(&file).and_then(|mut out| out.write_all(bytes));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&file);
+        let _ = fs::remove_dir(&directory);
+        return Err(e);
+    }
+    Ok((directory, file))
+}
+
+/// A number nobody can guess in advance: the standard library seeds each
+/// `RandomState` from the operating system's random source.
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    SystemTime::now().hash(&mut hasher);
+    process::id().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text_editor-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn end_position_waits_for_a_large_file_to_load() {
+        let dir = temp_dir("buffer-end");
+        let path = dir.join("large.txt");
+        let line = "x".repeat(99);
+        let count = LAZY_LOAD_THRESHOLD as usize / 100 + 1000;
+        let mut text = format!("{}\n", line).repeat(count);
+        text.push_str("the end");
+        fs::write(&path, &text).unwrap();
+
+        let mut buffer = Buffer::from_file(path).unwrap();
+        assert_eq!(buffer.end_position().unwrap(), (count, 7));
+        assert_eq!(buffer.get_line(count), Some("the end"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replaced_lines_are_encoded_and_marked_saved() {
+        let dir = temp_dir("buffer-replace");
+        let path = dir.join("open.txt");
+        fs::write(&path, "one\ntwo\nthree").unwrap();
+        let mut buffer = Buffer::from_file(path.clone()).unwrap();
+        buffer.insert_char(2, 0, '>');
+
+        let changes = vec![(1, String::from("2"))];
+        let bytes = buffer.encoded_with(&changes).unwrap();
+        assert_eq!(bytes, b"one\n2\n>three");
+        fs::write(&path, &bytes).unwrap();
+        assert!(buffer.replace_lines(changes, "replace"));
+        buffer.mark_saved(&bytes);
+        assert!(!buffer.is_modified());
+        assert!(!buffer.changed_on_disk());
+
+        buffer.set_read_only(true);
+        assert!(buffer.encoded_with(&[]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relative_save_paths_start_in_the_save_directory() {
+        let mut buffer = Buffer::new();
+        buffer.set_save_directory(PathBuf::from("/session"));
+        assert_eq!(buffer.resolve_save_path(PathBuf::from("a.txt")), Path::new("/session/a.txt"));
+
+        buffer.set_filename(PathBuf::from("/elsewhere/b.txt"));
+        assert_eq!(buffer.resolve_save_path(PathBuf::from("sub/c.txt")), Path::new("/session/sub/c.txt"));
+        assert_eq!(buffer.resolve_save_path(PathBuf::from("/abs/d.txt")), Path::new("/abs/d.txt"));
+    }
+
+    #[test]
+    fn unreadable_files_cannot_be_written() {
+        let mut unreadable = Buffer::new();
+        unreadable.protect_unreadable_path(PathBuf::from("/no/such/dir/secret.txt"));
+        assert!(!unreadable.can_write_file());
+
+        let mut fresh = Buffer::new();
+        assert!(fresh.can_write_file());
+        fresh.set_filename(temp_dir("writable").join("new.txt"));
+        assert!(fresh.can_write_file());
+    }
+
+    #[test]
+    fn end_position_is_the_end_of_the_last_line() {
+        let mut buffer = Buffer::with_lines(vec![String::from("one"), String::from("two three")], None);
+        assert_eq!(buffer.end_position().unwrap(), (1, 9));
+        let mut empty = Buffer::with_lines(vec![String::new()], None);
+        assert_eq!(empty.end_position().unwrap(), (0, 0));
+    }
+}
@@ -0,0 +1,225 @@
+//! Named commands: what the editor can be told to do from the command line,
+//! the command palette and key bindings, with their arguments and default
+//! keys.
+
+use crate::editor::Editor;
+use crate::finder;
+use crate::list::{ListKey, ListNav};
+use crate::terminal::Terminal;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    Save,
+    SaveAs,
+    Open,
+    Close,
+    Buffers,
+    Buffer,
+    NextBuffer,
+    PreviousBuffer,
+    Theme,
+    Encoding,
+    ReadOnly,
+    Files,
+    FindFile,
+    Search,
+    Replace,
+    Goto,
+    Undo,
+    Comment,
+    Complete,
+    SplitBelow,
+    SplitRight,
+    ClosePane,
+    NextPane,
+    PaneUp,
+    PaneDown,
+    PaneLeft,
+    PaneRight,
+    GrowPane,
+    ShrinkPane,
+    Set,
+    Execute,
+    Palette,
+}
+
+/// What a command's argument is, for tab completion.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    None,
+    Path,
+    Buffer,
+    Theme,
+    Encoding,
+    Option,
+    Text,
+}
+
+pub struct CommandInfo {
+    pub command: Command,
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// What the argument looks like; `<...>` if it is required, `[...]` if
+    /// the command asks for it when left out.
+    pub usage: &'static str,
+    pub argument: Argument,
+    pub description: &'static str,
+    /// Keys bound to the command unless keys.ini says otherwise.
+    pub keys: &'static [&'static str],
+}
+
+const fn info(
+    command: Command,
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    argument: Argument,
+    description: &'static str,
+    keys: &'static [&'static str],
+) -> CommandInfo {
+    CommandInfo { command, name, aliases, usage, argument, description, keys }
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    info(Command::Quit, "quit", &["q"], "", Argument::None, "Quit the editor", &["Ctrl-Q"]),
+    info(Command::Save, "save", &["w"], "[path]", Argument::Path, "Save the file (as path, if given)", &["Ctrl-S"]),
+    info(Command::SaveAs, "save-as", &["saveas"], "[path]", Argument::Path, "Save under a new name", &["Ctrl-A"]),
+    info(Command::Open, "open", &["e", "edit"], "[path]", Argument::Path, "Open a file", &["Ctrl-O"]),
+    info(Command::Close, "close", &[], "", Argument::None, "Close the buffer", &["Ctrl-W"]),
+    info(Command::Buffers, "buffers", &["ls"], "", Argument::None, "List open buffers", &["Ctrl-B"]),
+    info(Command::Buffer, "buffer", &["b"], "<number|name>", Argument::Buffer, "Switch to an open buffer", &[]),
+    info(Command::NextBuffer, "next-buffer", &["bn"], "", Argument::None, "Switch to the next buffer", &["Ctrl-PageDown"]),
+    info(Command::PreviousBuffer, "previous-buffer", &["bp"], "", Argument::None, "Switch to the previous buffer", &["Ctrl-PageUp"]),
+    info(Command::Theme, "theme", &[], "[name]", Argument::Theme, "Switch color theme", &["Ctrl-T"]),
+    info(Command::Encoding, "encoding", &[], "[name]", Argument::Encoding, "Reopen or save in another encoding", &["Ctrl-E"]),
+    info(Command::ReadOnly, "read-only", &["ro"], "", Argument::None, "Toggle read-only mode", &["Ctrl-R"]),
+    info(Command::Files, "files", &["browse"], "", Argument::None, "Browse the project's files", &["Ctrl-D"]),
+    info(Command::FindFile, "find-file", &[], "", Argument::None, "Find a file by name", &["Ctrl-P"]),
+    info(Command::Search, "search", &["find", "grep"], "[pattern]", Argument::Text, "Search the project's files", &["Ctrl-F"]),
+    info(Command::Replace, "replace", &[], "[pattern]", Argument::Text, "Search the project's files and replace matches", &[]),
+    info(Command::Goto, "goto", &["g"], "[line[:column]]", Argument::Text, "Go to a line and column", &["Ctrl-G"]),
+    info(Command::Undo, "undo", &[], "", Argument::None, "Undo the last replace", &["Ctrl-Z"]),
+    info(Command::Comment, "comment", &[], "", Argument::None, "Toggle a line comment", &["Ctrl-/", "Ctrl-K Ctrl-C"]),
+    info(Command::Complete, "complete", &[], "", Argument::None, "Complete the word before the cursor", &["Ctrl-N"]),
+    info(Command::SplitBelow, "split-below", &["split"], "", Argument::None, "Split the pane, new pane below", &["Ctrl-X 2"]),
+    info(Command::SplitRight, "split-right", &["vsplit"], "", Argument::None, "Split the pane, new pane to the right", &["Ctrl-X 3"]),
+    info(Command::ClosePane, "close-pane", &[], "", Argument::None, "Close the pane", &["Ctrl-X 0"]),
+    info(Command::NextPane, "next-pane", &[], "", Argument::None, "Move to the next pane", &["Ctrl-X o"]),
+    info(Command::PaneUp, "pane-up", &[], "", Argument::None, "Move to the pane above", &["Ctrl-X Up"]),
+    info(Command::PaneDown, "pane-down", &[], "", Argument::None, "Move to the pane below", &["Ctrl-X Down"]),
+    info(Command::PaneLeft, "pane-left", &[], "", Argument::None, "Move to the pane on the left", &["Ctrl-X Left"]),
+    info(Command::PaneRight, "pane-right", &[], "", Argument::None, "Move to the pane on the right", &["Ctrl-X Right"]),
+    info(Command::GrowPane, "grow-pane", &[], "", Argument::None, "Make the pane bigger", &["Ctrl-X +", "Ctrl-X ="]),
+    info(Command::ShrinkPane, "shrink-pane", &[], "", Argument::None, "Make the pane smaller", &["Ctrl-X -"]),
+    info(Command::Set, "set", &[], "[option [value]]", Argument::Option, "Show or change an option", &[]),
+    info(Command::Execute, "command-line", &[], "", Argument::None, "Type a command", &["Ctrl-L"]),
+    info(Command::Palette, "commands", &["palette"], "", Argument::None, "List commands and their keys", &["F1"]),
+];
+
+impl Command {
+    pub fn info(self) -> &'static CommandInfo {
+        COMMANDS.iter().find(|info| info.command == self).expect("every command is listed")
+    }
+
+    /// Whether the command can't run without an argument.
+    pub fn needs_argument(self) -> bool {
+        self.info().usage.starts_with('<')
+    }
+}
+
+/// The command called `name` or with `name` as an alias.
+pub fn find(name: &str) -> Option<Command> {
+    COMMANDS.iter()
+        .find(|info| info.name == name || info.aliases.contains(&name))
+        .map(|info| info.command)
+}
+
+/// Split a command line into its command and argument. A bare go-to target
+/// (`12`, `12:5`, `+3`, `50%`) is short for `goto`.
+pub fn parse(line: &str) -> Result<(Command, &str), String> {
+    let line = line.trim();
+    let (name, argument) = line.split_once(char::is_whitespace).map_or((line, ""), |(n, a)| (n, a.trim()));
+    if let Some(command) = find(name) {
+        return Ok((command, argument));
+    }
+    if name.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-') {
+        return Ok((Command::Goto, line));
+    }
+    Err(format!("unknown command '{}'", name))
+}
+
+/// Names of the commands starting with `prefix`, in the order listed.
+pub fn names_starting_with(prefix: &str) -> Vec<&'static str> {
+    COMMANDS.iter().map(|info| info.name).filter(|name| name.starts_with(prefix)).collect()
+}
+
+impl Editor {
+    /// List every command with its keys and description, narrowed down as
+    /// a fuzzy search of names and descriptions is typed. Enter runs the
+    /// selected command, first opening the command line for its argument
+    /// if it can't do without one.
+    pub fn command_palette(&mut self) {
+        let mut query = String::new();
+        let mut list = ListNav::new(self.screen_rows as usize);
+
+        let choice = loop {
+            let mut ranked: Vec<(i64, &CommandInfo)> = COMMANDS.iter()
+                .filter_map(|info| {
+                    let score = |text| finder::fuzzy_score(&query, text).map(|(score, _)| score);
+                    score(info.name).max(score(info.description)).map(|score| (score, info))
+                })
+                .collect();
+            // Stable, so equal scores stay in the order listed
+            ranked.sort_by_key(|&(score, _)| Reverse(score));
+
+            let keys: Vec<String> = ranked.iter().map(|(_, info)| self.keymap.keys_for(info.command).join(", ")).collect();
+            let keys_width = keys.iter().map(|k| k.chars().count()).max().unwrap_or(0);
+            let items: Vec<String> = ranked.iter()
+                .zip(&keys)
+                .map(|((_, info), keys)| {
+                    let name = format!("{} {}", info.name, info.usage);
+                    format!(" {:<22} {:<keys_width$} {}", name, keys, info.description)
+                })
+                .collect();
+            list.set_len(items.len());
+            if items.is_empty() {
+                self.draw_list(&[String::from("  (no matching commands)")], usize::MAX, 0);
+            } else {
+                self.draw_list(&items, list.selected(), list.top());
+            }
+
+            let status = format!(" Commands - {} of {}", items.len(), COMMANDS.len());
+            let label = "Command: ";
+            let _ = Terminal::move_cursor(self.screen_rows, 0);
+            self.draw_overlay_bars(&status, &format!("{}{}", label, query));
+            let _ = Terminal::move_cursor(self.screen_rows + 1, (label.len() + query.chars().count()) as u16);
+            let _ = Terminal::show_cursor();
+            let _ = io::stdout().flush();
+
+            let Ok(byte) = self.read_byte() else { break None };
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape => break None,
+                ListKey::Enter => break ranked.get(list.selected()).map(|(_, info)| info.command),
+                ListKey::Byte(127 | 8) => {
+                    query.pop();
+                    list.select(0);
+                },
+                ListKey::Byte(byte) if (32..127).contains(&byte) => {
+                    query.push(byte as char);
+                    list.select(0);
+                },
+                _ => {}
+            }
+        };
+
+        match choice {
+            Some(command) if command.needs_argument() => self.command_line(&format!("{} ", command.info().name)),
+            Some(command) => self.run_command(command, ""),
+            None => {},
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// A `[name]` block of an INI-style config file and its `key = value` entries.
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Directory holding the editor's config files.
+///
+/// `TEXT_EDITOR_CONFIG_DIR` wins if set, otherwise the platform's usual
+/// per-user config location is used.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("TEXT_EDITOR_CONFIG_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+
+    #[cfg(windows)]
+    {
+        env::var("APPDATA").ok().map(|dir| PathBuf::from(dir).join("text_editor"))
+    }
+
+    #[cfg(not(windows))]
+    {
+        if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+            return Some(PathBuf::from(dir).join("text_editor"));
+        }
+        env::var("HOME").ok().map(|dir| PathBuf::from(dir).join(".config").join("text_editor"))
+    }
+}
+
+/// Parse INI-style text. Entries before the first header land in a section
+/// with an empty name; `#` and `;` start comment lines.
+pub fn parse_ini(text: &str) -> Vec<Section> {
+    let mut sections = vec![Section { name: String::new(), entries: Vec::new() }];
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push(Section { name: name.trim().to_string(), entries: Vec::new() });
+        } else if let Some((key, value)) = line.split_once('=') {
+            let section = sections.last_mut().expect("there is always a section");
+            section.entries.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    sections
+}
+
+/// Load and parse `file_name` from the config directory. A missing or
+/// unreadable file simply yields no sections.
+pub fn load_ini(file_name: &str) -> Vec<Section> {
+    config_dir()
+        .and_then(|dir| fs::read_to_string(dir.join(file_name)).ok())
+        .map(|text| parse_ini(&text))
+        .unwrap_or_default()
+}
+
+/// Look up `key` in the `[section]` of the main `config.ini`.
+pub fn setting(section: &str, key: &str) -> Option<String> {
+    load_ini("config.ini")
+        .iter()
+        .rev()
+        .filter(|s| s.name == section)
+        .find_map(|s| s.get(key).map(|v| v.to_string()))
+}
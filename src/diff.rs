@@ -0,0 +1,110 @@
+//! Line diffs for showing how two versions of a file differ.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiffOp<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Beyond this many cells the LCS table is skipped and the differing middle
+/// is reported as one big replacement.
+const MAX_TABLE_CELLS: usize = 4_000_000;
+
+/// Diff `old` against `new` line by line (longest common subsequence).
+pub fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffOp<'a>> {
+    // Common prefix and suffix don't need the table
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<DiffOp> = old[..prefix].iter().map(|l| DiffOp::Equal(l)).collect();
+
+    if (old_mid.len() + 1) * (new_mid.len() + 1) > MAX_TABLE_CELLS {
+        ops.extend(old_mid.iter().map(|l| DiffOp::Delete(l)));
+        ops.extend(new_mid.iter().map(|l| DiffOp::Insert(l)));
+    } else {
+        // lcs[i][j] = LCS length of old_mid[i..] and new_mid[j..]
+        let width = new_mid.len() + 1;
+        let mut lcs = vec![0u32; (old_mid.len() + 1) * width];
+        for i in (0..old_mid.len()).rev() {
+            for j in (0..new_mid.len()).rev() {
+                lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old_mid.len() && j < new_mid.len() {
+            if old_mid[i] == new_mid[j] {
+                ops.push(DiffOp::Equal(old_mid[i]));
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                ops.push(DiffOp::Delete(old_mid[i]));
+                i += 1;
+            } else {
+                ops.push(DiffOp::Insert(new_mid[j]));
+                j += 1;
+            }
+        }
+        ops.extend(old_mid[i..].iter().map(|l| DiffOp::Delete(l)));
+        ops.extend(new_mid[j..].iter().map(|l| DiffOp::Insert(l)));
+    }
+
+    ops.extend(old[old.len() - suffix..].iter().map(|l| DiffOp::Equal(l)));
+    ops
+}
+
+/// Render a diff in unified format with `context` unchanged lines around
+/// each change. Returns no lines when nothing differs.
+pub fn unified(old_name: &str, new_name: &str, ops: &[DiffOp], context: usize) -> Vec<String> {
+    let changed: Vec<usize> = ops.iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, DiffOp::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return Vec::new();
+    }
+
+    let mut out = vec![format!("--- {}", old_name), format!("+++ {}", new_name)];
+
+    // Group changes whose context windows touch into hunks
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    for (start, end) in hunks {
+        // Line numbers (1-based) where the hunk starts in each version
+        let old_start = 1 + ops[..start].iter().filter(|op| !matches!(op, DiffOp::Insert(_))).count();
+        let new_start = 1 + ops[..start].iter().filter(|op| !matches!(op, DiffOp::Delete(_))).count();
+        let old_len = ops[start..end].iter().filter(|op| !matches!(op, DiffOp::Insert(_))).count();
+        let new_len = ops[start..end].iter().filter(|op| !matches!(op, DiffOp::Delete(_))).count();
+
+        out.push(format!("@@ -{},{} +{},{} @@", old_start, old_len, new_start, new_len));
+        for op in &ops[start..end] {
+            out.push(match op {
+                DiffOp::Equal(l) => format!(" {}", l),
+                DiffOp::Delete(l) => format!("-{}", l),
+                DiffOp::Insert(l) => format!("+{}", l),
+            });
+        }
+    }
+
+    out
+}
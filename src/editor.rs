@@ -1,122 +1,905 @@
 use crate::buffer::Buffer;
-use crate::terminal::Terminal;
-use std::io::{self, Read, stdin, Write};
-use std::path::PathBuf;
+use crate::command::{self, Argument, Command};
+use crate::config;
+use crate::diff;
+use crate::encoding::Encoding;
+use crate::goto::Goto;
+use crate::hex::{self, BYTES_PER_ROW};
+use crate::keymap::{self, Key, Keymap, Lookup};
+use crate::layout::{self, Direction, Layout, Rect, SplitDirection};
+use crate::list::{self, ListKey, ListNav};
+use crate::prompt::{self, Completion, History, HistoryWalk, LineInput, Prompt, PromptKind};
+use crate::sandbox::SavePolicy;
+use crate::syntax::{Highlight, Syntax};
+use crate::terminal::{CLEAR_LINE, DEFAULT_FG, InputGate, RESET_COLORS, Terminal};
+use crate::theme::{Theme, ThemeSet};
+use crate::width;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How often the file is checked for changes made by other programs while
+/// the editor sits idle.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Redraw interval while a large file is loading.
+pub const LOADING_REFRESH: Duration = Duration::from_millis(100);
+
+/// How long to wait after ESC for the rest of an escape sequence before
+/// taking it as a lone Escape key.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Narrowest pane a side-by-side split may produce.
+const MIN_PANE_WIDTH: usize = 20;
+
+/// Percent of a split moved by one resize step.
+const PANE_RESIZE_STEP: i16 = 5;
+
+/// Most path completions listed at once above the prompt.
+const MAX_COMPLETION_ROWS: usize = 8;
+
+/// Cursor and scroll position within a buffer.
+#[derive(Clone, Copy, Default)]
+struct ViewState {
+    cursor_x: usize,
+    cursor_y: usize,
+    scroll_offset: usize,
+    preferred_column: Option<PreferredColumn>,
+}
+
+/// Display column that moving up and down aims for, so passing through a
+/// short line doesn't lose it. It only holds while the cursor stays where
+/// the last vertical move put it; moving sideways or editing drops it.
+#[derive(Clone, Copy)]
+struct PreferredColumn {
+    column: usize,
+    /// Cursor `(row, byte)` after the vertical move.
+    at: (usize, usize),
+}
+
+/// A pane's window onto a buffer.
+#[derive(Clone, Copy)]
+struct View {
+    /// Index into the buffer list.
+    buffer: usize,
+    state: ViewState,
+}
+
+/// One screen line of a pane being composed. Text is clipped to the pane
+/// width and padded out to it; color codes pass through untouched.
+pub struct PaneLine {
+    out: String,
+    used: usize,
+    width: usize,
+}
+
+impl PaneLine {
+    pub fn new(width: usize) -> Self {
+        PaneLine { out: String::from(RESET_COLORS), used: 0, width }
+    }
+
+    pub fn color(&mut self, code: &str) {
+        self.out.push_str(code);
+    }
+
+    pub fn push(&mut self, ch: char) {
+        let width = width::char_width(ch);
+        if self.used + width <= self.width {
+            self.out.push(ch);
+            self.used += width;
+        } else {
+            // A wide character that doesn't fit ends the line
+            self.out.push_str(&" ".repeat(self.width - self.used));
+            self.used = self.width;
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.used >= self.width
+    }
+
+    pub fn text(&mut self, text: &str) {
+        for ch in text.chars() {
+            self.push(ch);
+        }
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push_str(&" ".repeat(self.width - self.used));
+        self.out.push_str(RESET_COLORS);
+        self.out
+    }
+}
+
+/// When the tab bar above the panes is shown.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TabBar {
+    Always,
+    /// Only while more than one buffer is open.
+    Auto,
+    Never,
+}
+
+impl TabBar {
+    fn from_setting(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "always" | "true" | "on" => Some(TabBar::Always),
+            "auto" => Some(TabBar::Auto),
+            "never" | "false" | "off" => Some(TabBar::Never),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TabBar::Always => "always",
+            TabBar::Auto => "auto",
+            TabBar::Never => "never",
+        }
+    }
+}
+
+/// Options the `set` command changes.
+const OPTIONS: &[&str] = &["tab_bar", "read_only", "word_chars", "theme"];
 
-const HELP_MESSAGE: &str = "HELP: Ctrl-Q = quit | Ctrl-S = save | Ctrl-A = save as";
+/// Tab labels longer than this many characters are cut short.
+const MAX_TAB_NAME: usize = 24;
+
+/// An entry in the buffer list. The active buffer is moved out into
+/// `Editor::buffer` while it is edited, leaving `None` in its entry.
+struct OpenBuffer {
+    buffer: Option<Buffer>,
+    view: ViewState,
+}
 
 pub struct Editor {
     buffer: Buffer,
-    _terminal: Terminal,
+    buffers: Vec<OpenBuffer>,
+    current: usize,
+    /// Panes on screen. The active view's cursor and scroll position live in
+    /// the fields below while it has focus, and its buffer is `buffer`.
+    views: Vec<View>,
+    active_view: usize,
+    layout: Layout,
+    tab_bar: TabBar,
+    /// Characters that count as part of a word for Ctrl-Left/Right, besides
+    /// letters and digits.
+    word_chars: String,
+    save_policy: SavePolicy,
+    /// Earlier answers to prompts, kept between sessions.
+    history: History,
+    pub keymap: Keymap,
+    /// Keys of a key sequence typed so far.
+    pending_keys: Vec<Key>,
+    /// Key help for the message bar, from the keymap.
+    help: String,
+    terminal: Terminal,
     cursor_x: usize,
     cursor_y: usize,
     scroll_offset: usize,
-    screen_rows: u16,
-    screen_cols: u16,
+    preferred_column: Option<PreferredColumn>,
+    pub screen_rows: u16,
+    pub screen_cols: u16,
     quit: bool,
-    status_message: String,
+    pub status_message: String,
     save_count: u32,
     message_is_temporary: bool,
+    themes: ThemeSet,
+    pub theme: Theme,
+    pub input: Receiver<u8>,
+    /// Keeps the input reader off stdin while another program uses it.
+    input_gate: InputGate,
 }
 
 impl Editor {
     pub fn new() -> Self {
-        let terminal = Terminal::new().expect("Failed to initialize terminal");
-        let (rows, cols) = Terminal::get_terminal_size().unwrap_or((24, 80));
-
-        Editor {
-            buffer: Buffer::new(),
-            _terminal: terminal,
-            cursor_x: 0,
-            cursor_y: 0,
-            scroll_offset: 0,
-            screen_rows: rows.saturating_sub(2), // Reserve 2 rows for status bar
-            screen_cols: cols,
-            quit: false,
-            status_message: String::from(HELP_MESSAGE),
-            save_count: 0,
-            message_is_temporary: false,
-        }
+        Editor::with_buffer(Buffer::new())
     }
 
     pub fn from_file(path: &str) -> io::Result<Self> {
-        let terminal = Terminal::new().expect("Failed to initialize terminal");
-        let (rows, cols) = Terminal::get_terminal_size().unwrap_or((24, 80));
-
         let buffer = Buffer::from_file(PathBuf::from(path))?;
-
-        Ok(Editor {
-            buffer,
-            _terminal: terminal,
-            cursor_x: 0,
-            cursor_y: 0,
-            scroll_offset: 0,
-            screen_rows: rows.saturating_sub(2),
-            screen_cols: cols,
-            quit: false,
-            status_message: String::from(HELP_MESSAGE),
-            save_count: 0,
-            message_is_temporary: false,
-        })
+        Ok(Editor::with_buffer(buffer))
     }
 
     pub fn new_with_filename(path: &str) -> io::Result<Self> {
-        let terminal = Terminal::new().expect("Failed to initialize terminal");
-        let (rows, cols) = Terminal::get_terminal_size().unwrap_or((24, 80));
-
         let mut buffer = Buffer::new();
         buffer.set_filename(PathBuf::from(path));
+        Ok(Editor::with_buffer(buffer))
+    }
 
-        Ok(Editor {
-            buffer,
-            _terminal: terminal,
-            cursor_x: 0,
-            cursor_y: 0,
-            scroll_offset: 0,
-            screen_rows: rows.saturating_sub(2),
-            screen_cols: cols,
-            quit: false,
-            status_message: String::from(HELP_MESSAGE),
-            save_count: 0,
-            message_is_temporary: false,
-        })
+    /// Start with an untitled buffer after `path` failed to open, keeping
+    /// the error on screen and guarding the file against being overwritten.
+    pub fn from_unreadable_file(path: &str, error: &io::Error) -> Self {
+        let mut buffer = Buffer::new();
+        buffer.protect_unreadable_path(PathBuf::from(path));
+
+        let mut editor = Editor::with_buffer(buffer);
+        editor.show_message(format!("Could not open {}: {} (editing an untitled buffer)", path, error));
+        editor
     }
 
     pub fn new_with_save_directory(directory: &str) -> io::Result<Self> {
-        let terminal = Terminal::new().expect("Failed to initialize terminal");
-        let (rows, cols) = Terminal::get_terminal_size().unwrap_or((24, 80));
-
         let mut buffer = Buffer::new();
         buffer.set_save_directory(PathBuf::from(directory));
+        Ok(Editor::with_buffer(buffer))
+    }
+
+    fn with_buffer(buffer: Buffer) -> Self {
+        let mut terminal = Terminal::new().expect("Failed to initialize terminal");
+        let (rows, cols) = Terminal::get_terminal_size().unwrap_or((24, 80));
+
+        let themes = ThemeSet::load();
+        let theme = config::setting("editor", "theme")
+            .and_then(|name| themes.get(&name).cloned())
+            .unwrap_or_else(Theme::dark);
+
+        let tab_bar = config::setting("editor", "tab_bar")
+            .and_then(|value| TabBar::from_setting(&value))
+            .unwrap_or(TabBar::Auto);
+        if config::setting("editor", "mouse").is_some_and(|value| value.trim() == "true") {
+            let _ = terminal.enable_mouse_reporting();
+        }
+        let word_chars = config::setting("editor", "word_chars").unwrap_or_else(|| String::from("_"));
+        let (input, input_gate) = Terminal::spawn_input_reader();
+        let (keymap, problems) = Keymap::load();
+        let help = keymap.help();
+        let mut save_policy = SavePolicy::load();
+        if let Some(directory) = buffer.save_directory() {
+            save_policy.set_session_directory(directory);
+        }
 
-        Ok(Editor {
+        Editor {
             buffer,
-            _terminal: terminal,
+            buffers: vec![OpenBuffer { buffer: None, view: ViewState::default() }],
+            current: 0,
+            views: vec![View { buffer: 0, state: ViewState::default() }],
+            active_view: 0,
+            layout: Layout::Pane(0),
+            tab_bar,
+            word_chars,
+            save_policy,
+            history: History::load(),
+            keymap,
+            pending_keys: Vec::new(),
+            terminal,
             cursor_x: 0,
             cursor_y: 0,
             scroll_offset: 0,
-            screen_rows: rows.saturating_sub(2),
+            preferred_column: None,
+            screen_rows: rows.saturating_sub(2), // Reserve 2 rows for status bar
             screen_cols: cols,
             quit: false,
-            status_message: String::from(HELP_MESSAGE),
+            status_message: if problems.is_empty() { help.clone() } else { format!("keys.ini: {}", problems.join("; ")) },
             save_count: 0,
-            message_is_temporary: false,
-        })
+            message_is_temporary: !problems.is_empty(),
+            help,
+            themes,
+            theme,
+            input,
+            input_gate,
+        }
     }
 
     pub fn set_save_directory(&mut self, directory: &str) {
         self.buffer.set_save_directory(PathBuf::from(directory));
+        self.save_policy.set_session_directory(Path::new(directory));
+    }
+
+    /// Set read-only mode on every open buffer (the `--readonly` flag).
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.buffer.set_read_only(read_only);
+        for buffer in self.buffers.iter_mut().filter_map(|b| b.buffer.as_mut()) {
+            buffer.set_read_only(read_only);
+        }
+    }
+
+    /// Open `path` in a new buffer and switch to it, or switch to the buffer
+    /// that already holds it. A path that doesn't exist yet opens an empty
+    /// buffer that will be saved there.
+    pub fn open_file(&mut self, path: &str) -> io::Result<()> {
+        let path = PathBuf::from(path);
+        if let Some(index) = self.buffer_index_of(&path) {
+            self.switch_to_buffer(index);
+            return Ok(());
+        }
+
+        let mut buffer = if path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a directory", path.display())));
+        } else if path.exists() {
+            Buffer::from_file(path.clone())?
+        } else {
+            let mut buffer = Buffer::new();
+            buffer.set_filename(path.clone());
+            buffer
+        };
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            buffer.set_save_directory(dir.to_path_buf());
+        }
+
+        self.buffers.push(OpenBuffer { buffer: Some(buffer), view: ViewState::default() });
+        self.switch_to_buffer(self.buffers.len() - 1);
+        Ok(())
+    }
+
+    /// Show `message` in the message bar until the next key press.
+    pub fn show_message(&mut self, message: String) {
+        self.status_message = message;
+        self.message_is_temporary = true;
+    }
+
+    /// Make the buffer at `index` in the buffer list the active one.
+    pub fn select_buffer(&mut self, index: usize) {
+        if index < self.buffers.len() {
+            self.switch_to_buffer(index);
+        }
+    }
+
+    pub fn buffer_index_of(&self, path: &Path) -> Option<usize> {
+        (0..self.buffers.len()).find(|&i| self.buffer_at(i).is_file(path))
+    }
+
+    /// How many buffers are open.
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// The buffer at `index` in the buffer list, active or not.
+    pub fn buffer_at(&self, index: usize) -> &Buffer {
+        if index == self.current {
+            &self.buffer
+        } else {
+            self.buffers[index].buffer.as_ref().expect("inactive buffers are kept in the list")
+        }
+    }
+
+    pub fn buffer_at_mut(&mut self, index: usize) -> &mut Buffer {
+        if index == self.current {
+            &mut self.buffer
+        } else {
+            self.buffers[index].buffer.as_mut().expect("inactive buffers are kept in the list")
+        }
+    }
+
+    fn view_state(&self) -> ViewState {
+        ViewState {
+            cursor_x: self.cursor_x,
+            cursor_y: self.cursor_y,
+            scroll_offset: self.scroll_offset,
+            preferred_column: self.preferred_column,
+        }
+    }
+
+    fn restore_view_state(&mut self, view: ViewState) {
+        self.cursor_x = view.cursor_x;
+        self.cursor_y = view.cursor_y;
+        self.scroll_offset = view.scroll_offset;
+        self.preferred_column = view.preferred_column;
+        self.clamp_cursor();
+    }
+
+    /// Show the buffer at `index` in the active pane, keeping each buffer's
+    /// cursor and scroll position.
+    fn switch_to_buffer(&mut self, index: usize) {
+        if index == self.current {
+            return;
+        }
+
+        self.buffers[self.current].view = self.view_state();
+        self.swap_in_buffer(index);
+        self.views[self.active_view].buffer = index;
+        self.restore_view_state(self.buffers[index].view);
+        self.show_message(format!("Buffer {}/{}: {}", index + 1, self.buffers.len(), self.buffer.display_name()));
+
+        // It may have changed on disk while it was in the background
+        self.check_disk_changes();
+    }
+
+    /// Move the active buffer back to the list and take the one at `index`
+    /// out of it. Cursor state is left to the caller.
+    fn swap_in_buffer(&mut self, index: usize) {
+        if index == self.current {
+            return;
+        }
+        let incoming = self.buffers[index].buffer.take().expect("inactive buffers are kept in the list");
+        let outgoing = mem::replace(&mut self.buffer, incoming);
+        self.buffers[self.current].buffer = Some(outgoing);
+        self.current = index;
+    }
+
+    fn next_buffer(&mut self) {
+        self.switch_to_buffer((self.current + 1) % self.buffers.len());
+    }
+
+    fn previous_buffer(&mut self) {
+        self.switch_to_buffer((self.current + self.buffers.len() - 1) % self.buffers.len());
+    }
+
+    /// Open `given`, or the file asked for.
+    fn open_file_prompt(&mut self, given: Option<&str>) {
+        let prompt = Prompt::new(PromptKind::File, "Open: ").complete_paths(&self.open_base());
+        let Some(input) = self.answer(given, prompt, "Open cancelled") else { return };
+
+        // Relative paths are taken from the current buffer's directory
+        let path = self.open_base().join(input.trim());
+
+        if let Err(e) = self.open_file(&path.to_string_lossy()) {
+            self.show_message(format!("Could not open {}: {}", path.display(), e));
+        }
+    }
+
+    /// Directory relative paths are opened from: the current buffer's.
+    fn open_base(&self) -> PathBuf {
+        self.buffer.directory().map_or_else(|| PathBuf::from("."), Path::to_path_buf)
+    }
+
+    /// Directory the file browser and finder work in: the save directory,
+    /// else the current file's directory, else the working directory.
+    pub fn project_root(&self) -> Option<PathBuf> {
+        self.buffer.save_directory()
+            .or_else(|| self.buffer.directory())
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok())
+    }
+
+    /// Go to `given`, or where asked.
+    fn go_to_prompt(&mut self, given: Option<&str>) {
+        if self.buffer.is_binary() {
+            self.show_message(String::from("Go to line isn't available in the hex view"));
+            return;
+        }
+        let prompt = Prompt::new(PromptKind::Goto, "Go to line[:column] (+N, -N, N%): ")
+            .validate(|_: &Editor, text: &str| Goto::parse(text).map(|_| ()));
+        let Some(text) = self.answer(given, prompt, "Go to cancelled") else { return };
+        if let Ok(goto) = Goto::parse(&text) {
+            self.go_to(goto);
+        }
+    }
+
+    /// Move the cursor to `goto`'s line and column. Without a column it
+    /// keeps to the preferred column, as moving up and down does. A file
+    /// still loading is read to the end first so the line can be found.
+    pub fn go_to(&mut self, goto: Goto) {
+        if self.buffer.is_binary() {
+            return;
+        }
+        if let Err(e) = self.buffer.finish_loading() {
+            self.show_message(format!("Error loading file: {}", e));
+        }
+        let line = goto.line(self.cursor_y, self.buffer.line_count());
+        match goto.column() {
+            Some(column) => {
+                let text = self.buffer.get_line(line).unwrap_or("");
+                let index = text.char_indices().nth(column).map_or(text.len(), |(i, _)| i);
+                self.jump_to(line, index);
+            },
+            None => {
+                let column = self.preferred_column();
+                self.move_vertically_to(line, column);
+                self.scroll_offset = line.saturating_sub(self.view_rows() / 2);
+                self.adjust_scroll();
+            },
+        }
+    }
+
+    /// Put the cursor at byte `column` of `line` in the active buffer, with
+    /// the line in the middle of the view.
+    pub fn jump_to(&mut self, line: usize, column: usize) {
+        self.cursor_y = line;
+        self.cursor_x = column;
+        self.scroll_offset = line.saturating_sub(self.view_rows() / 2);
+        self.clamp_cursor();
+    }
+
+    /// Read a command line, starting with `initial`, and run it. Tab
+    /// completes command names, then their arguments.
+    pub fn command_line(&mut self, initial: &str) {
+        let prompt = Prompt::new(PromptKind::Command, "Command: ")
+            .initial(initial)
+            .complete_with(|editor: &Editor, text: &str| editor.command_completions(text))
+            .validate(|_: &Editor, line: &str| match command::parse(line)? {
+                (command, "") if command.needs_argument() => {
+                    Err(format!("{} needs {}", command.info().name, command.info().usage))
+                },
+                _ => Ok(()),
+            });
+        let Some(line) = self.run_prompt(prompt) else { return };
+        if let Ok((command, argument)) = command::parse(&line) {
+            self.run_command(command, argument);
+        }
+    }
+
+    /// Carry out `command`. Commands that ask for something take
+    /// `argument` as the answer instead, unless it is empty.
+    pub fn run_command(&mut self, command: Command, argument: &str) {
+        let given = Some(argument).filter(|a| !a.is_empty());
+        match command {
+            Command::Undo | Command::Comment | Command::Complete if self.buffer.is_binary() => {
+                self.show_message(format!("{} isn't available in the hex view", command.info().name));
+            },
+            Command::Quit => self.quit = true,
+            Command::Save if given.is_some() => self.save_file_as(given),
+            Command::Save => self.save_file(),
+            Command::SaveAs => self.save_file_as(given),
+            Command::Open => self.open_file_prompt(given),
+            Command::Close => self.close_buffer(),
+            Command::Buffers => self.list_buffers(),
+            Command::Buffer => self.switch_to_named_buffer(argument),
+            Command::NextBuffer => self.next_buffer(),
+            Command::PreviousBuffer => self.previous_buffer(),
+            Command::Theme => self.switch_theme(given),
+            Command::Encoding => self.change_encoding(given),
+            Command::ReadOnly => self.toggle_read_only(),
+            Command::Files => self.file_browser(),
+            Command::FindFile => self.find_file(),
+            Command::Search => self.project_search(given, false),
+            Command::Replace => self.project_search(given, true),
+            Command::Goto => self.go_to_prompt(given),
+            Command::Undo => self.undo(),
+            Command::Comment => self.toggle_comment(),
+            Command::Complete => self.complete_word(),
+            Command::SplitBelow => self.split_pane(SplitDirection::Horizontal),
+            Command::SplitRight => self.split_pane(SplitDirection::Vertical),
+            Command::ClosePane => self.close_pane(),
+            Command::NextPane => self.cycle_pane(),
+            Command::PaneUp => self.move_pane_focus(Direction::Up),
+            Command::PaneDown => self.move_pane_focus(Direction::Down),
+            Command::PaneLeft => self.move_pane_focus(Direction::Left),
+            Command::PaneRight => self.move_pane_focus(Direction::Right),
+            Command::GrowPane => self.resize_pane(PANE_RESIZE_STEP),
+            Command::ShrinkPane => self.resize_pane(-PANE_RESIZE_STEP),
+            Command::Set => self.set_option(argument),
+            Command::Execute => self.command_line(""),
+            Command::Palette => self.command_palette(),
+        }
+    }
+
+    /// Switch to the buffer numbered `which` (from 1, as listed) or with
+    /// that name.
+    fn switch_to_named_buffer(&mut self, which: &str) {
+        let index = match which.parse::<usize>() {
+            Ok(n) => Some(n).filter(|n| (1..=self.buffers.len()).contains(n)).map(|n| n - 1),
+            Err(_) => (0..self.buffers.len()).find(|&i| self.buffer_at(i).display_name() == which),
+        };
+        match index {
+            Some(index) => self.switch_to_buffer(index),
+            None => {
+                self.show_message(format!("No buffer {}", which));
+            },
+        }
+    }
+
+    /// Tab completions for a command line: command names until one is
+    /// typed, then its argument.
+    fn command_completions(&self, text: &str) -> (usize, Vec<String>) {
+        let Some((name, argument)) = text.split_once(' ') else {
+            let names = command::names_starting_with(text);
+            return (0, names.iter().map(|name| format!("{} ", name)).collect());
+        };
+        let start = text.len() - argument.trim_start().len();
+        let typed = &text[start..];
+        let Some(command) = command::find(name) else { return (start, Vec::new()) };
+
+        let candidates = match command.info().argument {
+            Argument::Path => {
+                let base = if command == Command::Open { self.open_base() } else { self.save_base() };
+                let (component, found) = prompt::path_completions(&base, typed);
+                return (start + component, found);
+            },
+            Argument::Option => match typed.split_once(' ') {
+                None => starting_with(OPTIONS.iter().map(|option| format!("{} ", option)), typed),
+                Some((option, value)) => {
+                    return (start + option.len() + 1, starting_with(self.option_values(option), value));
+                },
+            },
+            Argument::Theme => starting_with(self.themes.names().into_iter().map(String::from), typed),
+            Argument::Encoding => starting_with(Encoding::ALL.iter().map(|e| e.name().to_string()), typed),
+            Argument::Buffer => {
+                let names = (0..self.buffers.len()).map(|i| self.buffer_at(i).display_name().to_string());
+                starting_with(names, typed)
+            },
+            Argument::None | Argument::Text => Vec::new(),
+        };
+        (start, candidates)
+    }
+
+    /// Values `set` accepts for `option`, where there is a fixed choice.
+    fn option_values(&self, option: &str) -> Vec<String> {
+        match option {
+            "tab_bar" => ["always", "auto", "never"].map(String::from).to_vec(),
+            "read_only" => ["on", "off"].map(String::from).to_vec(),
+            "theme" => self.themes.names().into_iter().map(String::from).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Current value of `option` as `set` shows it.
+    fn option_value(&self, option: &str) -> String {
+        match option {
+            "tab_bar" => self.tab_bar.name().to_string(),
+            "read_only" => String::from(if self.buffer.is_read_only() { "on" } else { "off" }),
+            "word_chars" => self.word_chars.clone(),
+            _ => self.theme.name.clone(),
+        }
+    }
+
+    /// `set option value` changes an option for this session; `set option`
+    /// shows its value and `set` alone shows them all.
+    fn set_option(&mut self, argument: &str) {
+        let (option, value) = argument.trim().split_once(' ').map_or((argument.trim(), ""), |(o, v)| (o, v.trim()));
+        if option.is_empty() {
+            let values: Vec<String> = OPTIONS.iter().map(|o| format!("{}={}", o, self.option_value(o))).collect();
+            self.show_message(values.join(" | "));
+            return;
+        }
+        if !OPTIONS.contains(&option) {
+            self.show_message(format!("Unknown option {} (options are {})", option, OPTIONS.join(", ")));
+            return;
+        }
+        if value.is_empty() {
+            self.show_message(format!("{}={}", option, self.option_value(option)));
+            return;
+        }
+
+        match option {
+            "tab_bar" => match TabBar::from_setting(value) {
+                Some(tab_bar) => self.tab_bar = tab_bar,
+                None => {
+                    self.show_message(String::from("tab_bar is always, auto or never"));
+                    return;
+                },
+            },
+            "read_only" => match value {
+                "on" | "true" => self.buffer.set_read_only(true),
+                "off" | "false" => match self.editing_refusal() {
+                    Some(refusal) => return self.show_message(refusal),
+                    None => self.buffer.set_read_only(false),
+                },
+                _ => {
+                    self.show_message(String::from("read_only is on or off"));
+                    return;
+                },
+            },
+            "word_chars" => self.word_chars = value.to_string(),
+            // Says what it did itself
+            _ => return self.switch_theme(Some(value)),
+        }
+        self.show_message(format!("{}={}", option, self.option_value(option)));
+    }
+
+    /// Pick a buffer from a list showing which ones have unsaved changes.
+    fn list_buffers(&mut self) {
+        let items: Vec<String> = (0..self.buffers.len())
+            .map(|i| {
+                let buffer = self.buffer_at(i);
+                let marker = if i == self.current { '>' } else { ' ' };
+                let modified = if buffer.is_modified() { " (modified)" } else { "" };
+                let path = buffer.filename().map(|p| format!("  {}", p.display())).unwrap_or_default();
+                format!("{} {:>2} {}{}{}", marker, i + 1, buffer.display_name(), modified, path)
+            })
+            .collect();
+
+        if let Some(index) = self.pick("Buffers", &items, self.current) {
+            self.switch_to_buffer(index);
+        }
+    }
+
+    /// Close the active buffer, asking first if it has unsaved changes. The
+    /// last buffer is replaced by an empty one.
+    fn close_buffer(&mut self) {
+        let name = self.buffer.display_name().to_string();
+        if self.buffer.is_modified() {
+            let question = format!("{} has unsaved changes. (s)ave, (d)iscard or Esc to cancel? ", name);
+            match self.ask(&question) {
+                Some(b's') => {
+                    self.save_file();
+                    // Save failed or was aborted; its message is already showing
+                    if self.buffer.is_modified() {
+                        return;
+                    }
+                },
+                Some(b'd') => {},
+                _ => {
+                    self.show_message(String::from("Close cancelled"));
+                    return;
+                }
+            }
+        }
+
+        if self.buffers.len() == 1 {
+            let mut empty = Buffer::new();
+            if let Some(dir) = self.buffer.directory() {
+                empty.set_save_directory(dir.to_path_buf());
+            }
+            self.buffer = empty;
+            self.restore_view_state(ViewState::default());
+            for view in &mut self.views {
+                view.state = ViewState::default();
+            }
+        } else {
+            let closing = self.current;
+            let next = if closing + 1 < self.buffers.len() { closing + 1 } else { closing - 1 };
+            self.switch_to_buffer(next);
+            self.buffers.remove(closing);
+            if self.current > closing {
+                self.current -= 1;
+            }
+
+            // Other panes showing the closed buffer move on with this one
+            for view in &mut self.views {
+                if view.buffer == closing {
+                    view.buffer = self.current;
+                    view.state = ViewState::default();
+                } else if view.buffer > closing {
+                    view.buffer -= 1;
+                }
+            }
+        }
+
+        self.show_message(format!("Closed {}", name));
+    }
+
+    /// Split the active pane in two, both showing its buffer. The new pane
+    /// gets the focus.
+    fn split_pane(&mut self, direction: SplitDirection) {
+        let rect = self.active_rect();
+        let fits = match direction {
+            // Each half needs a text row and its title line
+            SplitDirection::Horizontal => rect.height >= 4,
+            SplitDirection::Vertical => rect.width > 2 * MIN_PANE_WIDTH,
+        };
+        if !fits {
+            self.show_message(String::from("Not enough room to split this pane"));
+            return;
+        }
+
+        let state = self.view_state();
+        let new_view = self.views.len();
+        self.views[self.active_view].state = state;
+        self.views.push(View { buffer: self.current, state });
+        self.layout.split(self.active_view, direction, new_view);
+        self.active_view = new_view;
+        self.adjust_scroll();
+    }
+
+    fn close_pane(&mut self) {
+        let closing = self.active_view;
+        let Some(next) = self.layout.remove(closing) else {
+            self.show_message(String::from("Only one pane"));
+            return;
+        };
+
+        self.buffers[self.current].view = self.view_state();
+        self.views.remove(closing);
+        self.active_view = next;
+        let view = self.views[next];
+        self.swap_in_buffer(view.buffer);
+        self.restore_view_state(view.state);
+    }
+
+    fn focus_pane(&mut self, view: usize) {
+        if view == self.active_view {
+            return;
+        }
+
+        let state = self.view_state();
+        self.views[self.active_view].state = state;
+        self.buffers[self.current].view = state;
+        self.active_view = view;
+
+        let view = self.views[view];
+        self.swap_in_buffer(view.buffer);
+        self.restore_view_state(view.state);
+    }
+
+    /// Focus the next pane in screen order.
+    fn cycle_pane(&mut self) {
+        let panes = self.layout.panes(self.text_area());
+        if let Some(position) = panes.iter().position(|(view, _)| *view == self.active_view) {
+            let (next, _) = panes[(position + 1) % panes.len()];
+            self.focus_pane(next);
+        }
+    }
+
+    fn move_pane_focus(&mut self, direction: Direction) {
+        let panes = self.layout.panes(self.text_area());
+        match layout::neighbor(&panes, self.active_view, direction) {
+            Some(view) => self.focus_pane(view),
+            None => {
+                self.show_message(String::from("No pane in that direction"));
+            }
+        }
+    }
+
+    fn resize_pane(&mut self, delta: i16) {
+        if self.layout.resize(self.active_view, delta) {
+            self.adjust_scroll();
+        } else {
+            self.show_message(String::from("Only one pane"));
+        }
+    }
+
+    /// Keep the cursor of every pane on buffer `index` within its text,
+    /// after the buffer was changed from outside those panes.
+    pub fn clamp_views(&mut self, index: usize) {
+        let (buffer, saved) = if index == self.current {
+            (&self.buffer, None)
+        } else {
+            let open = &mut self.buffers[index];
+            (open.buffer.as_ref().expect("inactive buffers are kept in the list"), Some(&mut open.view))
+        };
+        let clamp = |state: &mut ViewState| {
+            state.cursor_y = state.cursor_y.min(buffer.line_count() - 1);
+            state.cursor_x = buffer.floor_char_boundary(state.cursor_y, state.cursor_x);
+            state.scroll_offset = state.scroll_offset.min(state.cursor_y);
+            state.preferred_column = None;
+        };
+        if let Some(state) = saved {
+            clamp(state);
+        }
+        for (i, view) in self.views.iter_mut().enumerate() {
+            if view.buffer == index && i != self.active_view {
+                clamp(&mut view.state);
+            }
+        }
+        if index == self.current {
+            self.preferred_column = None;
+            self.clamp_cursor();
+        }
+    }
+
+    /// After an edit added or removed lines, shift the other panes on the
+    /// same buffer so they keep showing the same text.
+    fn sync_views(&mut self, (buffer, line_count, cursor_y): (usize, usize, usize)) {
+        let delta = self.buffer.line_count() as isize - line_count as isize;
+        if buffer != self.current || delta == 0 {
+            return;
+        }
+
+        let edit_row = cursor_y.min(self.cursor_y);
+        let last_row = self.buffer.line_count() - 1;
+        for (index, view) in self.views.iter_mut().enumerate() {
+            if index == self.active_view || view.buffer != self.current {
+                continue;
+            }
+            let state = &mut view.state;
+            if state.cursor_y > edit_row {
+                state.cursor_y = state.cursor_y.saturating_add_signed(delta).max(edit_row);
+            }
+            if state.scroll_offset > edit_row {
+                state.scroll_offset = state.scroll_offset.saturating_add_signed(delta).max(edit_row);
+            }
+            state.cursor_y = state.cursor_y.min(last_row);
+            state.scroll_offset = state.scroll_offset.min(state.cursor_y);
+        }
     }
 
     pub fn run(&mut self) -> io::Result<()> {
         loop {
+            self.poll_loading();
             self.refresh_screen()?;
 
             if self.quit {
                 break;
             }
 
-            self.process_keypress()?;
+            // While a file loads, wake up regularly to show progress; otherwise
+            // wake up now and then to notice the file changing on disk
+            let timeout = if self.buffer.loading_progress().is_some() { LOADING_REFRESH } else { DISK_CHECK_INTERVAL };
+            let byte = match self.input.recv_timeout(timeout) {
+                Ok(byte) => byte,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.check_disk_changes();
+                    continue;
+                },
+                Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+
+            let before = (self.current, self.buffer.line_count(), self.cursor_y);
+            self.process_keypress(byte)?;
+            self.sync_views(before);
         }
 
         Terminal::clear_screen()?;
@@ -124,18 +907,56 @@ impl Editor {
         Ok(())
     }
 
-    fn refresh_screen(&self) -> io::Result<()> {
+    pub fn read_byte(&self) -> io::Result<u8> {
+        self.input.recv().map_err(|_| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn poll_loading(&mut self) {
+        match self.buffer.poll_loading() {
+            // Lines read again in a fallback encoding replace the old ones
+            Ok(true) => self.clamp_cursor(),
+            Ok(false) => {},
+            Err(e) => {
+                self.show_message(format!("Error loading file: {}", e));
+            },
+        }
+        // Background buffers keep loading too. A failure is remembered by the
+        // buffer itself, which then refuses to save over the file.
+        for buffer in self.buffers.iter_mut().filter_map(|b| b.buffer.as_mut()) {
+            let _ = buffer.poll_loading();
+        }
+    }
+
+    pub fn refresh_screen(&mut self) -> io::Result<()> {
+        let panes = self.layout.panes(self.text_area());
+
+        // Only what's on screen (and anything above it) needs highlighting
+        for &(view, rect) in &panes {
+            let (index, scroll_offset) = if view == self.active_view {
+                (self.current, self.scroll_offset)
+            } else {
+                (self.views[view].buffer, self.views[view].state.scroll_offset)
+            };
+            let buffer = self.buffers[index].buffer.as_mut().unwrap_or(&mut self.buffer);
+            buffer.update_highlight(scroll_offset + rect.height);
+        }
+
         Terminal::hide_cursor()?;
         Terminal::move_cursor(0, 0)?;
 
-        self.draw_rows()?;
+        self.draw_rows(&panes)?;
         self.draw_status_bar()?;
         self.draw_message_bar()?;
 
-        // Position cursor (add 5 for line number gutter)
-        let screen_y = (self.cursor_y.saturating_sub(self.scroll_offset)) as u16;
-        let screen_x = (self.cursor_x + 5) as u16;
-        Terminal::move_cursor(screen_y, screen_x)?;
+        // Position cursor in the active pane (add 5 for line number gutter, or the offset column in hex view)
+        let rect = self.active_rect();
+        let screen_y = rect.y + self.cursor_y.saturating_sub(self.scroll_offset);
+        let screen_x = rect.x + if self.buffer.is_binary() {
+            hex::OFFSET_WIDTH + hex::nibble_column(self.cursor_x)
+        } else {
+            self.cursor_display_col() + 5
+        };
+        Terminal::move_cursor(screen_y as u16, screen_x as u16)?;
 
         Terminal::show_cursor()?;
 
@@ -145,66 +966,381 @@ impl Editor {
         Ok(())
     }
 
-    fn draw_rows(&self) -> io::Result<()> {
-        for row in 0..self.screen_rows {
-            Terminal::clear_line()?;
+    /// Screen column of the cursor within the text, counting characters
+    /// rather than bytes.
+    fn cursor_display_col(&self) -> usize {
+        self.buffer.get_line(self.cursor_y).map_or(self.cursor_x, |line| width::column_of(line, self.cursor_x))
+    }
+
+    /// Screen area shared by the panes: everything above the status bar.
+    fn text_area(&self) -> Rect {
+        let top = usize::from(self.shows_tab_bar());
+        Rect {
+            x: 0,
+            y: top,
+            width: self.screen_cols as usize,
+            height: (self.screen_rows as usize).saturating_sub(top),
+        }
+    }
+
+    fn shows_tab_bar(&self) -> bool {
+        match self.tab_bar {
+            TabBar::Always => true,
+            TabBar::Auto => self.buffers.len() > 1,
+            TabBar::Never => false,
+        }
+    }
+
+    /// Tabs that fit on the tab bar as (buffer index, start column, label),
+    /// plus whether tabs are hidden to the left and right. The active tab is
+    /// always shown, with as many neighbours as fit around it.
+    fn visible_tabs(&self) -> (Vec<(usize, usize, String)>, bool, bool) {
+        let labels: Vec<String> = (0..self.buffers.len())
+            .map(|i| {
+                let buffer = self.buffer_at(i);
+                let mut name: String = buffer.display_name().chars().take(MAX_TAB_NAME).collect();
+                if buffer.display_name().chars().count() > MAX_TAB_NAME {
+                    name.pop();
+                    name.push('\u{2026}');
+                }
+                let modified = if buffer.is_modified() { "*" } else { "" };
+                format!(" {}{} ", name, modified)
+            })
+            .collect();
+        let width_of = |i: usize| labels[i].chars().count() + 1; // plus the separator
+
+        // Leave a column on each side for the overflow markers
+        let room = (self.screen_cols as usize).saturating_sub(2);
+        let (mut first, mut last) = (self.current, self.current);
+        let mut used = width_of(self.current);
+        loop {
+            let grow_right = last + 1 < labels.len() && used + width_of(last + 1) <= room;
+            if grow_right {
+                last += 1;
+                used += width_of(last);
+            }
+            let grow_left = first > 0 && used + width_of(first - 1) <= room;
+            if grow_left {
+                first -= 1;
+                used += width_of(first);
+            }
+            if !grow_left && !grow_right {
+                break;
+            }
+        }
+
+        let mut x = 1;
+        let tabs = (first..=last)
+            .map(|i| {
+                let tab = (i, x, labels[i].clone());
+                x += width_of(i);
+                tab
+            })
+            .collect();
+        (tabs, first > 0, last + 1 < labels.len())
+    }
+
+    fn tab_bar_line(&self) -> String {
+        let (tabs, hidden_left, hidden_right) = self.visible_tabs();
+        let width = self.screen_cols as usize;
+        let mut line_out = PaneLine::new(width);
+        let inactive = |line_out: &mut PaneLine| {
+            line_out.color(RESET_COLORS);
+            line_out.color(&self.theme.current_line_bg.bg());
+            line_out.color(&self.theme.gutter_fg.fg());
+        };
+
+        inactive(&mut line_out);
+        line_out.push(if hidden_left { '<' } else { ' ' });
+        for (index, _, label) in tabs {
+            if index == self.current {
+                line_out.color(&self.theme.status_bar_bg.bg());
+                line_out.color(&self.theme.status_bar_fg.fg());
+                line_out.text(&label);
+                inactive(&mut line_out);
+            } else {
+                line_out.text(&label);
+            }
+            line_out.push('\u{2502}');
+        }
+
+        // Pad to the last column so the `>` marker sits at the right edge
+        let marker = if hidden_right { ">" } else { " " };
+        let filler = width.saturating_sub(line_out.used + 1);
+        line_out.text(&" ".repeat(filler));
+        line_out.text(marker);
+        line_out.finish()
+    }
+
+    /// Handle an SGR mouse report (`<button;x;y` parameters, 1-based
+    /// coordinates). A left click on a tab switches to its buffer.
+    fn handle_mouse(&mut self, params: &str) {
+        let mut fields = params.trim_start_matches('<').split(';').map(|f| f.parse::<usize>().ok());
+        let (Some(Some(button)), Some(Some(x)), Some(Some(y))) = (fields.next(), fields.next(), fields.next()) else {
+            return;
+        };
+        if button != 0 || y != 1 || !self.shows_tab_bar() {
+            return;
+        }
+
+        let column = x - 1;
+        let (tabs, _, _) = self.visible_tabs();
+        if let Some((index, _, _)) = tabs.iter().find(|(_, start, label)| (*start..start + label.chars().count()).contains(&column)) {
+            self.switch_to_buffer(*index);
+        }
+    }
+
+    fn active_rect(&self) -> Rect {
+        self.layout.panes(self.text_area())
+            .into_iter()
+            .find(|(view, _)| *view == self.active_view)
+            .map_or(self.text_area(), |(_, rect)| rect)
+    }
+
+    /// Rows of text in a pane. Once the screen is split, every pane gives
+    /// its last row to a title line.
+    fn pane_text_rows(&self, rect: Rect) -> usize {
+        if self.views.len() > 1 { rect.height.saturating_sub(1) } else { rect.height }
+    }
+
+    /// Rows of text visible in the active pane.
+    fn view_rows(&self) -> usize {
+        self.pane_text_rows(self.active_rect()).max(1)
+    }
+
+    fn draw_rows(&self, panes: &[(usize, Rect)]) -> io::Result<()> {
+        let mut out = String::new();
+
+        if self.shows_tab_bar() {
+            out.push_str(&Terminal::move_cursor_code(0, 0));
+            out.push_str(&self.tab_bar_line());
+        }
+
+        for &(view, rect) in panes {
+            let is_active = view == self.active_view;
+            let (buffer, state) = if is_active {
+                (&self.buffer, self.view_state())
+            } else {
+                (self.buffer_at(self.views[view].buffer), self.views[view].state)
+            };
+
+            let rows = self.pane_text_rows(rect);
+            let lines = match buffer.bytes() {
+                Some(bytes) => self.hex_pane_lines(bytes, state, rect.width, rows),
+                None => self.text_pane_lines(buffer, state, rect.width, rows),
+            };
+            for (row, line) in lines.into_iter().enumerate() {
+                out.push_str(&Terminal::move_cursor_code(rect.y + row, rect.x));
+                out.push_str(&line);
+            }
+
+            if rows < rect.height {
+                out.push_str(&Terminal::move_cursor_code(rect.y + rows, rect.x));
+                out.push_str(&self.pane_title_line(buffer, rect.width, is_active));
+            }
+        }
+
+        for separator in self.layout.separators(self.text_area()) {
+            for y in separator.y..separator.y + separator.height {
+                out.push_str(&Terminal::move_cursor_code(y, separator.x));
+                out.push_str(RESET_COLORS);
+                out.push_str(&self.theme.gutter_fg.fg());
+                out.push('\u{2502}');
+            }
+        }
+
+        out.push_str(RESET_COLORS);
+        out.push_str(&Terminal::move_cursor_code(self.screen_rows as usize, 0));
+        print!("{}", out);
+        Ok(())
+    }
+
+    /// Line numbers and highlighted text of one pane.
+    fn text_pane_lines(&self, buffer: &Buffer, state: ViewState, width: usize, rows: usize) -> Vec<String> {
+        let theme = &self.theme;
+        let mut lines = Vec::with_capacity(rows);
 
-            let file_row = row as usize + self.scroll_offset;
+        for row in 0..rows {
+            let file_row = row + state.scroll_offset;
+            let is_current_line = file_row == state.cursor_y;
+            let mut line_out = PaneLine::new(width);
+
+            // Background for the text area of this row
+            let line_bg = if is_current_line { Some(theme.current_line_bg) } else { theme.text_bg };
+
+            // Line number gutter
+            if let Some(bg) = theme.gutter_bg {
+                line_out.color(&bg.bg());
+            }
+            let number_fg = if is_current_line { theme.current_line_number_fg } else { theme.gutter_fg };
+            line_out.color(&number_fg.fg());
+            line_out.text(&format!("{:>4} ", file_row + 1));
+
+            // Text area
+            line_out.color(RESET_COLORS);
+            if let Some(bg) = line_bg {
+                line_out.color(&bg.bg());
+            }
+            if let Some(fg) = theme.text_fg {
+                line_out.color(&fg.fg());
+            }
 
-            if file_row >= self.buffer.line_count() {
+            if file_row >= buffer.line_count() {
                 // Draw empty rows with line numbers
-                if self.buffer.line_count() == 0 && row == self.screen_rows / 3 {
-                    let welcome = format!("Text Editor -- version 0.1.0");
-                    let padding = (self.screen_cols as usize).saturating_sub(welcome.len()) / 2;
+                if buffer.line_count() == 0 && row == rows / 3 {
+                    let welcome = "Text Editor -- version 0.1.0";
+                    let padding = width.saturating_sub(welcome.len()) / 2;
                     if padding > 0 {
-                        print!("{:>4} ", file_row + 1);
-                        print!("{}{}", " ".repeat(padding.saturating_sub(5)), welcome);
-                    } else {
-                        print!("{:>4} ", file_row + 1);
+                        line_out.text(&format!("{}{}", " ".repeat(padding.saturating_sub(5)), welcome));
                     }
-                } else {
-                    print!("{:>4} ", file_row + 1);
                 }
-            } else {
-                // Draw actual file content with line number
-                print!("{:>4} ", file_row + 1);
-                if let Some(line) = self.buffer.get_line(file_row) {
-                    let len = std::cmp::min(line.len(), self.screen_cols.saturating_sub(5) as usize);
-                    print!("{}", &line[..len]);
+            } else if let Some(line) = buffer.get_line(file_row) {
+                // Draw actual file content, switching colors at token boundaries
+                let highlights = buffer.line_highlight(file_row);
+                let mut last_hl = None;
+                let mut column = 0;
+
+                for (i, ch) in line.char_indices() {
+                    if line_out.is_full() {
+                        break;
+                    }
+                    let hl = highlights.and_then(|h| h.get(i).copied()).unwrap_or(Highlight::Normal);
+                    if last_hl != Some(hl) {
+                        match theme.highlight_color(hl) {
+                            Some(fg) => line_out.color(&fg.fg()),
+                            None => line_out.color(DEFAULT_FG),
+                        }
+                        last_hl = Some(hl);
+                    }
+                    // Tabs are drawn as spaces up to the next tab stop
+                    let advance = width::advance(ch, column);
+                    if ch == '\t' {
+                        (0..advance).for_each(|_| line_out.push(' '));
+                    } else {
+                        line_out.push(ch);
+                    }
+                    column += advance;
                 }
             }
 
-            println!("\r");
+            lines.push(line_out.finish());
         }
-        Ok(())
+        lines
     }
 
-    fn draw_status_bar(&self) -> io::Result<()> {
-        Terminal::set_bg_color(238, 238, 238)?;
-        Terminal::set_fg_color(0, 0, 0)?;
+    /// Offset column, hex bytes and an ASCII pane for binary files.
+    fn hex_pane_lines(&self, bytes: &[u8], state: ViewState, width: usize, rows: usize) -> Vec<String> {
+        let theme = &self.theme;
+        let cursor_offset = state.cursor_y * BYTES_PER_ROW + state.cursor_x / 2;
+        let mut lines = Vec::with_capacity(rows);
 
-        let filename = self.buffer.filename()
-            .and_then(|p| p.file_name())
-            .and_then(|n| n.to_str())
-            .unwrap_or("[No Name]");
+        for row in 0..rows {
+            let file_row = row + state.scroll_offset;
+            let is_current_line = file_row == state.cursor_y;
+            let line_bg = if is_current_line { Some(theme.current_line_bg) } else { theme.text_bg };
 
-        let modified = if self.buffer.is_modified() { " (modified)" } else { "" };
-        let left_status = format!(" {} - {} lines{}", filename, self.buffer.line_count(), modified);
+            let mut line_out = PaneLine::new(width);
+            if let Some(bg) = line_bg {
+                line_out.color(&bg.bg());
+            }
 
-        let right_status = format!("{}/{} ", self.cursor_y + 1, self.cursor_x + 1);
+            if file_row < hex::row_count(bytes.len()) {
+                let pane = hex::hex_pane(bytes, file_row);
+                let (offset, hex_bytes) = pane.split_at(hex::OFFSET_WIDTH);
+
+                let number_fg = if is_current_line { theme.current_line_number_fg } else { theme.gutter_fg };
+                line_out.color(&number_fg.fg());
+                line_out.text(offset);
+
+                match theme.text_fg {
+                    Some(fg) => line_out.color(&fg.fg()),
+                    None => line_out.color(DEFAULT_FG),
+                }
+                line_out.text(hex_bytes);
+                line_out.text(" |");
+
+                let start = file_row * BYTES_PER_ROW;
+                let end = (start + BYTES_PER_ROW).min(bytes.len());
+                for (offset, &byte) in bytes[start..end].iter().enumerate().map(|(i, b)| (start + i, b)) {
+                    if offset == cursor_offset {
+                        line_out.color(&theme.selection_bg.bg());
+                        line_out.push(hex::ascii_char(byte));
+                        match line_bg {
+                            Some(bg) => line_out.color(&bg.bg()),
+                            None => line_out.color(RESET_COLORS),
+                        }
+                    } else {
+                        line_out.push(hex::ascii_char(byte));
+                    }
+                }
+                line_out.push('|');
+            }
 
-        let mut status = left_status.clone();
-        let status_len = left_status.len() + right_status.len();
+            lines.push(line_out.finish());
+        }
+        lines
+    }
 
-        if status_len < self.screen_cols as usize {
-            let padding = self.screen_cols as usize - status_len;
-            status.push_str(&" ".repeat(padding));
+    /// Name bar under a pane when the screen is split; the focused pane's
+    /// looks like the status bar.
+    fn pane_title_line(&self, buffer: &Buffer, width: usize, is_active: bool) -> String {
+        let mut line_out = PaneLine::new(width);
+        if is_active {
+            line_out.color(&self.theme.status_bar_bg.bg());
+            line_out.color(&self.theme.status_bar_fg.fg());
         } else {
-            status.truncate(self.screen_cols as usize - right_status.len());
+            line_out.color(&self.theme.current_line_bg.bg());
+            line_out.color(&self.theme.gutter_fg.fg());
         }
+        let modified = if buffer.is_modified() { " (modified)" } else { "" };
+        line_out.text(&format!(" {}{}", buffer.display_name(), modified));
+        line_out.finish()
+    }
+
+    fn draw_status_bar(&self) -> io::Result<()> {
+        let bg = self.theme.status_bar_bg;
+        let fg = self.theme.status_bar_fg;
+        Terminal::set_bg_color(bg.r, bg.g, bg.b)?;
+        Terminal::set_fg_color(fg.r, fg.g, fg.b)?;
+
+        let filename = match self.buffers.len() {
+            1 => self.buffer.display_name().to_string(),
+            count => format!("[{}/{}] {}", self.current + 1, count, self.buffer.display_name()),
+        };
+
+        let modified = if self.buffer.is_modified() { " (modified)" } else { "" };
+        let read_only = if self.buffer.is_read_only() { " [read-only]" } else { "" };
+        let loading = match self.buffer.loading_progress() {
+            Some(percent) => format!(" [loading {}%]", percent),
+            None => String::new(),
+        };
+        let (left_status, right_status) = match self.buffer.bytes() {
+            Some(bytes) => (
+                format!(" {} - {} bytes{}{}", filename, bytes.len(), modified, read_only),
+                format!("hex | 0x{:08x} ", self.hex_offset()),
+            ),
+            None => {
+                let file_type = self.buffer.syntax().map(|s| s.name.as_str()).unwrap_or("plain text");
+                (
+                    format!(" {} - {} lines{}{}{}", filename, self.buffer.line_count(), modified, read_only, loading),
+                    format!(
+                        "{} | {} | {}/{} ",
+                        file_type,
+                        self.buffer.encoding().name(),
+                        self.cursor_y + 1,
+                        self.cursor_display_col() + 1
+                    ),
+                )
+            }
+        };
 
-        status.push_str(&right_status);
-        print!("{}", status);
+        // Cut the left part short rather than lose the cursor position
+        let cols = self.screen_cols as usize;
+        let left_room = cols.saturating_sub(width::text_width(&right_status));
+        let left = width::truncate(&left_status, left_room);
+        let padding = left_room - width::text_width(left);
+        let status = format!("{}{}{}", left, " ".repeat(padding), right_status);
+        print!("{}", width::truncate(&status, cols));
 
         Terminal::reset_colors()?;
         println!("\r");
@@ -212,107 +1348,252 @@ impl Editor {
     }
 
     fn draw_message_bar(&self) -> io::Result<()> {
+        self.set_message_bar_colors();
         Terminal::clear_line()?;
-        let msg_len = std::cmp::min(self.status_message.len(), self.screen_cols as usize);
-        print!("{}", &self.status_message[..msg_len]);
+        print!("{}", width::truncate(&self.status_message, self.screen_cols as usize));
+        Terminal::reset_colors()?;
         Ok(())
     }
 
-    fn process_keypress(&mut self) -> io::Result<()> {
-        let mut buffer = [0; 1];
-        stdin().read_exact(&mut buffer)?;
-
-        let byte = buffer[0];
+    fn set_message_bar_colors(&self) {
+        print!("{}", RESET_COLORS);
+        if let Some(bg) = self.theme.message_bar_bg {
+            print!("{}", bg.bg());
+        }
+        if let Some(fg) = self.theme.message_bar_fg {
+            print!("{}", fg.fg());
+        }
+    }
 
+    fn process_keypress(&mut self, byte: u8) -> io::Result<()> {
         // Reset temporary message to help text if it was temporary
         if self.message_is_temporary {
-            self.status_message = String::from(HELP_MESSAGE);
+            self.status_message = self.help.clone();
             self.message_is_temporary = false;
         }
 
-        // Handle Ctrl key combinations
-        const CTRL_Q: u8 = b'q' & 0x1f; // Ctrl-Q = 17
-        const CTRL_S: u8 = b's' & 0x1f; // Ctrl-S = 19
-        const CTRL_A: u8 = b'a' & 0x1f; // Ctrl-A = 1
+        let sequence = if byte == 0x1b { self.read_escape_sequence() } else { None };
+        let key = match &sequence {
+            Some((params, final_byte)) => Key::from_escape(params, *final_byte),
+            None => Key::from_byte(byte),
+        };
+        if let Some(key) = key
+            && self.run_key(key)
+        {
+            return Ok(());
+        }
 
         match byte {
-            CTRL_Q => { // Ctrl-Q
-                self.quit = true;
-            },
-            CTRL_S => { // Ctrl-S
-                self.save_file();
-            },
-            CTRL_A => { // Ctrl-A (Save As)
-                self.save_file_as();
-            },
             0x1b => { // Escape sequence (arrow keys, etc.)
-                self.handle_escape_sequence()?;
+                if let Some((params, final_byte)) = sequence {
+                    self.handle_escape_sequence(&params, final_byte);
+                }
+            },
+            _ if self.buffer.is_binary() => { // Hex view has its own editing keys
+                self.process_hex_keypress(byte);
             },
             13 | 10 => { // Enter/Return
                 self.insert_newline();
             },
-            127 | 8 => { // Backspace
-                self.delete_char();
+            127 | 8 => { // Backspace
+                self.delete_char();
+            },
+            byte if (32..127).contains(&byte) => { // Printable ASCII
+                self.insert_char(byte as char);
+            },
+            _ => {
+                // Ignore other control characters
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add `key` to the key sequence being typed and run the command it
+    /// completes. Returns false if the key isn't part of any binding, so
+    /// it does what it does by itself (types, moves and so on).
+    fn run_key(&mut self, key: Key) -> bool {
+        self.pending_keys.push(key);
+        match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Command(command) => {
+                self.pending_keys.clear();
+                self.run_command(command, "");
+            },
+            Lookup::Prefix => {
+                let next: Vec<String> = self.keymap.continuations(&self.pending_keys)
+                    .iter()
+                    .map(|(keys, command)| format!("{} = {}", keys, command.info().name))
+                    .collect();
+                self.show_message(format!("{}: {}", keymap::sequence_name(&self.pending_keys), next.join(" | ")));
+            },
+            Lookup::Unbound => {
+                let typed = mem::take(&mut self.pending_keys);
+                if typed.len() == 1 {
+                    return false;
+                }
+                // Escape just cancels the sequence
+                if !key.is_escape() {
+                    self.show_message(format!("{} is not bound", keymap::sequence_name(&typed)));
+                }
+            },
+        }
+        true
+    }
+
+    /// Read the rest of an escape sequence after ESC. Returns `None` for a
+    /// lone Escape key press, otherwise the parameters and final byte, e.g.
+    /// `("", b'A')` for Up or `("5;5", b'~')` for Ctrl-PageUp.
+    fn read_escape_sequence(&self) -> Option<(String, u8)> {
+        match self.input.recv_timeout(ESCAPE_TIMEOUT).ok()? {
+            // CSI: parameter bytes up to a final byte in @..~
+            b'[' => {
+                let mut params = String::new();
+                loop {
+                    let byte = self.read_byte().ok()?;
+                    if (0x40..=0x7e).contains(&byte) {
+                        return Some((params, byte));
+                    }
+                    params.push(byte as char);
+                }
+            },
+            // SS3: some terminals send ESC O H for Home and so on
+            b'O' => self.read_byte().ok().map(|byte| (String::new(), byte)),
+            _ => None,
+        }
+    }
+
+    /// What `byte`, just read, is to a full-screen list, reading the rest of
+    /// an escape sequence if it starts one.
+    pub fn list_key(&self, byte: u8) -> ListKey {
+        match byte {
+            13 | 10 => ListKey::Enter,
+            0x1b => match self.read_escape_sequence() {
+                None => ListKey::Escape,
+                Some((params, final_byte)) => ListKey::Sequence(params, final_byte),
+            },
+            byte => ListKey::Byte(byte),
+        }
+    }
+
+    /// Keys not in the keymap that come as escape sequences, and terminal
+    /// reports.
+    fn handle_escape_sequence(&mut self, params: &str, final_byte: u8) {
+        match (params, final_byte) {
+            ("", b'I') => self.check_disk_changes(), // Terminal window gained focus
+            (mouse, b'M') if mouse.starts_with('<') => self.handle_mouse(mouse), // Mouse button pressed
+            _ if self.buffer.is_binary() => self.process_hex_escape(params, final_byte),
+            ("", b'A') => self.move_cursor_up(),    // Up arrow
+            ("", b'B') => self.move_cursor_down(),  // Down arrow
+            ("", b'C') => self.move_cursor_right(), // Right arrow
+            ("", b'D') => self.move_cursor_left(),  // Left arrow
+            ("", b'H') => self.move_cursor_home(),  // Home
+            ("", b'F') => self.move_cursor_end(),   // End
+            ("5", b'~') => self.move_page_up(),     // PageUp
+            ("6", b'~') => self.move_page_down(),   // PageDown
+            ("1;5", b'H') => self.move_to_line(0),  // Ctrl-Home
+            ("1;5", b'F') => self.move_to_end(),    // Ctrl-End
+            ("1;5", b'D') => self.move_word_left(),  // Ctrl-Left
+            ("1;5", b'C') => self.move_word_right(), // Ctrl-Right
+            ("1;5", b'A') => self.move_paragraph_up(),   // Ctrl-Up
+            ("1;5", b'B') => self.move_paragraph_down(), // Ctrl-Down
+            ("3", b'~') => self.delete_char_forward(), // Delete
+            _ => {}
+        }
+    }
+
+    fn process_hex_escape(&mut self, params: &str, final_byte: u8) {
+        const NIBBLES_PER_ROW: isize = (BYTES_PER_ROW * 2) as isize;
+
+        match (params, final_byte) {
+            ("", b'A') => self.move_hex_cursor(-NIBBLES_PER_ROW),
+            ("", b'B') => self.move_hex_cursor(NIBBLES_PER_ROW),
+            ("", b'C') => self.move_hex_cursor(1),
+            ("", b'D') => self.move_hex_cursor(-1),
+            ("", b'H') => self.cursor_x = 0,
+            ("", b'F') => self.move_hex_cursor(NIBBLES_PER_ROW - 1 - self.cursor_x as isize),
+            ("5", b'~') => {
+                self.cursor_y = self.cursor_y.saturating_sub(self.view_rows());
+                self.adjust_scroll();
             },
-            byte if byte >= 32 && byte < 127 => { // Printable ASCII
-                self.insert_char(byte as char);
+            ("6", b'~') => self.move_hex_cursor(NIBBLES_PER_ROW * self.view_rows() as isize),
+            ("1;5", b'H') => {
+                (self.cursor_y, self.cursor_x) = (0, 0);
+                self.adjust_scroll();
             },
-            _ => {
-                // Ignore other control characters
-            }
+            ("1;5", b'F') => self.move_hex_cursor(isize::MAX),
+            _ => {}
         }
-
-        Ok(())
     }
 
-    fn handle_escape_sequence(&mut self) -> io::Result<()> {
-        let mut buffer = [0; 2];
-
-        // Try to read the next two bytes
-        if stdin().read_exact(&mut buffer).is_ok() {
-            if buffer[0] == b'[' {
-                match buffer[1] {
-                    b'A' => self.move_cursor_up(),    // Up arrow
-                    b'B' => self.move_cursor_down(),  // Down arrow
-                    b'C' => self.move_cursor_right(), // Right arrow
-                    b'D' => self.move_cursor_left(),  // Left arrow
-                    b'H' => self.move_cursor_home(),  // Home
-                    b'F' => self.move_cursor_end(),   // End
-                    b'3' => {
-                        // Delete key sends ESC[3~
-                        let mut tilde = [0; 1];
-                        if stdin().read_exact(&mut tilde).is_ok() && tilde[0] == b'~' {
-                            self.delete_char_forward();
-                        }
-                    },
-                    _ => {}
-                }
+    fn process_hex_keypress(&mut self, byte: u8) {
+        if let Some(value) = hex::digit_value(byte) {
+            if !self.ensure_editable() {
+                return;
             }
+            // Overwrite the nibble under the cursor and step to the next one
+            let offset = self.hex_offset();
+            let high = self.cursor_x.is_multiple_of(2);
+            self.buffer.set_nibble(offset, high, value);
+            self.move_hex_cursor(1);
         }
+    }
 
-        Ok(())
+    /// Byte offset under the cursor in the hex view, where `cursor_y` is the
+    /// row and `cursor_x` the nibble within it.
+    fn hex_offset(&self) -> usize {
+        self.cursor_y * BYTES_PER_ROW + self.cursor_x / 2
+    }
+
+    fn move_hex_cursor(&mut self, delta: isize) {
+        let len = self.buffer.bytes().map_or(0, |b| b.len());
+        let last = (len * 2).saturating_sub(1);
+        let position = self.cursor_y * BYTES_PER_ROW * 2 + self.cursor_x;
+
+        if let Some(new) = position.checked_add_signed(delta) {
+            let new = new.min(last);
+            self.cursor_y = new / (BYTES_PER_ROW * 2);
+            self.cursor_x = new % (BYTES_PER_ROW * 2);
+            self.adjust_scroll();
+        }
     }
 
     fn move_cursor_up(&mut self) {
         if self.cursor_y > 0 {
-            self.cursor_y -= 1;
-            self.snap_cursor_to_line();
-            self.adjust_scroll();
+            let column = self.preferred_column();
+            self.move_vertically_to(self.cursor_y - 1, column);
         }
     }
 
     fn move_cursor_down(&mut self) {
         // Only move down if there's a line below
         if self.cursor_y + 1 < self.buffer.line_count() {
-            self.cursor_y += 1;
-            self.snap_cursor_to_line();
-            self.adjust_scroll();
+            let column = self.preferred_column();
+            self.move_vertically_to(self.cursor_y + 1, column);
+        }
+    }
+
+    /// The display column to aim for when moving up or down: the one kept
+    /// from the last vertical move if the cursor hasn't moved since,
+    /// otherwise the cursor's own.
+    fn preferred_column(&self) -> usize {
+        match self.preferred_column {
+            Some(preferred) if preferred.at == (self.cursor_y, self.cursor_x) => preferred.column,
+            _ => self.cursor_display_col(),
         }
     }
 
+    /// Put the cursor on `row`, at display `column` or as near as the line
+    /// allows, and keep `column` for the next vertical move.
+    fn move_vertically_to(&mut self, row: usize, column: usize) {
+        self.cursor_y = row;
+        self.cursor_x = self.buffer.get_line(row).map_or(0, |line| width::index_at_column(line, column));
+        self.preferred_column = Some(PreferredColumn { column, at: (self.cursor_y, self.cursor_x) });
+        self.adjust_scroll();
+    }
+
     fn move_cursor_left(&mut self) {
         if self.cursor_x > 0 {
-            self.cursor_x -= 1;
+            self.cursor_x = self.buffer.prev_char_boundary(self.cursor_y, self.cursor_x);
         } else if self.cursor_y > 0 {
             self.cursor_y -= 1;
             self.cursor_x = self.buffer.line_len(self.cursor_y);
@@ -323,7 +1604,7 @@ impl Editor {
     fn move_cursor_right(&mut self) {
         let line_len = self.buffer.line_len(self.cursor_y);
         if self.cursor_x < line_len {
-            self.cursor_x += 1;
+            self.cursor_x = self.buffer.next_char_boundary(self.cursor_y, self.cursor_x);
         } else if self.cursor_y < self.buffer.line_count() - 1 {
             // Wrap to next line if it exists
             self.cursor_y += 1;
@@ -334,46 +1615,330 @@ impl Editor {
         }
     }
 
+    /// Smart Home: to the first non-blank character, or to the start of the
+    /// line if already there.
     fn move_cursor_home(&mut self) {
-        self.cursor_x = 0;
+        let line = self.buffer.get_line(self.cursor_y).unwrap_or("");
+        let indent = line.len() - line.trim_start().len();
+        self.cursor_x = if self.cursor_x == indent { 0 } else { indent };
     }
 
     fn move_cursor_end(&mut self) {
         self.cursor_x = self.buffer.line_len(self.cursor_y);
     }
 
-    fn snap_cursor_to_line(&mut self) {
-        let line_len = self.buffer.line_len(self.cursor_y);
-        if self.cursor_x > line_len {
-            self.cursor_x = line_len;
+    /// Scroll up a screenful, keeping the cursor on the same screen row.
+    fn move_page_up(&mut self) {
+        let rows = self.view_rows();
+        let column = self.preferred_column();
+        self.scroll_offset = self.scroll_offset.saturating_sub(rows);
+        self.move_vertically_to(self.cursor_y.saturating_sub(rows), column);
+    }
+
+    /// Scroll down a screenful, keeping the cursor on the same screen row.
+    fn move_page_down(&mut self) {
+        let rows = self.view_rows();
+        let line_count = self.buffer.line_count();
+        let column = self.preferred_column();
+        self.scroll_offset = (self.scroll_offset + rows).min(line_count.saturating_sub(rows));
+        self.move_vertically_to((self.cursor_y + rows).min(line_count - 1), column);
+    }
+
+    /// Start of line `row`.
+    fn move_to_line(&mut self, row: usize) {
+        self.cursor_y = row;
+        self.cursor_x = 0;
+        self.adjust_scroll();
+    }
+
+    /// Ctrl-End: end of the last line, once the whole file is loaded.
+    fn move_to_end(&mut self) {
+        match self.buffer.end_position() {
+            Ok((row, column)) => {
+                self.cursor_y = row;
+                self.cursor_x = column;
+                self.adjust_scroll();
+            },
+            Err(e) => self.show_message(format!("Error loading file: {}", e)),
+        }
+    }
+
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || self.word_chars.contains(c)
+    }
+
+    /// Ctrl-Left: to the start of the word before the cursor, going back
+    /// over line breaks if need be.
+    fn move_word_left(&mut self) {
+        loop {
+            let line = self.buffer.get_line(self.cursor_y).unwrap_or("");
+            let before = line.get(..self.cursor_x).unwrap_or(line);
+            let rest = before.trim_end_matches(|c| !self.is_word_char(c));
+            if rest.is_empty() && self.cursor_y > 0 {
+                self.cursor_y -= 1;
+                self.cursor_x = self.buffer.line_len(self.cursor_y);
+                continue;
+            }
+            self.cursor_x = rest.trim_end_matches(|c| self.is_word_char(c)).len();
+            break;
+        }
+        self.adjust_scroll();
+    }
+
+    /// Ctrl-Right: to the end of the word after the cursor, going on over
+    /// line breaks if need be.
+    fn move_word_right(&mut self) {
+        loop {
+            let line = self.buffer.get_line(self.cursor_y).unwrap_or("");
+            let after = line.get(self.cursor_x..).unwrap_or("");
+            let rest = after.trim_start_matches(|c| !self.is_word_char(c));
+            if rest.is_empty() && self.cursor_y + 1 < self.buffer.line_count() {
+                self.cursor_y += 1;
+                self.cursor_x = 0;
+                continue;
+            }
+            self.cursor_x = line.len() - rest.trim_start_matches(|c| self.is_word_char(c)).len();
+            break;
+        }
+        self.adjust_scroll();
+    }
+
+    fn is_blank_line(&self, row: usize) -> bool {
+        self.buffer.get_line(row).is_some_and(|line| line.trim().is_empty())
+    }
+
+    /// Ctrl-Up: to the blank line before the paragraph above the cursor, or
+    /// the first line.
+    fn move_paragraph_up(&mut self) {
+        let mut row = self.cursor_y.saturating_sub(1);
+        while row > 0 && self.is_blank_line(row) {
+            row -= 1;
+        }
+        while row > 0 && !self.is_blank_line(row) {
+            row -= 1;
+        }
+        self.move_to_line(row);
+    }
+
+    /// Ctrl-Down: to the blank line after the paragraph below the cursor,
+    /// or the last line.
+    fn move_paragraph_down(&mut self) {
+        let last = self.buffer.line_count() - 1;
+        let mut row = (self.cursor_y + 1).min(last);
+        while row < last && self.is_blank_line(row) {
+            row += 1;
+        }
+        while row < last && !self.is_blank_line(row) {
+            row += 1;
         }
+        self.move_to_line(row);
+    }
+
+    fn snap_cursor_to_line(&mut self) {
+        // Also keeps the cursor off the middle of a multi-byte character
+        self.cursor_x = self.buffer.floor_char_boundary(self.cursor_y, self.cursor_x);
     }
 
     fn adjust_scroll(&mut self) {
         if self.cursor_y < self.scroll_offset {
             self.scroll_offset = self.cursor_y;
         }
-        if self.cursor_y >= self.scroll_offset + self.screen_rows as usize {
-            self.scroll_offset = self.cursor_y - self.screen_rows as usize + 1;
+        let rows = self.view_rows();
+        if self.cursor_y >= self.scroll_offset + rows {
+            self.scroll_offset = self.cursor_y - rows + 1;
+        }
+    }
+
+    /// Take back the last change recorded for undo in the active buffer.
+    fn undo(&mut self) {
+        if !self.ensure_editable() {
+            return;
+        }
+        let message = match self.buffer.undo() {
+            Ok(Some(description)) => format!("Undid {}", description),
+            Ok(None) => String::from("Nothing to undo"),
+            Err(e) => format!("Cannot undo: {}", e),
+        };
+        self.show_message(message);
+        self.clamp_cursor();
+    }
+
+    /// Whether the buffer may be edited; explains why not in the message bar.
+    fn ensure_editable(&mut self) -> bool {
+        if self.buffer.is_read_only() {
+            self.show_message(String::from("Buffer is read-only (Ctrl-R to allow editing)"));
+            return false;
+        }
+        true
+    }
+
+    fn toggle_read_only(&mut self) {
+        let read_only = !self.buffer.is_read_only();
+        if !read_only && let Some(refusal) = self.editing_refusal() {
+            self.show_message(refusal);
+            return;
+        }
+        self.buffer.set_read_only(read_only);
+        self.show_message(String::from(if read_only { "Read-only" } else { "Editing enabled" }));
+    }
+
+    /// Why editing can't be allowed: the file failed to open, or we lack
+    /// permission to write it and there's no `privileged_save` to fall back
+    /// on. `None` when saving could work.
+    fn editing_refusal(&self) -> Option<String> {
+        if self.buffer.can_write_file() {
+            return None;
+        }
+        match self.buffer.filename() {
+            Some(path) if config::setting("editor", "privileged_save").is_none() => {
+                Some(format!("No permission to write {}; staying read-only", path.display()))
+            },
+            Some(_) => None,
+            None => Some(String::from("The file could not be read; staying read-only")),
         }
     }
 
     fn insert_char(&mut self, ch: char) {
+        if !self.ensure_editable() {
+            return;
+        }
         self.buffer.insert_char(self.cursor_y, self.cursor_x, ch);
-        self.cursor_x += 1;
+        self.cursor_x += ch.len_utf8();
     }
 
     fn insert_newline(&mut self) {
+        if !self.ensure_editable() {
+            return;
+        }
+        self.dedent_closing_line();
+
+        // Language modes carry indentation over, adding a level after block openers
+        let indent = match (self.buffer.syntax(), self.buffer.get_line(self.cursor_y)) {
+            (Some(syntax), Some(line)) => {
+                syntax.indent_after_line(line.get(..self.cursor_x).unwrap_or(line))
+            },
+            _ => String::new(),
+        };
+
         self.buffer.insert_newline(self.cursor_y, self.cursor_x);
         self.cursor_y += 1;
-        self.cursor_x = 0;
+        self.buffer.insert_str(self.cursor_y, 0, &indent);
+        self.cursor_x = indent.len();
         self.adjust_scroll();
     }
 
+    /// When Enter finishes a line like `end` or `}`, move it one level left
+    /// unless the user already lined it up themselves.
+    fn dedent_closing_line(&mut self) {
+        let row = self.cursor_y;
+        let (Some(syntax), Some(line)) = (self.buffer.syntax(), self.buffer.get_line(row)) else {
+            return;
+        };
+        if !syntax.is_dedent_line(line) {
+            return;
+        }
+
+        let indent = Syntax::indentation(line).len();
+        let previous_indent = (0..row).rev()
+            .filter_map(|r| self.buffer.get_line(r))
+            .find(|l| !l.trim().is_empty())
+            .map(|l| Syntax::indentation(l).len())
+            .unwrap_or(0);
+        if indent == 0 || indent < previous_indent {
+            return;
+        }
+
+        let width = syntax.dedent_width(line);
+        self.buffer.delete_range(row, indent - width, indent);
+        self.cursor_x = self.cursor_x.saturating_sub(width);
+    }
+
+    fn toggle_comment(&mut self) {
+        if !self.ensure_editable() {
+            return;
+        }
+        let row = self.cursor_y;
+        let Some(prefix) = self.buffer.syntax().and_then(|s| s.line_comment.clone()) else {
+            self.show_message(String::from("No line comments for this file type"));
+            return;
+        };
+        let Some(line) = self.buffer.get_line(row) else { return };
+
+        let indent = Syntax::indentation(line).len();
+        let rest = &line[indent..];
+
+        if let Some(after) = rest.strip_prefix(prefix.as_str()) {
+            let len = prefix.len() + usize::from(after.starts_with(' '));
+            self.buffer.delete_range(row, indent, indent + len);
+            if self.cursor_x > indent {
+                self.cursor_x = self.cursor_x.saturating_sub(len).max(indent);
+            }
+        } else {
+            let marker = format!("{} ", prefix);
+            self.buffer.insert_str(row, indent, &marker);
+            if self.cursor_x >= indent {
+                self.cursor_x += marker.len();
+            }
+        }
+    }
+
+    fn complete_word(&mut self) {
+        if !self.ensure_editable() {
+            return;
+        }
+        let Some(line) = self.buffer.get_line(self.cursor_y) else { return };
+        let before = line.get(..self.cursor_x).unwrap_or(line);
+        let start = before
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(before.len());
+        let prefix = &before[start..];
+
+        let Some(syntax) = self.buffer.syntax() else {
+            self.show_message(String::from("No completions for plain text"));
+            return;
+        };
+        if prefix.is_empty() {
+            self.show_message(String::from("Nothing to complete"));
+            return;
+        }
+
+        let candidates: Vec<String> = syntax.completions_for(prefix).into_iter().map(String::from).collect();
+        let Some(first) = candidates.first() else {
+            self.show_message(format!("No completions for '{}'", prefix));
+            return;
+        };
+
+        // Insert as much as all candidates agree on
+        let common = candidates.iter().fold(first.as_str(), |acc, c| {
+            let len = acc.char_indices()
+                .zip(c.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map(|((i, a), _)| i + a.len_utf8())
+                .unwrap_or(0);
+            &acc[..len]
+        });
+        let insertion = common[prefix.len()..].to_string();
+
+        if candidates.len() > 1 {
+            self.show_message(candidates.join(" "));
+        }
+        self.buffer.insert_str(self.cursor_y, self.cursor_x, &insertion);
+        self.cursor_x += insertion.len();
+    }
+
     fn delete_char(&mut self) {
+        if !self.ensure_editable() {
+            return;
+        }
         if self.cursor_x > 0 {
-            self.buffer.delete_char(self.cursor_y, self.cursor_x - 1);
-            self.cursor_x -= 1;
+            let previous = self.buffer.prev_char_boundary(self.cursor_y, self.cursor_x);
+            self.buffer.delete_char(self.cursor_y, previous);
+            self.cursor_x = previous;
         } else if self.cursor_y > 0 {
             self.cursor_x = self.buffer.line_len(self.cursor_y - 1);
             self.buffer.delete_newline(self.cursor_y);
@@ -383,6 +1948,9 @@ impl Editor {
     }
 
     fn delete_char_forward(&mut self) {
+        if !self.ensure_editable() {
+            return;
+        }
         let line_len = self.buffer.line_len(self.cursor_y);
         if self.cursor_x < line_len {
             self.buffer.delete_char(self.cursor_y, self.cursor_x);
@@ -394,21 +1962,16 @@ impl Editor {
     fn save_file(&mut self) {
         // If no filename, prompt for one
         if self.buffer.filename().is_none() {
-            if let Some(filename) = self.prompt_for_filename() {
-                match self.buffer.save_as(PathBuf::from(&filename)) {
+            if let Some(target) = self.prompt_for_filename(None) {
+                match self.buffer.save_as(target) {
                     Ok(_) => {
                         self.save_count += 1;
-                        self.status_message = format!("{} saved! (save #{})", filename, self.save_count);
-                        self.message_is_temporary = true;
+                        self.show_message(format!("{} saved! (save #{})", self.buffer.display_name(), self.save_count));
                     },
                     Err(e) => {
-                        self.status_message = format!("Error saving file: {}", e);
-                        self.message_is_temporary = true;
+                        self.show_message(format!("Error saving file: {}", e));
                     }
                 }
-            } else {
-                self.status_message = format!("Save aborted");
-                self.message_is_temporary = true;
             }
         } else {
             // File already has a name, save directly
@@ -418,86 +1981,688 @@ impl Editor {
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "file".to_string());
 
-            self.save_count += 1;
+            // The file may have been opened from outside the save root
+            if let Some(path) = self.buffer.filename().cloned()
+                && self.check_save_path(&path).is_none()
+            {
+                return;
+            }
+
+            // Don't silently overwrite what another program wrote
+            if self.buffer.changed_on_disk() && !self.resolve_disk_conflict(true) {
+                return;
+            }
+
             match self.buffer.save() {
                 Ok(_) => {
-                    self.status_message = format!("{} saved! (save #{})", fname, self.save_count);
-                    self.message_is_temporary = true;
+                    self.save_count += 1;
+                    self.show_message(format!("{} saved! (save #{})", fname, self.save_count));
+                },
+                Err(e) if self.buffer.is_read_only() => {
+                    self.show_message(format!("Error saving file: {} (Ctrl-R to allow editing)", e));
+                },
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    self.save_privileged(&fname, e);
                 },
                 Err(e) => {
-                    self.status_message = format!("Error saving file: {}", e);
-                    self.message_is_temporary = true;
+                    self.show_message(format!("Error saving file: {}", e));
                 }
             }
         }
     }
 
-    fn save_file_as(&mut self) {
-        // Always prompt for filename (even if one exists)
-        if let Some(filename) = self.prompt_for_filename() {
-            match self.buffer.save_as(std::path::PathBuf::from(filename)) {
+    /// After a save failed for lack of permission, offer to write the file
+    /// through the `privileged_save` command from config.ini.
+    fn save_privileged(&mut self, fname: &str, error: io::Error) {
+        let Some(command) = config::setting("editor", "privileged_save") else {
+            self.show_message(format!("Error saving file: {} (set privileged_save in config.ini to save via e.g. sudo)", error));
+            return;
+        };
+
+        let question = format!("No permission to write {}. Save with `{}`? (y/n) ", fname, command);
+        if self.ask(&question) != Some(b'y') {
+            self.show_message(format!("Error saving file: {}", error));
+            return;
+        }
+
+        // Give the command a clean screen and the terminal in its normal
+        // mode, with nothing else reading keys, in case it asks for a password
+        let parked = self.input_gate.park();
+        let _ = Terminal::reset_colors();
+        let _ = Terminal::clear_screen();
+        let _ = Terminal::move_cursor(0, 0);
+        let _ = self.terminal.suspend();
+
+        let saved = self.buffer.save_privileged(&command);
+
+        let _ = self.terminal.resume();
+        drop(parked);
+        let message = match saved {
+            Ok(()) => {
+                self.save_count += 1;
+                format!("{} saved with {} (save #{})", fname, command, self.save_count)
+            },
+            Err(e) => format!("Privileged save failed: {}", e),
+        };
+        self.show_message(message);
+    }
+
+    /// Save under the name `given`, or one asked for (even if the buffer
+    /// has one already).
+    fn save_file_as(&mut self, given: Option<&str>) {
+        if let Some(target) = self.prompt_for_filename(given) {
+            match self.buffer.save_as(target) {
                 Ok(_) => {
-                    self.status_message = format!("File saved as successfully!");
-                    self.message_is_temporary = true;
+                    self.show_message(String::from("File saved as successfully!"));
                 },
                 Err(e) => {
-                    self.status_message = format!("Error saving file: {}", e);
-                    self.message_is_temporary = true;
+                    self.show_message(format!("Error saving file: {}", e));
                 }
             }
+        }
+    }
+
+    /// Switch to the theme `given`, or one asked for.
+    fn switch_theme(&mut self, given: Option<&str>) {
+        let names = self.themes.names().join(", ");
+        let original = self.theme.clone();
+        let prompt = Prompt::new(PromptKind::Theme, &format!("Theme ({}): ", names))
+            .on_change(|editor: &mut Editor, name: &str| {
+                // Preview each theme once its name is typed
+                if let Some(theme) = editor.themes.get(name.trim()) {
+                    editor.theme = theme.clone();
+                    let _ = editor.refresh_screen();
+                }
+            })
+            .validate(|editor: &Editor, name: &str| match editor.themes.get(name.trim()) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown theme {}", name.trim())),
+            });
+        let Some(name) = self.answer(given, prompt, "Theme unchanged") else {
+            self.theme = original;
+            return;
+        };
+
+        match self.themes.get(name.trim()) {
+            Some(theme) => {
+                self.theme = theme.clone();
+                self.show_message(format!("Theme: {}", self.theme.name));
+            },
+            None => self.show_message(format!("Unknown theme: {}", name.trim())),
+        }
+    }
+
+    /// Re-read the file in another encoding, or pick the encoding it will
+    /// be saved in.
+    fn change_encoding(&mut self, given: Option<&str>) {
+        if self.buffer.is_binary() {
+            self.show_message(String::from("Binary files have no encoding"));
+            return;
+        }
+
+        let names: Vec<&str> = Encoding::ALL.iter().map(|e| e.name()).collect();
+        let prompt = Prompt::new(PromptKind::Encoding, &format!("Encoding ({}): ", names.join(", ")))
+            .validate(|_: &Editor, name: &str| match Encoding::from_name(name) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown encoding {}", name.trim())),
+            });
+        let Some(name) = self.answer(given, prompt, "Encoding unchanged") else { return };
+        let Some(encoding) = Encoding::from_name(&name) else {
+            self.show_message(format!("Unknown encoding: {}", name.trim()));
+            return;
+        };
+
+        let question = format!("(r)eopen file as {0} or (s)ave as {0}? ", encoding.name());
+        match self.ask(&question) {
+            Some(b'r') if self.buffer.filename().is_none() => {
+                self.show_message(String::from("Nothing to reopen: the buffer has no file"));
+            },
+            Some(b'r') => {
+                if self.buffer.is_modified()
+                    && self.ask("Discard unsaved changes? (y/n) ") != Some(b'y')
+                {
+                    self.show_message(String::from("Reopen cancelled"));
+                    return;
+                }
+                match self.buffer.reload_with_encoding(encoding) {
+                    Ok(()) => {
+                        self.clamp_cursor();
+                        self.show_message(format!("Reopened as {}", encoding.name()));
+                    },
+                    Err(e) => self.show_message(format!("Can't reopen as {}: {}", encoding.name(), e)),
+                }
+            },
+            Some(b's') => match self.buffer.set_encoding(encoding) {
+                Ok(()) => self.save_file(),
+                Err(e) => self.show_message(format!("Can't save as {}: {}", encoding.name(), e)),
+            },
+            _ => self.show_message(String::from("Encoding unchanged")),
+        }
+    }
+
+    /// Keep the cursor inside the buffer after its contents were replaced.
+    fn clamp_cursor(&mut self) {
+        if self.buffer.is_binary() {
+            self.move_hex_cursor(0);
+        } else {
+            self.cursor_y = self.cursor_y.min(self.buffer.line_count() - 1);
+            self.snap_cursor_to_line();
+            self.adjust_scroll();
+        }
+    }
+
+    /// Ask what to do if another program changed the file since we last
+    /// read or wrote it.
+    fn check_disk_changes(&mut self) {
+        if self.buffer.changed_on_disk() {
+            self.resolve_disk_conflict(false);
+        }
+    }
+
+    /// Let the user reload the changed file, keep the buffer, or look at the
+    /// differences first. When `saving`, keeping ours means overwriting the
+    /// file; returns whether the save should go ahead.
+    fn resolve_disk_conflict(&mut self, saving: bool) -> bool {
+        let name = self.buffer.filename()
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .unwrap_or("file")
+            .to_string();
+        let question = if saving {
+            format!("{} changed on disk. (o)verwrite, (r)eload, (d)iff or Esc to cancel? ", name)
         } else {
-            self.status_message = format!("Save aborted");
-            self.message_is_temporary = true;
+            format!("{} changed on disk. (r)eload, (k)eep ours or (d)iff? ", name)
+        };
+
+        loop {
+            match self.ask(&question) {
+                Some(b'd') => self.show_disk_diff(),
+                Some(b'r') => {
+                    match self.buffer.reload() {
+                        Ok(()) => {
+                            self.clamp_cursor();
+                            self.show_message(format!("Reloaded {}", name));
+                        },
+                        Err(e) => self.show_message(format!("Can't reload {}: {}", name, e)),
+                    }
+                    return false;
+                },
+                Some(b'o') if saving => return true,
+                None if saving => {
+                    self.show_message(String::from("Save cancelled"));
+                    return false;
+                },
+                // Escape keeps ours too, so the question isn't repeated forever
+                Some(b'k') | None => {
+                    self.buffer.keep_ours();
+                    self.show_message(format!("Kept the edited {}; saving will overwrite the disk copy", name));
+                    return false;
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Unified diff from the file on disk to the buffer.
+    fn show_disk_diff(&mut self) {
+        let title = "Changes from disk to buffer";
+        if self.buffer.is_binary() {
+            self.show_text_view(title, &[String::from("Binary file differs")]);
+            return;
+        }
+
+        let disk = match self.buffer.disk_contents() {
+            Ok(text) => text,
+            Err(e) => {
+                self.show_text_view(title, &[format!("Can't read the file: {}", e)]);
+                return;
+            }
+        };
+        let ours = self.buffer.contents();
+        let disk_lines: Vec<&str> = disk.lines().collect();
+        let our_lines: Vec<&str> = ours.lines().collect();
+
+        let ops = diff::diff_lines(&disk_lines, &our_lines);
+        let mut lines = diff::unified("on disk", "buffer", &ops, 3);
+        if lines.is_empty() {
+            lines.push(String::from("No differences in the text (only encoding or line endings changed)"));
         }
+        self.show_text_view(title, &lines);
     }
 
-    fn prompt_for_filename(&mut self) -> Option<String> {
-        let mut filename = String::new();
+    /// Read-only full-screen view of `lines`, scrolled with the arrow keys,
+    /// PageUp/PageDown and space, and closed with q or Escape. Diff lines are
+    /// colored.
+    pub fn show_text_view(&mut self, title: &str, lines: &[String]) {
+        let rows = self.screen_rows as usize;
+        let width = self.screen_cols as usize;
+        let mut list = ListNav::scrolling(rows);
+        list.set_len(lines.len());
 
         loop {
-            // Draw the prompt
-            Terminal::hide_cursor().ok()?;
-            Terminal::move_cursor(self.screen_rows + 1, 0).ok()?;
-            Terminal::clear_line().ok()?;
-            print!("Save as: {}", filename);
-            io::stdout().flush().ok()?;
-            Terminal::show_cursor().ok()?;
-
-            // Read a character
-            let mut buffer = [0; 1];
-            if stdin().read_exact(&mut buffer).is_err() {
-                // Clean up before returning
-                Terminal::move_cursor(self.screen_rows + 1, 0).ok();
-                Terminal::clear_line().ok();
+            let top = list.top();
+            let _ = Terminal::hide_cursor();
+            let _ = Terminal::move_cursor(0, 0);
+
+            for row in 0..rows {
+                let mut out = String::from(RESET_COLORS);
+                if let Some(bg) = self.theme.text_bg {
+                    out.push_str(&bg.bg());
+                }
+                out.push_str(CLEAR_LINE);
+
+                if let Some(line) = lines.get(top + row) {
+                    let color = if line.starts_with("@@") {
+                        Some(self.theme.gutter_fg)
+                    } else if line.starts_with('+') && !line.starts_with("+++") {
+                        Some(self.theme.diff_added_fg)
+                    } else if line.starts_with('-') && !line.starts_with("---") {
+                        Some(self.theme.diff_removed_fg)
+                    } else {
+                        self.theme.text_fg
+                    };
+                    if let Some(fg) = color {
+                        out.push_str(&fg.fg());
+                    }
+                    out.extend(line.chars().take(width));
+                }
+
+                out.push_str(RESET_COLORS);
+                print!("{}\r\n", out);
+            }
+
+            let status = format!(" {} - lines {}-{} of {}", title, top + 1, (top + rows).min(lines.len()), lines.len());
+            self.draw_overlay_bars(&status, "Arrows/PageUp/PageDown/space to scroll, q or Esc to close");
+
+            let Ok(byte) = self.read_byte() else { break };
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape | ListKey::Byte(b'q' | b'Q') => break,
+                ListKey::Byte(b' ') => list.page(true),
+                _ => {}
+            }
+        }
+
+        let _ = Terminal::show_cursor();
+    }
+
+    /// Full-screen list to choose from, starting at `selected`. Returns the
+    /// chosen index, or `None` if cancelled with Escape.
+    fn pick(&mut self, title: &str, items: &[String], selected: usize) -> Option<usize> {
+        if items.is_empty() {
+            return None;
+        }
+        let mut list = ListNav::new(self.screen_rows as usize);
+        list.set_len(items.len());
+        list.select(selected);
+
+        let choice = loop {
+            self.draw_list(items, list.selected(), list.top());
+
+            let status = format!(" {} - {} of {}", title, list.selected() + 1, items.len());
+            self.draw_overlay_bars(&status, "Up/Down to choose, Enter to select, Esc to cancel");
+
+            let Ok(byte) = self.read_byte() else { break None };
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape => break None,
+                ListKey::Enter => break Some(list.selected()),
+                _ => {}
+            }
+        };
+
+        let _ = Terminal::show_cursor();
+        choice
+    }
+
+    /// Fill the screen above the bars with `items` from `top` on, with the
+    /// `selected` one highlighted.
+    pub fn draw_list(&self, items: &[String], selected: usize, top: usize) {
+        self.draw_marked_list(items, &[], selected, top);
+    }
+
+    /// Like `draw_list`, also picking out the characters in `marks[i]` (a
+    /// range of character positions) of each item `i` that has one.
+    pub fn draw_marked_list(&self, items: &[String], marks: &[(usize, usize)], selected: usize, top: usize) {
+        let rows = (self.screen_rows as usize).max(1);
+        let width = self.screen_cols as usize;
+
+        let _ = Terminal::hide_cursor();
+        let _ = Terminal::move_cursor(0, 0);
+        for row in 0..rows {
+            let mut base = String::new();
+            let bg = if top + row == selected { Some(self.theme.selection_bg) } else { self.theme.text_bg };
+            if let Some(bg) = bg {
+                base.push_str(&bg.bg());
+            }
+            if let Some(fg) = self.theme.text_fg {
+                base.push_str(&fg.fg());
+            }
+
+            let mut out = format!("{}{}{}", RESET_COLORS, base, CLEAR_LINE);
+            if let Some(item) = items.get(top + row) {
+                let (from, to) = marks.get(top + row).copied().unwrap_or((0, 0));
+                for (i, ch) in item.chars().take(width).enumerate() {
+                    if i == from && from < to {
+                        out.push_str(&self.theme.search_match_bg.bg());
+                        out.push_str(&self.theme.search_match_fg.fg());
+                    } else if i == to && from < to {
+                        out.push_str(RESET_COLORS);
+                        out.push_str(&base);
+                    }
+                    out.push(ch);
+                }
+            }
+            out.push_str(RESET_COLORS);
+            print!("{}\r\n", out);
+        }
+    }
+
+    /// Status and message bars under a full-screen view.
+    pub fn draw_overlay_bars(&self, status: &str, hint: &str) {
+        let cols = self.screen_cols as usize;
+        let status = width::truncate(status, cols);
+        let bar = format!("{}{}", status, " ".repeat(cols - width::text_width(status)));
+        print!("{}{}{}{}\r\n", self.theme.status_bar_bg.bg(), self.theme.status_bar_fg.fg(), bar, RESET_COLORS);
+        self.set_message_bar_colors();
+        print!("{}{}", CLEAR_LINE, width::truncate(hint, cols));
+        print!("{}", RESET_COLORS);
+        let _ = io::stdout().flush();
+    }
+
+    /// Show `question` in the message bar and return the (lowercased) key
+    /// pressed in answer, or `None` for Escape. Arrows and other keys sent
+    /// as escape sequences are ignored.
+    pub fn ask(&mut self, question: &str) -> Option<u8> {
+        Terminal::move_cursor(self.screen_rows + 1, 0).ok()?;
+        self.set_message_bar_colors();
+        Terminal::clear_line().ok()?;
+        print!("{}", question);
+        io::stdout().flush().ok()?;
+
+        let answer = loop {
+            match self.read_byte().ok().map(|byte| self.list_key(byte)) {
+                Some(ListKey::Byte(byte)) => break Some(byte.to_ascii_lowercase()),
+                Some(ListKey::Enter) => break Some(b'\r'),
+                Some(ListKey::Sequence(..)) => continue,
+                Some(ListKey::Escape) | None => break None,
+            }
+        };
+
+        Terminal::reset_colors().ok();
+        Terminal::move_cursor(self.screen_rows + 1, 0).ok();
+        Terminal::clear_line().ok();
+        answer
+    }
+
+    /// Ask where to save and check the answer against the save policy.
+    /// Overwriting another file and creating missing directories need a
+    /// yes first. Returns the path to save to, or `None` with the reason in
+    /// the message bar.
+    fn prompt_for_filename(&mut self, given: Option<&str>) -> Option<PathBuf> {
+        let prompt = Prompt::new(PromptKind::File, "Save as: ")
+            .complete_paths(&self.save_base())
+            .validate(|editor: &Editor, filename: &str| {
+                let target = editor.buffer.resolve_save_path(PathBuf::from(filename));
+                editor.save_policy.check(&target).map(|_| ()).map_err(|e| e.to_string())
+            });
+        let filename = self.answer(given, prompt, "Save aborted")?;
+        let target = self.buffer.resolve_save_path(PathBuf::from(&filename));
+        let target = self.check_save_path(&target)?;
+
+        let is_current_file = self.buffer.filename().is_some_and(|f| fs::canonicalize(f).is_ok_and(|f| f == target));
+        let question = if self.buffer.is_unreadable_path(&target) {
+            // Writing over a file we couldn't read needs an explicit yes
+            Some(format!("{} could not be read. Overwrite it anyway? (y/n) ", target.display()))
+        } else if target.is_file() && !is_current_file {
+            Some(format!("{} already exists. Overwrite it? (y/n) ", target.display()))
+        } else {
+            None
+        };
+        if let Some(question) = question {
+            if self.ask(&question) != Some(b'y') {
+                self.show_message(String::from("Save aborted"));
+                return None;
+            }
+            self.buffer.allow_overwriting_unreadable();
+        }
+
+        if let Some(parent) = target.parent()
+            && !parent.is_dir()
+        {
+            let question = format!("{} does not exist. Create it? (y/n) ", parent.display());
+            if self.ask(&question) != Some(b'y') {
+                self.show_message(String::from("Save aborted"));
+                return None;
+            }
+            if let Err(e) = fs::create_dir_all(parent) {
+                self.show_message(format!("Could not create {}: {}", parent.display(), e));
                 return None;
             }
+        }
+
+        Some(target)
+    }
+
+    /// Directory save paths are typed relative to: where a new file would
+    /// be saved.
+    fn save_base(&self) -> PathBuf {
+        let base = self.buffer.resolve_save_path(PathBuf::new());
+        if base.as_os_str().is_empty() { PathBuf::from(".") } else { base }
+    }
 
-            let byte = buffer[0];
+    /// Where saving to `path` would really write, if the save policy
+    /// allows it; otherwise explains the refusal in the message bar.
+    fn check_save_path(&mut self, path: &Path) -> Option<PathBuf> {
+        match self.save_policy.check(path) {
+            Ok(target) => Some(target),
+            Err(e) => {
+                self.show_message(format!("Not saved: {}", e));
+                None
+            },
+        }
+    }
+
+    pub fn prompt(&mut self, kind: PromptKind, label: &str) -> Option<String> {
+        self.run_prompt(Prompt::new(kind, label))
+    }
+
+    /// `given` if a command supplied it, otherwise the answer to `prompt`.
+    /// Either way the prompt's validation applies; a refusal, or
+    /// `cancelled` if the prompt is cancelled, goes in the message bar.
+    pub fn answer(&mut self, given: Option<&str>, prompt: Prompt<'_, Editor>, cancelled: &str) -> Option<String> {
+        let answer = match given {
+            Some(text) => match prompt.check(self, text) {
+                Ok(()) => return Some(text.to_string()),
+                Err(reason) => reason,
+            },
+            None => match self.run_prompt(prompt) {
+                Some(text) => return Some(text),
+                None => cancelled.to_string(),
+            },
+        };
+        self.show_message(answer);
+        None
+    }
+
+    /// Ask `prompt`'s question in the message bar and read the answer.
+    /// Left/Right, Ctrl-Left/Right (by word), Home and End move along the
+    /// line; Backspace, Delete, Ctrl-W (the word before the cursor), Ctrl-U
+    /// (to the start) and Ctrl-K (to the end) delete. Up and Down go
+    /// through earlier answers to the same kind of prompt.
+    ///
+    /// When the prompt completes (paths, command names and so on), Tab
+    /// completes what is typed so far; if several candidates fit they are
+    /// listed above the prompt, Tab or Up/Down pick one, Enter keeps it and
+    /// Escape goes back to what was typed.
+    pub fn run_prompt(&mut self, mut prompt: Prompt<'_, Editor>) -> Option<String> {
+        const CTRL_K: u8 = b'k' & 0x1f;
+        const CTRL_U: u8 = b'u' & 0x1f;
+        const CTRL_W: u8 = b'w' & 0x1f;
+
+        let mut input = LineInput::default();
+        input.set(prompt.initial_text());
+        let mut completion = Completion::default();
+        let mut walk = HistoryWalk::new(self.history.answers(prompt.kind()));
+        // Why the last answer was refused, until the next key
+        let mut error: Option<String> = None;
+
+        let answer = loop {
+            if completion.is_open() {
+                let column = prompt.label().chars().count() + completion.name_column();
+                self.draw_completions(completion.candidates(), completion.picked(), column);
+            }
+            self.draw_prompt(prompt.label(), &input, error.as_deref());
 
+            let Ok(byte) = self.read_byte() else { break None };
+            let was_open = completion.is_open();
+            let before = input.text().to_string();
+            error = None;
             match byte {
-                13 | 10 => { // Enter - confirm
-                    if !filename.is_empty() {
-                        // Clean up the prompt line before returning
-                        Terminal::move_cursor(self.screen_rows + 1, 0).ok();
-                        Terminal::clear_line().ok();
-                        return Some(filename);
+                13 | 10 if completion.picked().is_some() => completion.close(&mut input, false),
+                13 | 10 if prompt.allows_empty() || !input.text().is_empty() => {
+                    match prompt.check(self, input.text()) {
+                        Ok(()) => break Some(input.text().to_string()),
+                        Err(reason) => error = Some(reason),
+                    }
+                },
+                9 if completion.is_open() => completion.step(&mut input, 1),
+                9 => {
+                    if let Some((start, found)) = prompt.completions(self, input.text()) {
+                        completion.offer(&mut input, start, found);
                     }
                 },
-                27 => { // Escape - cancel
-                    // Clean up before returning
-                    Terminal::move_cursor(self.screen_rows + 1, 0).ok();
-                    Terminal::clear_line().ok();
-                    return None;
+                127 | 8 => {
+                    completion.close(&mut input, false);
+                    input.backspace();
                 },
-                127 | 8 => { // Backspace
-                    filename.pop();
+                CTRL_W | CTRL_U | CTRL_K => {
+                    completion.close(&mut input, false);
+                    match byte {
+                        CTRL_W => input.delete_word_before(),
+                        CTRL_U => input.delete_to_start(),
+                        _ => input.delete_to_end(),
+                    }
                 },
-                byte if byte >= 32 && byte < 127 => { // Printable ASCII
-                    filename.push(byte as char);
+                0x1b => match self.read_escape_sequence() {
+                    None if completion.is_open() => completion.close(&mut input, true),
+                    None => break None,
+                    Some((params, final_byte)) => match (params.as_str(), final_byte) {
+                        ("", b'A') if completion.is_open() => completion.step(&mut input, -1),
+                        ("", b'B') if completion.is_open() => completion.step(&mut input, 1),
+                        ("", b'A') => walk.step(&mut input, -1),
+                        ("", b'B') => walk.step(&mut input, 1),
+                        (params, final_byte) => {
+                            completion.close(&mut input, false);
+                            match (params, final_byte) {
+                                ("", b'C') => input.move_right(),
+                                ("", b'D') => input.move_left(),
+                                ("1;5", b'C') => input.move_word_right(),
+                                ("1;5", b'D') => input.move_word_left(),
+                                ("", b'H') => input.move_home(),
+                                ("", b'F') => input.move_end(),
+                                ("3", b'~') => input.delete(),
+                                _ => {}
+                            }
+                        },
+                    },
                 },
-                _ => {}
+                byte => {
+                    if let Some(ch) = self.read_char(byte) {
+                        completion.close(&mut input, false);
+                        input.insert(ch);
+                    }
+                },
+            }
+            // Repaint what the list covered
+            if was_open && !completion.is_open() {
+                let _ = self.refresh_screen();
+            }
+            if input.text() != before {
+                prompt.changed(self, input.text());
             }
+        };
+
+        if let Some(answer) = &answer {
+            self.history.add(prompt.kind(), answer);
+        }
+        if completion.is_open() {
+            let _ = self.refresh_screen();
+        }
+        // Clean up the prompt line before returning
+        Terminal::reset_colors().ok();
+        Terminal::move_cursor(self.screen_rows + 1, 0).ok();
+        Terminal::clear_line().ok();
+        answer
+    }
+
+    /// The printable character whose UTF-8 encoding starts with `first`,
+    /// reading the rest of it.
+    fn read_char(&self, first: u8) -> Option<char> {
+        let len = match first {
+            0x20..=0x7e => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return None,
+        };
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            bytes.push(self.read_byte().ok()?);
+        }
+        std::str::from_utf8(&bytes).ok()?.chars().next()
+    }
+
+    /// Show `label` and the line being typed in the message bar, scrolled
+    /// sideways if need be to keep the cursor in view, followed by `error`
+    /// if there is one.
+    fn draw_prompt(&self, label: &str, input: &LineInput, error: Option<&str>) {
+        let width = (self.screen_cols as usize).max(1);
+        let column = label.chars().count() + input.cursor_column();
+        let skip = (column + 1).saturating_sub(width);
+        let error = error.map(|e| format!("  [{}]", e)).unwrap_or_default();
+        let line: String = label.chars()
+            .chain(input.text().chars())
+            .chain(error.chars())
+            .skip(skip)
+            .take(width)
+            .collect();
+
+        let _ = Terminal::hide_cursor();
+        let _ = Terminal::move_cursor(self.screen_rows + 1, 0);
+        self.set_message_bar_colors();
+        let _ = Terminal::clear_line();
+        print!("{}", line);
+        let _ = Terminal::move_cursor(self.screen_rows + 1, (column - skip) as u16);
+        let _ = Terminal::show_cursor();
+        let _ = io::stdout().flush();
+    }
+
+    /// List path completions in a box just above the status bar, lined up
+    /// with screen column `column`.
+    fn draw_completions(&self, candidates: &[String], picked: Option<usize>, column: usize) {
+        let rows = candidates.len().min(MAX_COMPLETION_ROWS).min(self.screen_rows as usize);
+        let top = list::keep_in_view(picked.unwrap_or(0), 0, rows);
+        let screen_width = self.screen_cols as usize;
+        let width = (candidates.iter().map(|c| c.chars().count()).max().unwrap_or(0) + 2).min(screen_width);
+        if rows == 0 || width < 2 {
+            return;
         }
+        let column = column.min(screen_width - width);
+        let first_row = self.screen_rows as usize - rows;
+
+        let mut out = String::new();
+        for (i, candidate) in candidates.iter().enumerate().skip(top).take(rows) {
+            let bg = if picked == Some(i) { self.theme.selection_bg } else { self.theme.status_bar_bg };
+            let text: String = format!(" {:<w$}", candidate, w = width - 1).chars().take(width).collect();
+            out.push_str(&Terminal::move_cursor_code(first_row + i - top, column));
+            out.push_str(&format!("{}{}{}{}", bg.bg(), self.theme.status_bar_fg.fg(), text, RESET_COLORS));
+        }
+        print!("{}", out);
     }
 }
 
+/// Those of `candidates` that start with `typed`.
+fn starting_with(candidates: impl IntoIterator<Item = String>, typed: &str) -> Vec<String> {
+    candidates.into_iter().filter(|candidate| candidate.starts_with(typed)).collect()
+}
@@ -0,0 +1,307 @@
+use crate::hex;
+use std::io;
+
+/// Character encodings a file can be read from and written back in.
+/// Text is always held as UTF-8 inside the buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16LeBom,
+    Utf16Be,
+    Utf16BeBom,
+    Windows1252,
+    Latin1,
+}
+
+/// Windows-1252 characters for bytes 0x80..=0x9F. The five bytes it leaves
+/// undefined map to the matching C1 controls, as in Latin-1, so decoding
+/// and re-encoding never loses a byte.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16_BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+impl Encoding {
+    pub const ALL: [Encoding; 8] = [
+        Encoding::Utf8,
+        Encoding::Utf8Bom,
+        Encoding::Utf16Le,
+        Encoding::Utf16LeBom,
+        Encoding::Utf16Be,
+        Encoding::Utf16BeBom,
+        Encoding::Windows1252,
+        Encoding::Latin1,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le => "UTF-16 LE",
+            Encoding::Utf16LeBom => "UTF-16 LE BOM",
+            Encoding::Utf16Be => "UTF-16 BE",
+            Encoding::Utf16BeBom => "UTF-16 BE BOM",
+            Encoding::Windows1252 => "Windows-1252",
+            Encoding::Latin1 => "ISO-8859-1",
+        }
+    }
+
+    /// Parse a user-typed encoding name, ignoring case, spaces, `-` and `_`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let key: String = name
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        match key.as_str() {
+            "utf8" => Some(Encoding::Utf8),
+            "utf8bom" => Some(Encoding::Utf8Bom),
+            "utf16le" => Some(Encoding::Utf16Le),
+            "utf16lebom" | "utf16" => Some(Encoding::Utf16LeBom),
+            "utf16be" => Some(Encoding::Utf16Be),
+            "utf16bebom" => Some(Encoding::Utf16BeBom),
+            "windows1252" | "cp1252" | "ansi" => Some(Encoding::Windows1252),
+            "iso88591" | "latin1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Whether lines can be found by splitting the raw bytes on `\n`, which
+    /// is what the background loader relies on.
+    pub fn is_byte_oriented(self) -> bool {
+        !matches!(self, Encoding::Utf16Le | Encoding::Utf16LeBom | Encoding::Utf16Be | Encoding::Utf16BeBom)
+    }
+
+    /// The byte order mark written at the start of the file, if any.
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16LeBom => UTF16_LE_BOM,
+            Encoding::Utf16BeBom => UTF16_BE_BOM,
+            _ => &[],
+        }
+    }
+
+    /// Guess the encoding from the first bytes of a file. `None` means the
+    /// file looks binary.
+    pub fn detect(sample: &[u8]) -> Option<Self> {
+        if sample.starts_with(UTF8_BOM) {
+            return Some(Encoding::Utf8Bom);
+        }
+        if sample.starts_with(UTF16_LE_BOM) {
+            return Some(Encoding::Utf16LeBom);
+        }
+        if sample.starts_with(UTF16_BE_BOM) {
+            return Some(Encoding::Utf16BeBom);
+        }
+        if hex::looks_binary(sample) {
+            return None;
+        }
+
+        match std::str::from_utf8(sample) {
+            Ok(_) => Some(Encoding::Utf8),
+            // A character cut off by the end of the sample is still UTF-8
+            Err(e) if e.error_len().is_none() => Some(Encoding::Utf8),
+            Err(_) => Some(Encoding::single_byte(sample)),
+        }
+    }
+
+    /// Windows-1252 and Latin-1 differ only in bytes 0x80..=0x9F: printable
+    /// in Windows-1252, control characters in Latin-1. Text using any of
+    /// them is taken as Windows-1252.
+    fn single_byte(bytes: &[u8]) -> Encoding {
+        if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) {
+            Encoding::Windows1252
+        } else {
+            Encoding::Latin1
+        }
+    }
+
+    /// What the background loader reads a file as instead when `detect`
+    /// guessed `self` from its start but the rest isn't valid: invalid
+    /// UTF-8 further on makes it Windows-1252, which can read any byte.
+    pub fn fallback(self) -> Option<Encoding> {
+        (self == Encoding::Utf8).then_some(Encoding::Windows1252)
+    }
+
+    /// Decode a whole file whose encoding `detect` guessed as `self` from
+    /// its start, settling on another if the rest of the file shows the
+    /// guess was wrong.
+    pub fn decode_detected(self, bytes: &[u8]) -> io::Result<(String, Encoding)> {
+        let encoding = match self {
+            Encoding::Utf8 if std::str::from_utf8(bytes).is_err() => Encoding::single_byte(bytes),
+            Encoding::Latin1 => Encoding::single_byte(bytes),
+            _ => self,
+        };
+        Ok((encoding.decode(bytes)?, encoding))
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> io::Result<String> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("not valid {}: {}", self.name(), e))
+        };
+
+        match self {
+            // Without a BOM of its own, a BOM in the file is kept as U+FEFF
+            // and written back
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).map_err(|e| invalid(&e))
+            },
+            Encoding::Utf16Le | Encoding::Utf16LeBom | Encoding::Utf16Be | Encoding::Utf16BeBom => {
+                let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+                if !bytes.len().is_multiple_of(2) {
+                    return Err(invalid(&"odd number of bytes"));
+                }
+
+                let little_endian = matches!(self, Encoding::Utf16Le | Encoding::Utf16LeBom);
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|pair| if little_endian {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    })
+                    .collect();
+                String::from_utf16(&units).map_err(|e| invalid(&e))
+            },
+            Encoding::Windows1252 => Ok(bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect()),
+            Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        }
+    }
+
+    /// Encode `text` for writing. Fails on the first character the encoding
+    /// can't represent rather than silently replacing it.
+    pub fn encode(self, text: &str) -> io::Result<Vec<u8>> {
+        let unrepresentable = |c: char| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' (U+{:04X}) can't be saved as {}", c, c as u32, self.name()),
+            )
+        };
+
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => Ok([self.bom(), text.as_bytes()].concat()),
+            Encoding::Utf16Le | Encoding::Utf16LeBom => Ok(self.bom().iter().copied()
+                .chain(text.encode_utf16().flat_map(|u| u.to_le_bytes()))
+                .collect()),
+            Encoding::Utf16Be | Encoding::Utf16BeBom => Ok(self.bom().iter().copied()
+                .chain(text.encode_utf16().flat_map(|u| u.to_be_bytes()))
+                .collect()),
+            Encoding::Windows1252 => text
+                .chars()
+                .map(|c| match c as u32 {
+                    0..=0x7F | 0xA0..=0xFF => Ok(c as u8),
+                    _ => WINDOWS_1252_HIGH
+                        .iter()
+                        .position(|&h| h == c)
+                        .map(|i| 0x80 + i as u8)
+                        .ok_or_else(|| unrepresentable(c)),
+                })
+                .collect(),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c as u32).map_err(|_| unrepresentable(c)))
+                .collect(),
+        }
+    }
+}
+
+/// Detect the encoding of a whole file's `bytes` and decode them, as
+/// buffers are read. `None` means the file looks binary.
+pub fn decode_file(bytes: &[u8]) -> Option<io::Result<(String, Encoding)>> {
+    let sample = &bytes[..bytes.len().min(hex::SAMPLE_SIZE)];
+    Encoding::detect(sample).map(|encoding| encoding.decode_detected(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "caf\u{e9} \u{20ac}5\nline two";
+
+    #[test]
+    fn every_encoding_round_trips() {
+        for encoding in Encoding::ALL {
+            let text = match encoding {
+                // No euro sign in Latin-1
+                Encoding::Latin1 => "caf\u{e9} 5\nline two",
+                _ => TEXT,
+            };
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(encoding.decode(&bytes).unwrap(), text, "{}", encoding.name());
+        }
+    }
+
+    #[test]
+    fn detects_byte_order_marks() {
+        for encoding in [Encoding::Utf8Bom, Encoding::Utf16LeBom, Encoding::Utf16BeBom] {
+            let bytes = encoding.encode(TEXT).unwrap();
+            assert_eq!(decode_file(&bytes).unwrap().unwrap(), (TEXT.to_string(), encoding));
+        }
+    }
+
+    #[test]
+    fn keeps_utf16_without_a_bom_that_way() {
+        let bytes = Encoding::Utf16Le.encode("hi").unwrap();
+        assert_eq!(bytes, b"h\0i\0");
+        let text = Encoding::Utf16Le.decode(&bytes).unwrap();
+        assert_eq!(Encoding::Utf16Le.encode(&text).unwrap(), bytes);
+        assert_eq!(Encoding::Utf16BeBom.encode("hi").unwrap(), b"\xFE\xFF\0h\0i");
+    }
+
+    #[test]
+    fn tells_utf8_windows_1252_and_latin1_apart() {
+        assert_eq!(decode_file("caf\u{e9}".as_bytes()).unwrap().unwrap().1, Encoding::Utf8);
+        assert_eq!(decode_file(b"caf\xe9").unwrap().unwrap(), (String::from("caf\u{e9}"), Encoding::Latin1));
+        assert_eq!(decode_file(b"\x93quoted\x94").unwrap().unwrap(), (String::from("\u{201c}quoted\u{201d}"), Encoding::Windows1252));
+        assert!(decode_file(b"\0\x01\x02\x03binary\0\0").is_none());
+    }
+
+    #[test]
+    fn settles_on_the_whole_file_not_just_its_start() {
+        let mut bytes = "caf\u{e9} ".repeat(2000).into_bytes();
+        bytes.extend_from_slice(b"\x80");
+        assert_eq!(Encoding::detect(&bytes[..hex::SAMPLE_SIZE]), Some(Encoding::Utf8));
+        assert_eq!(decode_file(&bytes).unwrap().unwrap().1, Encoding::Windows1252);
+
+        let mut bytes = b"caf\xe9 ".repeat(2000);
+        bytes.extend_from_slice(b"\x80");
+        assert_eq!(Encoding::detect(&bytes[..hex::SAMPLE_SIZE]), Some(Encoding::Latin1));
+        assert_eq!(decode_file(&bytes).unwrap().unwrap().1, Encoding::Windows1252);
+    }
+
+    #[test]
+    fn refuses_what_cant_be_represented() {
+        assert!(Encoding::Latin1.encode("\u{20ac}").is_err());
+        assert!(Encoding::Windows1252.encode("\u{3042}").is_err());
+        assert!(Encoding::Utf16Le.decode(b"abc").is_err());
+        assert!(Encoding::Utf8.decode(b"\xff").is_err());
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(Encoding::from_name("UTF-16 le"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::from_name("utf_16_be_bom"), Some(Encoding::Utf16BeBom));
+        assert_eq!(Encoding::from_name("Latin1"), Some(Encoding::Latin1));
+        for encoding in Encoding::ALL {
+            assert_eq!(Encoding::from_name(encoding.name()), Some(encoding));
+        }
+        assert_eq!(Encoding::from_name("ebcdic"), None);
+    }
+}
@@ -0,0 +1,612 @@
+//! Quick open: an index of the files under a directory, fuzzy ranking of
+//! their paths against what has been typed so far, and the overlay that
+//! lists them beside a preview.
+
+use crate::editor::{Editor, PaneLine};
+use crate::hex;
+use crate::list::{ListKey, ListNav};
+use crate::terminal::{RESET_COLORS, Terminal};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Indexing stops after this many files so a huge tree can't stall the
+/// editor.
+pub const MAX_FILES: usize = 50_000;
+
+/// One `.gitignore` line.
+struct IgnoreRule {
+    /// Directory of the `.gitignore`, relative to the index root ("" for
+    /// the root itself).
+    base: String,
+    pattern: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns containing a slash match the whole path below `base`;
+    /// others match the file name at any depth.
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str, base: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            base: base.to_string(),
+            pattern: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let below = if self.base.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(&self.base).and_then(|p| p.strip_prefix('/')) {
+                Some(p) => p,
+                None => return false,
+            }
+        };
+        let subject = if self.anchored { below } else { below.rsplit('/').next().unwrap_or(below) };
+        glob_match(&self.pattern, &subject.chars().collect::<Vec<_>>())
+    }
+}
+
+/// Match `text` against a gitignore-style glob: `*` and `?` stay within
+/// one path component, `**` crosses them, `[...]` is a character class.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => match rest {
+            // `**/` matches zero or more whole directories
+            ['/', rest @ ..] => {
+                glob_match(rest, text)
+                    || (0..text.len()).any(|i| text[i] == '/' && glob_match(rest, &text[i + 1..]))
+            },
+            _ => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        },
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => text.first().is_some_and(|&c| c != '/') && glob_match(rest, &text[1..]),
+        ['[', rest @ ..] if rest.contains(&']') => {
+            let Some(&c) = text.first() else { return false };
+            let (negated, class) = match rest {
+                ['!' | '^', class @ ..] => (true, class),
+                _ => (false, rest),
+            };
+            // A `]` right at the start is part of the class
+            let end = 1 + class[1..].iter().position(|&c| c == ']').unwrap_or(class.len() - 1);
+            let Some(set) = class.get(..end) else { return false };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && c != '/' && glob_match(&class[(end + 1).min(class.len())..], &text[1..])
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// Files under a directory, as `/`-separated paths relative to it.
+/// Directories named `.git` and anything matched by a `.gitignore` are left
+/// out, and symlinked directories aren't followed.
+pub struct FileIndex {
+    root: PathBuf,
+    files: Vec<String>,
+    truncated: bool,
+}
+
+impl FileIndex {
+    pub fn build(root: &Path) -> Self {
+        let mut index = FileIndex {
+            root: root.to_path_buf(),
+            files: Vec::new(),
+            truncated: false,
+        };
+        index.walk(root, "", &mut Vec::new());
+        index
+    }
+
+    fn walk(&mut self, dir: &Path, rel: &str, rules: &mut Vec<IgnoreRule>) {
+        let inherited = rules.len();
+        if let Ok(text) = fs::read_to_string(dir.join(".gitignore")) {
+            rules.extend(text.lines().filter_map(|line| IgnoreRule::parse(line, rel)));
+        }
+
+        let mut children: Vec<(String, bool)> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_type = entry.file_type().ok()?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Links count as files only when they point at one
+                let is_dir = file_type.is_dir();
+                if file_type.is_symlink() && !entry.path().is_file() {
+                    return None;
+                }
+                Some((name, is_dir))
+            })
+            .filter(|(name, _)| name != ".git")
+            .collect();
+        children.sort();
+
+        for (name, is_dir) in children {
+            if self.truncated {
+                break;
+            }
+            let path = if rel.is_empty() { name.clone() } else { format!("{}/{}", rel, name) };
+            // The last rule that matches decides
+            let ignored = rules.iter().rev().find(|r| r.matches(&path, is_dir)).is_some_and(|r| !r.negated);
+            if ignored {
+                continue;
+            }
+
+            if is_dir {
+                self.walk(&dir.join(&name), &path, rules);
+            } else if self.files.len() < MAX_FILES {
+                self.files.push(path);
+            } else {
+                self.truncated = true;
+            }
+        }
+
+        rules.truncate(inherited);
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Whether indexing stopped at `MAX_FILES`.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The best `limit` files for `query`, best first. An empty query lists
+    /// files in index order.
+    pub fn rank(&self, query: &str, limit: usize) -> Vec<FileMatch> {
+        let mut matches: Vec<FileMatch> = self.files.iter()
+            .enumerate()
+            .filter_map(|(index, path)| {
+                let (score, positions) = fuzzy_score(query, path)?;
+                Some(FileMatch { index, score, positions })
+            })
+            .collect();
+        // With nothing typed every file ties; they stay in index order
+        if query.chars().any(|c| !c.is_whitespace()) {
+            matches.sort_by(|a, b| {
+                b.score.cmp(&a.score)
+                    .then_with(|| self.files[a.index].len().cmp(&self.files[b.index].len()))
+                    .then_with(|| a.index.cmp(&b.index))
+            });
+        }
+        matches.truncate(limit);
+        matches
+    }
+}
+
+pub struct FileMatch {
+    /// Position in `FileIndex::files`.
+    pub index: usize,
+    pub score: i64,
+    /// Character positions in the path matched by the query.
+    pub positions: Vec<usize>,
+}
+
+/// Score how well `query` matches `candidate` as a case-insensitive
+/// subsequence, or `None` if it doesn't. Matches at the start of words and
+/// path components, runs of consecutive characters and matches within the
+/// file name score higher; gaps score lower. Also returns the matched
+/// character positions. Whitespace in the query is ignored.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect();
+    let chars: Vec<char> = candidate.chars().collect();
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    let whole = match_in(&query, &chars, 0);
+    // Prefer the file name when the whole query fits in it
+    let name_start = chars.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+    let in_name = match_in(&query, &chars, name_start).map(|(score, positions)| (score + NAME_BONUS, positions));
+
+    match (whole, in_name) {
+        (Some(a), Some(b)) => Some(if b.0 >= a.0 { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+const MATCH_SCORE: i64 = 16;
+const BOUNDARY_BONUS: i64 = 12;
+const CAMEL_BONUS: i64 = 10;
+const CONSECUTIVE_BONUS: i64 = 8;
+const GAP_PENALTY: i64 = 3;
+const MAX_GAP_PENALTY: i64 = 15;
+const NAME_BONUS: i64 = 24;
+
+/// Match `query` against `chars[from..]`: find the shortest window that
+/// ends at the earliest possible place, then score the match within it.
+fn match_in(query: &[char], chars: &[char], from: usize) -> Option<(i64, Vec<usize>)> {
+    let lower = |i: usize| chars[i].to_lowercase().next().unwrap_or(chars[i]);
+
+    // Earliest end of a full match
+    let mut q = 0;
+    let mut end = None;
+    for i in from..chars.len() {
+        if lower(i) == query[q] {
+            q += 1;
+            if q == query.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    // Latest start that still matches up to `end`
+    let mut q = query.len();
+    let mut start = end;
+    for i in (from..=end).rev() {
+        if lower(i) == query[q - 1] {
+            q -= 1;
+            if q == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut positions = Vec::with_capacity(query.len());
+    let mut q = 0;
+    for i in start..=end {
+        if q < query.len() && lower(i) == query[q] {
+            positions.push(i);
+            q += 1;
+        }
+    }
+
+    let mut score = 0;
+    for (k, &i) in positions.iter().enumerate() {
+        score += MATCH_SCORE;
+        let previous = i.checked_sub(1).map(|p| chars[p]);
+        match previous {
+            None | Some('/' | '\\' | '_' | '-' | '.' | ' ') => score += BOUNDARY_BONUS,
+            Some(p) if p.is_lowercase() && chars[i].is_uppercase() => score += CAMEL_BONUS,
+            _ => {},
+        }
+        if k > 0 {
+            let gap = (i - positions[k - 1] - 1) as i64;
+            if gap == 0 {
+                score += CONSECUTIVE_BONUS;
+            } else {
+                score -= (GAP_PENALTY + gap).min(MAX_GAP_PENALTY);
+            }
+        }
+    }
+    Some((score, positions))
+}
+
+/// Most files the finder lists for a query.
+const MAX_FINDER_RESULTS: usize = 1000;
+
+/// How much of a file the finder reads for its preview.
+const PREVIEW_BYTES: usize = 64 * 1024;
+
+impl Editor {
+    /// Quick open: type part of a path to narrow down the files under the
+    /// project root, with a preview of the selected one.
+    pub fn find_file(&mut self) {
+        const CTRL_N: u8 = b'n' & 0x1f;
+        const CTRL_P: u8 = b'p' & 0x1f;
+
+        let Some(root) = self.project_root() else {
+            self.show_message(String::from("No directory to search"));
+            return;
+        };
+        self.status_message = format!("Indexing {}...", root.display());
+        let _ = self.refresh_screen();
+        let index = FileIndex::build(&root);
+
+        let rows = (self.screen_rows as usize).max(1);
+        let width = self.screen_cols as usize;
+        // The preview only gets a column when there is room for it
+        let list_width = if width >= 60 { width * 2 / 5 } else { width };
+        let mut query = String::new();
+        let mut matches = index.rank(&query, MAX_FINDER_RESULTS);
+        let mut list = ListNav::new(rows);
+        let mut preview: Option<(usize, Vec<String>)> = None;
+
+        loop {
+            list.set_len(matches.len());
+            if list_width < width
+                && let Some(m) = matches.get(list.selected())
+                && preview.as_ref().is_none_or(|(shown, _)| *shown != m.index)
+            {
+                let path = index.root().join(&index.files()[m.index]);
+                preview = Some((m.index, preview_lines(&path, rows)));
+            }
+            let preview_text = matches.get(list.selected())
+                .and_then(|m| preview.as_ref().filter(|(shown, _)| *shown == m.index))
+                .map_or(&[][..], |(_, lines)| &lines[..]);
+            self.draw_finder(&index, &matches, list.selected(), list.top(), list_width, preview_text);
+
+            let truncated = if index.is_truncated() { " (index truncated)" } else { "" };
+            let status = format!(" Find file - {} of {} files{}", matches.len(), index.files().len(), truncated);
+            let label = "Find file: ";
+            let _ = Terminal::move_cursor(self.screen_rows, 0);
+            self.draw_overlay_bars(&status, &format!("{}{}", label, query));
+            let _ = Terminal::move_cursor(self.screen_rows + 1, (label.len() + query.chars().count()) as u16);
+            let _ = Terminal::show_cursor();
+            let _ = io::stdout().flush();
+
+            let Ok(byte) = self.read_byte() else { break };
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape => break,
+                ListKey::Enter => {
+                    let Some(m) = matches.get(list.selected()) else { continue };
+                    let path = index.root().join(&index.files()[m.index]);
+                    if let Err(e) = self.open_file(&path.to_string_lossy()) {
+                        self.show_message(format!("Could not open {}: {}", path.display(), e));
+                    }
+                    break;
+                },
+                ListKey::Byte(CTRL_N) => list.step(1),
+                ListKey::Byte(CTRL_P) => list.step(-1),
+                ListKey::Byte(127 | 8) => {
+                    query.pop();
+                    matches = index.rank(&query, MAX_FINDER_RESULTS);
+                    list.select(0);
+                },
+                ListKey::Byte(byte) if (32..127).contains(&byte) => {
+                    query.push(byte as char);
+                    matches = index.rank(&query, MAX_FINDER_RESULTS);
+                    list.select(0);
+                },
+                _ => {}
+            }
+        }
+
+        if self.status_message.starts_with("Indexing") {
+            self.status_message.clear();
+        }
+    }
+
+    /// Result list of the file finder with the query's matches picked out,
+    /// and the preview beside it.
+    fn draw_finder(&self, index: &FileIndex, matches: &[FileMatch], selected: usize, top: usize, list_width: usize, preview: &[String]) {
+        let theme = &self.theme;
+        let width = self.screen_cols as usize;
+        let _ = Terminal::hide_cursor();
+        let mut frame = String::new();
+
+        for row in 0..(self.screen_rows as usize).max(1) {
+            frame.push_str(&Terminal::move_cursor_code(row, 0));
+
+            let mut line = PaneLine::new(list_width);
+            let bg = if top + row == selected { Some(theme.selection_bg) } else { theme.text_bg };
+            let base = format!("{}{}", bg.map(|c| c.bg()).unwrap_or_default(), theme.text_fg.map(|c| c.fg()).unwrap_or_default());
+            line.color(&base);
+            if let Some(m) = matches.get(top + row) {
+                line.push(' ');
+                for (i, ch) in index.files()[m.index].chars().enumerate() {
+                    if m.positions.contains(&i) {
+                        line.color(&theme.search_match_bg.bg());
+                        line.color(&theme.search_match_fg.fg());
+                        line.push(ch);
+                        line.color(RESET_COLORS);
+                        line.color(&base);
+                    } else {
+                        line.push(ch);
+                    }
+                }
+            }
+            frame.push_str(&line.finish());
+
+            if list_width < width {
+                frame.push_str(&theme.gutter_fg.fg());
+                frame.push('│');
+                let mut line = PaneLine::new(width - list_width - 1);
+                if let Some(bg) = theme.text_bg {
+                    line.color(&bg.bg());
+                }
+                if let Some(fg) = theme.text_fg {
+                    line.color(&fg.fg());
+                }
+                line.text(preview.get(row).map_or("", String::as_str));
+                frame.push_str(&line.finish());
+            }
+        }
+
+        print!("{}", frame);
+    }
+}
+
+/// First `rows` lines of the file at `path` for the finder's preview.
+fn preview_lines(path: &Path, rows: usize) -> Vec<String> {
+    let mut sample = Vec::new();
+    match fs::File::open(path).and_then(|f| f.take(PREVIEW_BYTES as u64).read_to_end(&mut sample)) {
+        Ok(_) if hex::looks_binary(&sample[..sample.len().min(hex::SAMPLE_SIZE)]) => vec![String::from("(binary file)")],
+        Ok(_) => String::from_utf8_lossy(&sample)
+            .lines()
+            .take(rows)
+            .map(|line| line.replace('\t', "    "))
+            .collect(),
+        Err(e) => vec![format!("(cannot read: {})", e)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        glob_match(&pattern.chars().collect::<Vec<_>>(), &text.chars().collect::<Vec<_>>())
+    }
+
+    fn ignores(line: &str, path: &str, is_dir: bool) -> bool {
+        IgnoreRule::parse(line, "").is_some_and(|rule| rule.matches(path, is_dir) && !rule.negated)
+    }
+
+    fn score(query: &str, candidate: &str) -> i64 {
+        fuzzy_score(query, candidate).unwrap_or_else(|| panic!("{:?} didn't match {:?}", query, candidate)).0
+    }
+
+    /// An empty directory of its own for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text_editor-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("*.rs", "main.rs"));
+        assert!(!glob("*.rs", "src/main.rs"));
+        assert!(glob("src/*.rs", "src/main.rs"));
+        assert!(glob("**/*.rs", "main.rs"));
+        assert!(glob("**/*.rs", "a/b/main.rs"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(glob("a/**/b", "a/x/y/b"));
+        assert!(glob("a/**", "a/x/y"));
+        assert!(glob("?.txt", "a.txt"));
+        assert!(!glob("?.txt", "/.txt"));
+        assert!(glob("[abc].txt", "b.txt"));
+        assert!(!glob("[!abc].txt", "b.txt"));
+        assert!(glob("[a-c]x", "cx"));
+        assert!(glob("[]]", "]"));
+        assert!(glob(r"\*", "*"));
+        assert!(!glob(r"\*", "a"));
+    }
+
+    #[test]
+    fn ignore_rules() {
+        assert!(ignores("target", "target", true));
+        assert!(ignores("target", "a/b/target", false));
+        assert!(ignores("target/", "target", true));
+        assert!(!ignores("target/", "target", false));
+        assert!(ignores("/build", "build", true));
+        assert!(!ignores("/build", "src/build", true));
+        assert!(ignores("docs/*.md", "docs/a.md", false));
+        assert!(!ignores("docs/*.md", "x/docs/a.md", false));
+        assert!(!ignores("!keep.log", "keep.log", false));
+        assert!(IgnoreRule::parse("!keep.log", "").unwrap().negated);
+        assert!(ignores(r"\!important", "!important", false));
+        for line in ["", "   ", "# comment", "/", "!"] {
+            assert!(IgnoreRule::parse(line, "").is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn nested_ignore_rules_apply_below_their_directory() {
+        let rule = IgnoreRule::parse("*.tmp", "sub").unwrap();
+        assert!(rule.matches("sub/a.tmp", false));
+        assert!(rule.matches("sub/deeper/a.tmp", false));
+        assert!(!rule.matches("a.tmp", false));
+        assert!(!rule.matches("subway/a.tmp", false));
+
+        let rule = IgnoreRule::parse("/out", "sub").unwrap();
+        assert!(rule.matches("sub/out", true));
+        assert!(!rule.matches("sub/x/out", true));
+    }
+
+    #[test]
+    fn index_leaves_out_ignored_files() {
+        let root = temp_dir("finder-index");
+        let write = |path: &str, text: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        };
+        write(".gitignore", "*.log\n!keep.log\ntarget/\n");
+        write("a.rs", "");
+        write("debug.log", "");
+        write("keep.log", "");
+        write("target/out.rs", "");
+        write("src/.gitignore", "gen.rs\n");
+        write("src/gen.rs", "");
+        write("src/lib.rs", "");
+        write("gen.rs", "");
+        write(".git/config", "");
+
+        let index = FileIndex::build(&root);
+        assert_eq!(index.files(), [".gitignore", "a.rs", "gen.rs", "keep.log", "src/.gitignore", "src/lib.rs"]);
+        assert!(!index.is_truncated());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn fuzzy_matches_are_case_insensitive_subsequences() {
+        assert!(fuzzy_score("xyz", "src/main.rs").is_none());
+        assert!(fuzzy_score("mainsrc", "src/main.rs").is_none());
+        assert_eq!(fuzzy_score("MAIN", "src/main.rs").unwrap().1, [4, 5, 6, 7]);
+        assert_eq!(fuzzy_score("s m", "src/main.rs").unwrap().1, [0, 4]);
+        assert_eq!(fuzzy_score("", "anything"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn fuzzy_scores_favour_names_boundaries_and_runs() {
+        // Within the file name beats across directories
+        assert!(score("edit", "src/editor.rs") > score("edit", "e/d/i/t.rs"));
+        // Word starts beat the middle of words
+        assert!(score("fb", "file_browser.rs") > score("fb", "offbeat.rs"));
+        assert!(score("fb", "FileBrowser.rs") > score("fb", "offbeat.rs"));
+        // Consecutive beats spread out
+        assert!(score("abc", "abcxxxxx") > score("abc", "axxbxxcx"));
+    }
+
+    #[test]
+    fn fuzzy_match_prefers_the_tightest_window() {
+        assert_eq!(fuzzy_score("ab", "a_x_ab").unwrap().1, [4, 5]);
+    }
+
+    #[test]
+    fn rank_orders_by_score_then_length() {
+        let index = FileIndex {
+            root: PathBuf::new(),
+            files: ["docs/readme.md", "src/reader.rs", "README.md", "zzz"].map(String::from).to_vec(),
+            truncated: false,
+        };
+        let ranked: Vec<&str> = index.rank("read", 10).iter().map(|m| index.files()[m.index].as_str()).collect();
+        assert_eq!(ranked, ["README.md", "src/reader.rs", "docs/readme.md"]);
+        assert_eq!(index.rank("read", 1).len(), 1);
+        assert_eq!(index.rank("", 10).iter().map(|m| m.index).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+}
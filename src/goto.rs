@@ -0,0 +1,179 @@
+//! Where "go to" takes the cursor: `line`, `line:column`, lines relative to
+//! the cursor (`+N`, `-N`) or a percentage of the file (`N%`), and
+//! `file:line:column` locations on the command line.
+
+/// The line part of a go-to target.
+#[derive(Clone, Copy)]
+enum Line {
+    /// Counted from 1, as typed.
+    Absolute(usize),
+    Relative(isize),
+    Percent(usize),
+}
+
+/// A line to go to and, optionally, a column on it.
+#[derive(Clone, Copy)]
+pub struct Goto {
+    line: Line,
+    /// Counted from 1, as typed.
+    column: Option<usize>,
+}
+
+impl Goto {
+    /// Parse `line[:column]`, where the line may also be `+N` or `-N`
+    /// (relative to the cursor) or `N%` (of the way through the file).
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (line, column) = match text.split_once(':') {
+            Some((line, column)) => (line, Some(column)),
+            None => (text, None),
+        };
+
+        let number = |digits: &str| {
+            digits.parse::<usize>().map_err(|_| format!("'{}' is not a line number", text))
+        };
+        let offset = |digits: &str| {
+            isize::try_from(number(digits)?).map_err(|_| format!("'{}' is out of range", text))
+        };
+        let line = if let Some(n) = line.strip_prefix('+') {
+            Line::Relative(offset(n)?)
+        } else if let Some(n) = line.strip_prefix('-') {
+            Line::Relative(-offset(n)?)
+        } else if let Some(n) = line.strip_suffix('%') {
+            match number(n)? {
+                p @ 0..=100 => Line::Percent(p),
+                _ => return Err(String::from("percentage must be 0 to 100")),
+            }
+        } else {
+            Line::Absolute(number(line)?)
+        };
+        let column = match column {
+            Some(c) => Some(c.trim().parse::<usize>().map_err(|_| format!("'{}' is not a column number", c))?),
+            None => None,
+        };
+
+        Ok(Goto { line, column })
+    }
+
+    /// Go to line `number` (from 1).
+    pub fn line_number(number: usize) -> Self {
+        Goto { line: Line::Absolute(number), column: None }
+    }
+
+    /// Line (from 0) this leads to with the cursor on line `current` of a
+    /// file of `line_count` lines, kept within the file.
+    pub fn line(&self, current: usize, line_count: usize) -> usize {
+        let last = line_count.saturating_sub(1);
+        match self.line {
+            Line::Absolute(n) => n.saturating_sub(1).min(last),
+            Line::Relative(delta) => current.saturating_add_signed(delta).min(last),
+            Line::Percent(p) => (last * p + 50) / 100,
+        }
+    }
+
+    /// Column (from 0, in characters), if one was given.
+    pub fn column(&self) -> Option<usize> {
+        self.column.map(|c| c.saturating_sub(1))
+    }
+}
+
+/// Split a `file:line` or `file:line:column` command-line argument into
+/// the file and where to go in it. Anything else is all file name, so
+/// Windows drive letters are left alone.
+pub fn split_location(arg: &str) -> (&str, Option<Goto>) {
+    let numeric = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    let Some((rest, last)) = arg.rsplit_once(':').filter(|(_, last)| numeric(last)) else {
+        return (arg, None);
+    };
+    let (file, location) = match rest.rsplit_once(':') {
+        Some((file, line)) if numeric(line) => (file, format!("{}:{}", line, last)),
+        _ => (rest, last.to_string()),
+    };
+    if file.is_empty() {
+        return (arg, None);
+    }
+    (file, Goto::parse(&location).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line (from 0) `text` leads to from line 10 of a 101-line file.
+    fn target(text: &str) -> usize {
+        Goto::parse(text).unwrap().line(10, 101)
+    }
+
+    #[test]
+    fn absolute_lines_count_from_one_and_stay_in_the_file() {
+        assert_eq!(target("1"), 0);
+        assert_eq!(target(" 42 "), 41);
+        assert_eq!(target("0"), 0);
+        assert_eq!(target("500"), 100);
+    }
+
+    #[test]
+    fn relative_lines_move_from_the_cursor() {
+        assert_eq!(target("+5"), 15);
+        assert_eq!(target("-3"), 7);
+        assert_eq!(target("-20"), 0);
+        assert_eq!(target("+1000"), 100);
+    }
+
+    #[test]
+    fn percentages_go_part_way_through_the_file() {
+        assert_eq!(target("0%"), 0);
+        assert_eq!(target("50%"), 50);
+        assert_eq!(target("100%"), 100);
+        assert!(Goto::parse("101%").is_err());
+    }
+
+    #[test]
+    fn columns_count_from_one() {
+        let goto = Goto::parse("12:7").unwrap();
+        assert_eq!(goto.line(0, 100), 11);
+        assert_eq!(goto.column(), Some(6));
+        assert_eq!(Goto::parse("12").unwrap().column(), None);
+        assert!(Goto::parse("12:x").is_err());
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_line() {
+        for text in ["", "abc", "+", "-x", "%", "1.5"] {
+            assert!(Goto::parse(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_too_big_to_move_by() {
+        let huge = (isize::MAX as usize + 1).to_string();
+        assert!(Goto::parse(&format!("+{}", huge)).is_err());
+        assert!(Goto::parse(&format!("-{}", huge)).is_err());
+        assert_eq!(Goto::parse(&format!("-{}", isize::MAX)).unwrap().line(10, 101), 0);
+    }
+
+    #[test]
+    fn splits_locations_off_file_names() {
+        let (file, goto) = split_location("src/main.rs:12:5");
+        assert_eq!(file, "src/main.rs");
+        let goto = goto.unwrap();
+        assert_eq!((goto.line(0, 100), goto.column()), (11, Some(4)));
+
+        let (file, goto) = split_location("notes.txt:3");
+        assert_eq!(file, "notes.txt");
+        assert_eq!(goto.unwrap().column(), None);
+    }
+
+    #[test]
+    fn leaves_other_colons_in_the_file_name() {
+        for arg in [r"C:\work\notes.txt", "notes.txt", "a:b", ":12", "notes.txt:", "notes.txt:1:2:x"] {
+            let (file, goto) = split_location(arg);
+            assert_eq!(file, arg);
+            assert!(goto.is_none(), "{:?}", arg);
+        }
+        let (file, goto) = split_location(r"C:\work\notes.txt:4");
+        assert_eq!(file, r"C:\work\notes.txt");
+        assert!(goto.is_some());
+    }
+}
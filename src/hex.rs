@@ -0,0 +1,61 @@
+//! Helpers for the hex view used for binary files.
+
+pub const BYTES_PER_ROW: usize = 16;
+
+/// Width of the `00000000  ` offset column.
+pub const OFFSET_WIDTH: usize = 10;
+
+/// How much of a file is inspected to decide whether it is binary.
+pub const SAMPLE_SIZE: usize = 8192;
+
+/// A file is treated as binary if it contains NUL bytes or is mostly
+/// control characters. Text that merely isn't UTF-8 is left to the
+/// encoding detection.
+pub fn looks_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    control * 10 > sample.len() * 3
+}
+
+pub fn row_count(byte_count: usize) -> usize {
+    byte_count.div_ceil(BYTES_PER_ROW).max(1)
+}
+
+/// Screen column of a nibble within a row, relative to the start of the
+/// hex pane. There is an extra gap after the eighth byte.
+pub fn nibble_column(nibble: usize) -> usize {
+    let byte = nibble / 2;
+    byte * 3 + usize::from(byte >= BYTES_PER_ROW / 2) + nibble % 2
+}
+
+/// The offset column and hex pane of one row, padded to full width.
+pub fn hex_pane(bytes: &[u8], row: usize) -> String {
+    let start = row * BYTES_PER_ROW;
+    let mut out = format!("{:08x}  ", start);
+
+    for i in 0..BYTES_PER_ROW {
+        if i == BYTES_PER_ROW / 2 {
+            out.push(' ');
+        }
+        match bytes.get(start + i) {
+            Some(b) => out.push_str(&format!("{:02x} ", b)),
+            None => out.push_str("   "),
+        }
+    }
+    out
+}
+
+/// Printable form of a byte for the ASCII pane.
+pub fn ascii_char(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }
+}
+
+/// Value of a typed hex digit.
+pub fn digit_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
@@ -0,0 +1,396 @@
+//! Which keys run which commands: single keys and key sequences such as
+//! `Ctrl-X 2`, from each command's default keys and the `[keys]` section
+//! of keys.ini.
+//!
+//! ```ini
+//! [keys]
+//! # command = keys, comma separated; replaces the command's default keys
+//! comment = Ctrl-/, Ctrl-K Ctrl-C
+//! # nothing after the = leaves the command unbound
+//! undo =
+//! ```
+
+use crate::command::{self, COMMANDS, Command};
+use crate::config::{self, Section};
+use std::fmt;
+
+const KEYS_FILE: &str = "keys.ini";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Code {
+    Char(char),
+    Function(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    Tab,
+    Enter,
+    Backspace,
+    Escape,
+}
+
+/// A key press, as far as the terminal can tell keys apart.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    code: Code,
+    ctrl: bool,
+}
+
+/// Names of the keys that aren't characters, as written in keys.ini.
+const NAMED_KEYS: &[(&str, Code)] = &[
+    ("Up", Code::Up),
+    ("Down", Code::Down),
+    ("Left", Code::Left),
+    ("Right", Code::Right),
+    ("Home", Code::Home),
+    ("End", Code::End),
+    ("PageUp", Code::PageUp),
+    ("PageDown", Code::PageDown),
+    ("Insert", Code::Insert),
+    ("Delete", Code::Delete),
+    ("Tab", Code::Tab),
+    ("Enter", Code::Enter),
+    ("Backspace", Code::Backspace),
+    ("Escape", Code::Escape),
+    ("Space", Code::Char(' ')),
+];
+
+impl Key {
+    fn plain(code: Code) -> Self {
+        Key { code, ctrl: false }
+    }
+
+    fn ctrl(code: Code) -> Self {
+        Key { code, ctrl: true }
+    }
+
+    /// The key that sent `byte` on its own.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0 => Key::ctrl(Code::Char(' ')),
+            8 | 127 => Key::plain(Code::Backspace),
+            9 => Key::plain(Code::Tab),
+            10 | 13 => Key::plain(Code::Enter),
+            0x1b => Key::plain(Code::Escape),
+            1..=26 => Key::ctrl(Code::Char((b'a' + byte - 1) as char)),
+            0x1c => Key::ctrl(Code::Char('\\')),
+            0x1d => Key::ctrl(Code::Char(']')),
+            0x1e => Key::ctrl(Code::Char('^')),
+            // Ctrl-/ and Ctrl-_ send the same byte
+            0x1f => Key::ctrl(Code::Char('/')),
+            32..=126 => Key::plain(Code::Char(byte as char)),
+            _ => return None,
+        })
+    }
+
+    /// The key that sent the escape sequence with `params` and
+    /// `final_byte`, if it is a key the keymap knows.
+    pub fn from_escape(params: &str, final_byte: u8) -> Option<Self> {
+        // Ctrl shows up as a `;5` modifier parameter
+        let (number, ctrl) = match params.split_once(';') {
+            Some((number, "5")) => (number, true),
+            Some(_) => return None,
+            None => (params, false),
+        };
+        let code = match (number, final_byte) {
+            ("" | "1", b'A') => Code::Up,
+            ("" | "1", b'B') => Code::Down,
+            ("" | "1", b'C') => Code::Right,
+            ("" | "1", b'D') => Code::Left,
+            ("" | "1", b'H') | ("1" | "7", b'~') => Code::Home,
+            ("" | "1", b'F') | ("4" | "8", b'~') => Code::End,
+            ("" | "1", b'P') => Code::Function(1),
+            ("" | "1", b'Q') => Code::Function(2),
+            ("" | "1", b'R') => Code::Function(3),
+            ("" | "1", b'S') => Code::Function(4),
+            (number, b'~') => match number.parse::<u8>().ok()? {
+                2 => Code::Insert,
+                3 => Code::Delete,
+                5 => Code::PageUp,
+                6 => Code::PageDown,
+                n @ 11..=15 => Code::Function(n - 10),
+                n @ 17..=21 => Code::Function(n - 11),
+                n @ 23..=24 => Code::Function(n - 12),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Key { code, ctrl })
+    }
+
+    /// Parse a key as written in keys.ini: `Ctrl-S`, `F1`, `Ctrl-PageUp`,
+    /// `o`.
+    fn parse(text: &str) -> Result<Self, String> {
+        let (ctrl, name) = match text.get(..5) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("ctrl-") && text.len() > 5 => (true, &text[5..]),
+            _ => (false, text),
+        };
+
+        let mut chars = name.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(ch), None) if ctrl => Code::Char(ch.to_ascii_lowercase()),
+            (Some(ch), None) => Code::Char(ch),
+            _ => {
+                let function = name.strip_prefix(['F', 'f']).and_then(|n| n.parse::<u8>().ok());
+                match function {
+                    Some(n @ 1..=12) => Code::Function(n),
+                    _ => NAMED_KEYS.iter()
+                        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                        .map(|&(_, code)| code)
+                        .ok_or_else(|| format!("unknown key '{}'", text))?,
+                }
+            },
+        };
+
+        let key = Key { code, ctrl };
+        match code {
+            Code::Escape => Err(String::from("Escape cancels key sequences")),
+            Code::Char('h' | 'i' | 'j' | 'm' | '[') if ctrl => {
+                Err(format!("the terminal sends {} as Backspace, Tab, Enter or Escape", key))
+            },
+            Code::Char('_') if ctrl => Ok(Key::ctrl(Code::Char('/'))),
+            Code::Char(ch) if ctrl && !ch.is_ascii_lowercase() && !" /\\]^".contains(ch) => {
+                Err(format!("the terminal can't send {}", text))
+            },
+            _ if ctrl && matches!(code, Code::Tab | Code::Enter | Code::Backspace) => {
+                Err(format!("the terminal can't send {}", text))
+            },
+            _ => Ok(key),
+        }
+    }
+
+    pub fn is_escape(self) -> bool {
+        self.code == Code::Escape
+    }
+
+    /// Whether a key sequence may start with this key. Other keys type,
+    /// move the cursor or edit.
+    fn starts_sequences(self) -> bool {
+        match self.code {
+            Code::Function(_) => true,
+            Code::Up | Code::Down | Code::Left | Code::Right | Code::Home | Code::End => false,
+            _ => self.ctrl,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl-")?;
+        }
+        match self.code {
+            Code::Char(' ') => write!(f, "Space"),
+            Code::Char(ch) if self.ctrl => write!(f, "{}", ch.to_ascii_uppercase()),
+            Code::Char(ch) => write!(f, "{}", ch),
+            Code::Function(n) => write!(f, "F{}", n),
+            code => {
+                let name = NAMED_KEYS.iter().find(|&&(_, c)| c == code).map_or("?", |(name, _)| name);
+                write!(f, "{}", name)
+            },
+        }
+    }
+}
+
+/// Parse a key sequence: keys separated by spaces.
+fn parse_sequence(text: &str) -> Result<Vec<Key>, String> {
+    let keys = text.split_whitespace().map(Key::parse).collect::<Result<Vec<Key>, String>>()?;
+    match keys.first() {
+        None => Err(String::from("no keys")),
+        Some(&first) if !first.starts_sequences() => {
+            Err(format!("{} is an editing key; bindings start with Ctrl or a function key", first))
+        },
+        Some(_) => Ok(keys),
+    }
+}
+
+/// Keys as written in keys.ini and shown in help.
+pub fn sequence_name(keys: &[Key]) -> String {
+    keys.iter().map(Key::to_string).collect::<Vec<_>>().join(" ")
+}
+
+/// Whether `a` and `b` can't both be bound: they are the same, or one
+/// starts the other so it could never be finished.
+fn clashes(a: &[Key], b: &[Key]) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// What the keys pressed so far lead to.
+pub enum Lookup {
+    Command(Command),
+    /// The start of one or more key sequences; wait for the next key.
+    Prefix,
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Command)>,
+}
+
+impl Keymap {
+    /// Each command's default keys, replaced by its keys in keys.ini where
+    /// that has any. Also returns what was wrong with keys.ini: unknown
+    /// commands or keys, and keys that clash with ones bound earlier in the
+    /// file, which are left out. A default key that clashes with keys.ini
+    /// gives way to it.
+    pub fn load() -> (Self, Vec<String>) {
+        Keymap::from_ini(&config::load_ini(KEYS_FILE))
+    }
+
+    fn from_ini(sections: &[Section]) -> (Self, Vec<String>) {
+        let mut problems = Vec::new();
+        let mut custom: Vec<(Vec<Key>, Command)> = Vec::new();
+        let mut rebound = Vec::new();
+
+        for section in sections.iter().filter(|s| s.name == "keys") {
+            for (name, value) in &section.entries {
+                let Some(command) = command::find(name) else {
+                    problems.push(format!("unknown command {}", name));
+                    continue;
+                };
+                rebound.push(command);
+                for text in value.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+                    let keys = match parse_sequence(text) {
+                        Ok(keys) => keys,
+                        Err(e) => {
+                            problems.push(format!("{}: {}", name, e));
+                            continue;
+                        },
+                    };
+                    match custom.iter().find(|(other, _)| clashes(other, &keys)) {
+                        Some((other, bound)) => problems.push(format!(
+                            "{} for {} clashes with {} for {}",
+                            text, name, sequence_name(other), bound.info().name
+                        )),
+                        None => custom.push((keys, command)),
+                    }
+                }
+            }
+        }
+
+        let defaults: Vec<(Vec<Key>, Command)> = COMMANDS.iter()
+            .filter(|info| !rebound.contains(&info.command))
+            .flat_map(|info| info.keys.iter().map(move |text| {
+                (parse_sequence(text).expect("default keys are valid"), info.command)
+            }))
+            .filter(|(keys, _)| !custom.iter().any(|(other, _)| clashes(other, keys)))
+            .collect();
+        let mut bindings = defaults;
+        bindings.extend(custom);
+
+        (Keymap { bindings }, problems)
+    }
+
+    /// Look up the keys pressed so far.
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        let mut prefix = false;
+        for (sequence, command) in &self.bindings {
+            if sequence.as_slice() == keys {
+                return Lookup::Command(*command);
+            }
+            prefix |= sequence.starts_with(keys);
+        }
+        if prefix { Lookup::Prefix } else { Lookup::Unbound }
+    }
+
+    /// Names of the keys bound to `command`.
+    pub fn keys_for(&self, command: Command) -> Vec<String> {
+        self.bindings.iter()
+            .filter(|(_, bound)| *bound == command)
+            .map(|(keys, _)| sequence_name(keys))
+            .collect()
+    }
+
+    /// How each key sequence starting with `prefix` goes on, and what it
+    /// runs, in the order commands are listed.
+    pub fn continuations(&self, prefix: &[Key]) -> Vec<(String, Command)> {
+        let mut found: Vec<(String, Command)> = self.bindings.iter()
+            .filter(|(keys, _)| keys.len() > prefix.len() && keys.starts_with(prefix))
+            .map(|(keys, command)| (sequence_name(&keys[prefix.len()..]), *command))
+            .collect();
+        found.sort_by_key(|(_, command)| COMMANDS.iter().position(|info| info.command == *command));
+        found
+    }
+
+    /// The help line for the message bar: each command's first key, and
+    /// the keys that start sequences.
+    pub fn help(&self) -> String {
+        let mut entries = Vec::new();
+        let mut prefixes: Vec<Key> = Vec::new();
+        for info in COMMANDS {
+            let mut sequences = self.bindings.iter().filter(|(_, bound)| *bound == info.command).map(|(keys, _)| keys);
+            match sequences.clone().find(|keys| keys.len() == 1).or_else(|| sequences.next()) {
+                Some(keys) if keys.len() == 1 => entries.push(format!("{} = {}", keys[0], info.name)),
+                Some(keys) if !prefixes.contains(&keys[0]) => prefixes.push(keys[0]),
+                _ => {},
+            }
+        }
+        entries.extend(prefixes.iter().map(|key| format!("{} = more", key)));
+        format!("HELP: {}", entries.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(ini: &str) -> (Keymap, Vec<String>) {
+        Keymap::from_ini(&config::parse_ini(ini))
+    }
+
+    fn ctrl(ch: char) -> Key {
+        Key::ctrl(Code::Char(ch))
+    }
+
+    #[test]
+    fn parses_key_sequences() {
+        let keys = parse_sequence("Ctrl-K  ctrl-c").unwrap();
+        assert!(keys == [ctrl('k'), ctrl('c')]);
+        assert_eq!(sequence_name(&keys), "Ctrl-K Ctrl-C");
+        assert!(parse_sequence("F1 x").unwrap() == [Key::plain(Code::Function(1)), Key::plain(Code::Char('x'))]);
+
+        assert!(parse_sequence("").is_err());
+        assert!(parse_sequence("x Ctrl-C").is_err());
+        assert!(parse_sequence("Ctrl-M").is_err());
+        assert!(parse_sequence("Ctrl-K Escape").is_err());
+        assert!(parse_sequence("Ctrl-Nope").is_err());
+    }
+
+    #[test]
+    fn a_prefix_of_another_binding_clashes() {
+        let (keys, problems) = keymap("[keys]\nsave = Ctrl-X\nquit = Ctrl-X Ctrl-C\n");
+        assert_eq!(problems, ["Ctrl-X Ctrl-C for quit clashes with Ctrl-X for save"]);
+        assert!(matches!(keys.lookup(&[ctrl('x')]), Lookup::Command(Command::Save)));
+        assert!(keys.keys_for(Command::Quit).is_empty());
+
+        // A default sequence gives way to a custom key that starts it
+        let (keys, problems) = keymap("[keys]\nquit = Ctrl-K\n");
+        assert!(problems.is_empty());
+        assert!(matches!(keys.lookup(&[ctrl('k')]), Lookup::Command(Command::Quit)));
+        assert_eq!(keys.keys_for(Command::Comment), ["Ctrl-/"]);
+    }
+
+    #[test]
+    fn a_key_can_only_be_bound_once() {
+        let (keys, problems) = keymap("[keys]\nsave = Ctrl-X\nquit = Ctrl-X, F2\n");
+        assert_eq!(problems, ["Ctrl-X for quit clashes with Ctrl-X for save"]);
+        assert!(matches!(keys.lookup(&[ctrl('x')]), Lookup::Command(Command::Save)));
+        assert_eq!(keys.keys_for(Command::Quit), ["F2"]);
+    }
+
+    #[test]
+    fn sequences_wait_for_their_next_key() {
+        let (keys, problems) = keymap("");
+        assert!(problems.is_empty());
+        assert!(matches!(keys.lookup(&[ctrl('k')]), Lookup::Prefix));
+        assert!(matches!(keys.lookup(&[ctrl('k'), ctrl('c')]), Lookup::Command(Command::Comment)));
+        assert!(matches!(keys.lookup(&[ctrl('k'), ctrl('y')]), Lookup::Unbound));
+    }
+}
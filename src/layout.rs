@@ -0,0 +1,223 @@
+//! Arrangement of split panes on screen.
+//!
+//! The layout is a binary tree: leaves are panes (identified by the index of
+//! the view they show) and inner nodes split their area between two children,
+//! either one above the other or side by side.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplitDirection {
+    /// One pane above the other.
+    Horizontal,
+    /// Panes side by side, with a separator column between them.
+    Vertical,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Share of a split given to its first child can't go below this (or above
+/// 100 minus this) percent.
+const MIN_RATIO: u16 = 10;
+
+pub enum Layout {
+    Pane(usize),
+    Split {
+        direction: SplitDirection,
+        /// Percentage of the area given to `first`.
+        ratio: u16,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Replace the pane showing `view` with a split of it and `new_view`.
+    pub fn split(&mut self, view: usize, direction: SplitDirection, new_view: usize) -> bool {
+        match self {
+            Layout::Pane(v) if *v == view => {
+                *self = Layout::Split {
+                    direction,
+                    ratio: 50,
+                    first: Box::new(Layout::Pane(view)),
+                    second: Box::new(Layout::Pane(new_view)),
+                };
+                true
+            },
+            Layout::Pane(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.split(view, direction, new_view) || second.split(view, direction, new_view)
+            },
+        }
+    }
+
+    /// Remove the pane showing `view`; its sibling takes over the space.
+    /// Views numbered above `view` are renumbered down by one, as the caller
+    /// removes `view` from its list. Returns the first pane of the sibling,
+    /// after renumbering, or `None` if `view` is the only pane.
+    pub fn remove(&mut self, view: usize) -> Option<usize> {
+        let sibling = self.remove_pane(view)?;
+        self.renumber_after(view);
+        Some(if sibling > view { sibling - 1 } else { sibling })
+    }
+
+    fn remove_pane(&mut self, view: usize) -> Option<usize> {
+        let Layout::Split { first, second, .. } = self else { return None };
+
+        let keep = if matches!(**first, Layout::Pane(v) if v == view) {
+            take(second)
+        } else if matches!(**second, Layout::Pane(v) if v == view) {
+            take(first)
+        } else {
+            return first.remove_pane(view).or_else(|| second.remove_pane(view));
+        };
+
+        *self = keep;
+        Some(self.first_pane())
+    }
+
+    fn renumber_after(&mut self, removed: usize) {
+        match self {
+            Layout::Pane(v) => {
+                if *v > removed {
+                    *v -= 1;
+                }
+            },
+            Layout::Split { first, second, .. } => {
+                first.renumber_after(removed);
+                second.renumber_after(removed);
+            },
+        }
+    }
+
+    fn first_pane(&self) -> usize {
+        match self {
+            Layout::Pane(v) => *v,
+            Layout::Split { first, .. } => first.first_pane(),
+        }
+    }
+
+    fn contains(&self, view: usize) -> bool {
+        match self {
+            Layout::Pane(v) => *v == view,
+            Layout::Split { first, second, .. } => first.contains(view) || second.contains(view),
+        }
+    }
+
+    /// Grow (positive `delta`) or shrink the pane showing `view` by `delta`
+    /// percent of the split it belongs to. Returns false if it isn't split.
+    pub fn resize(&mut self, view: usize, delta: i16) -> bool {
+        let Layout::Split { ratio, first, second, .. } = self else { return false };
+
+        // The innermost split holding the pane is the one to adjust
+        if first.resize(view, delta) || second.resize(view, delta) {
+            return true;
+        }
+        let delta = if first.contains(view) {
+            delta
+        } else if second.contains(view) {
+            -delta
+        } else {
+            return false;
+        };
+
+        let max = 100 - MIN_RATIO as i16;
+        *ratio = (*ratio as i16 + delta).clamp(MIN_RATIO as i16, max) as u16;
+        true
+    }
+
+    /// Screen area of every pane within `area`.
+    pub fn panes(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut panes = Vec::new();
+        self.collect(area, &mut panes, &mut Vec::new());
+        panes
+    }
+
+    /// Columns between side-by-side panes, to be drawn as separators.
+    pub fn separators(&self, area: Rect) -> Vec<Rect> {
+        let mut separators = Vec::new();
+        self.collect(area, &mut Vec::new(), &mut separators);
+        separators
+    }
+
+    fn collect(&self, area: Rect, panes: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        match self {
+            Layout::Pane(v) => panes.push((*v, area)),
+            Layout::Split { direction: SplitDirection::Horizontal, ratio, first, second } => {
+                let top = share(area.height, 0, *ratio);
+                first.collect(Rect { height: top, ..area }, panes, separators);
+                second.collect(Rect { y: area.y + top, height: area.height - top, ..area }, panes, separators);
+            },
+            Layout::Split { direction: SplitDirection::Vertical, ratio, first, second } => {
+                let left = share(area.width, 1, *ratio);
+                let right_x = (area.x + left + 1).min(area.x + area.width);
+                first.collect(Rect { width: left, ..area }, panes, separators);
+                if left < area.width {
+                    separators.push(Rect { x: area.x + left, width: 1, ..area });
+                }
+                let right = Rect { x: right_x, width: area.x + area.width - right_x, ..area };
+                second.collect(right, panes, separators);
+            },
+        }
+    }
+}
+
+/// Size of the first part when `total` cells, less `gap` for a separator,
+/// are split at `ratio` percent. Both parts keep at least one cell if there
+/// is room.
+fn share(total: usize, gap: usize, ratio: u16) -> usize {
+    let available = total.saturating_sub(gap);
+    if available < 2 {
+        return available;
+    }
+    (available * ratio as usize / 100).clamp(1, available - 1)
+}
+
+/// Move a subtree out, leaving a placeholder about to be overwritten.
+fn take(layout: &mut Layout) -> Layout {
+    std::mem::replace(layout, Layout::Pane(usize::MAX))
+}
+
+/// The pane next to `from` in `direction`: the closest one on that side,
+/// preferring panes that line up with it.
+pub fn neighbor(panes: &[(usize, Rect)], from: usize, direction: Direction) -> Option<usize> {
+    let (_, a) = *panes.iter().find(|(v, _)| *v == from)?;
+
+    panes.iter()
+        .filter(|(v, _)| *v != from)
+        .filter_map(|&(v, r)| {
+            // Distance across the gap, and how far apart the panes sit along it
+            let (gap, offset) = match direction {
+                Direction::Left if r.x + r.width <= a.x => (a.x - (r.x + r.width), span_distance(a.y, a.height, r.y, r.height)),
+                Direction::Right if r.x >= a.x + a.width => (r.x - (a.x + a.width), span_distance(a.y, a.height, r.y, r.height)),
+                Direction::Up if r.y + r.height <= a.y => (a.y - (r.y + r.height), span_distance(a.x, a.width, r.x, r.width)),
+                Direction::Down if r.y >= a.y + a.height => (r.y - (a.y + a.height), span_distance(a.x, a.width, r.x, r.width)),
+                _ => return None,
+            };
+            Some(((gap, offset), v))
+        })
+        .min()
+        .map(|(_, v)| v)
+}
+
+/// Zero when the spans `a..a+a_len` and `b..b+b_len` overlap, otherwise the
+/// space between them.
+fn span_distance(a: usize, a_len: usize, b: usize, b_len: usize) -> usize {
+    if b >= a + a_len {
+        b - (a + a_len)
+    } else {
+        a.saturating_sub(b + b_len)
+    }
+}
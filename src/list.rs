@@ -0,0 +1,161 @@
+//! Moving around the full-screen lists: the file browser, quick open,
+//! search results, the command palette, pickers and text views. The keys
+//! they all share are handled here; whatever else a list does with a key
+//! is up to the list.
+
+/// A key read while a list is shown.
+pub enum ListKey {
+    Enter,
+    /// Escape on its own, which closes the list.
+    Escape,
+    /// Any other single byte.
+    Byte(u8),
+    /// An escape sequence: its parameters and final byte.
+    Sequence(String, u8),
+}
+
+/// The selected item of a list and the first one on screen.
+pub struct ListNav {
+    selected: usize,
+    top: usize,
+    rows: usize,
+    len: usize,
+    /// A text view has no selection: moving scrolls the view, and
+    /// `selected` is the top line.
+    scrolling: bool,
+}
+
+impl ListNav {
+    /// A list showing `rows` items at a time.
+    pub fn new(rows: usize) -> Self {
+        ListNav { selected: 0, top: 0, rows: rows.max(1), len: 0, scrolling: false }
+    }
+
+    /// A text view showing `rows` lines at a time.
+    pub fn scrolling(rows: usize) -> Self {
+        ListNav { scrolling: true, ..ListNav::new(rows) }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    /// Set how many items there are now, keeping the selection on one of
+    /// them.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        self.select(self.selected);
+    }
+
+    /// Select item `index`, or the last one if there aren't that many, and
+    /// scroll as little as possible to show it.
+    pub fn select(&mut self, index: usize) {
+        let last = if self.scrolling { self.len.saturating_sub(self.rows) } else { self.len.saturating_sub(1) };
+        self.selected = index.min(last);
+        self.top = if self.scrolling { self.selected } else { keep_in_view(self.selected, self.top, self.rows) };
+    }
+
+    /// Move the selection `delta` items down (up if negative).
+    pub fn step(&mut self, delta: isize) {
+        self.select(self.selected.saturating_add_signed(delta));
+    }
+
+    /// Move a screenful down (up if `down` is false).
+    pub fn page(&mut self, down: bool) {
+        let rows = self.rows as isize;
+        self.step(if down { rows } else { -rows });
+    }
+
+    /// Act on the keys every list shares: Up and Down, PageUp and PageDown,
+    /// Home and End. Returns whether `key` was one of them.
+    pub fn handle(&mut self, key: &ListKey) -> bool {
+        let ListKey::Sequence(params, final_byte) = key else { return false };
+        match (params.as_str(), final_byte) {
+            ("", b'A') => self.step(-1),
+            ("", b'B') => self.step(1),
+            ("", b'H') => self.select(0),
+            ("", b'F') => self.select(usize::MAX),
+            ("5", b'~') => self.page(false),
+            ("6", b'~') => self.page(true),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// First item to show so that `selected` is on screen, scrolling as little
+/// as possible from `top`.
+pub fn keep_in_view(selected: usize, top: usize, rows: usize) -> usize {
+    if selected < top {
+        selected
+    } else if selected >= top + rows {
+        selected + 1 - rows
+    } else {
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(params: &str, final_byte: u8) -> ListKey {
+        ListKey::Sequence(params.to_string(), final_byte)
+    }
+
+    #[test]
+    fn moves_within_the_list() {
+        let mut list = ListNav::new(5);
+        list.set_len(12);
+        assert!(list.handle(&key("", b'A')));
+        assert_eq!(list.selected(), 0);
+        assert!(list.handle(&key("", b'F')));
+        assert_eq!((list.selected(), list.top()), (11, 7));
+        assert!(list.handle(&key("5", b'~')));
+        assert_eq!((list.selected(), list.top()), (6, 6));
+        assert!(list.handle(&key("", b'H')));
+        assert_eq!((list.selected(), list.top()), (0, 0));
+        list.page(true);
+        list.page(true);
+        list.page(true);
+        assert_eq!((list.selected(), list.top()), (11, 7));
+    }
+
+    #[test]
+    fn leaves_other_keys_to_the_list() {
+        let mut list = ListNav::new(5);
+        list.set_len(3);
+        for other in [ListKey::Enter, ListKey::Escape, ListKey::Byte(b'q'), key("", b'C')] {
+            assert!(!list.handle(&other));
+        }
+        assert_eq!(list.selected(), 0);
+    }
+
+    #[test]
+    fn keeps_the_selection_on_an_item_as_the_list_changes() {
+        let mut list = ListNav::new(5);
+        list.set_len(10);
+        list.select(9);
+        list.set_len(4);
+        assert_eq!((list.selected(), list.top()), (3, 3));
+        list.set_len(0);
+        assert_eq!((list.selected(), list.top()), (0, 0));
+    }
+
+    #[test]
+    fn text_views_scroll_without_passing_the_last_screenful() {
+        let mut list = ListNav::scrolling(5);
+        list.set_len(12);
+        list.step(1);
+        assert_eq!(list.top(), 1);
+        list.page(true);
+        list.page(true);
+        assert_eq!(list.top(), 7);
+        list.set_len(3);
+        assert_eq!(list.top(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::encoding::Encoding;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+/// Files at least this big are opened lazily.
+pub const LAZY_LOAD_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// How much is read up front, before the first frame, for a lazy file.
+/// Plenty for a screenful of lines.
+const INITIAL_CHUNK: usize = 256 * 1024;
+
+/// Worker thread read size.
+const CHUNK: usize = 1024 * 1024;
+
+// The worker simply exits when it reaches the end of the file; the closed
+// channel is the "done" signal.
+enum Message {
+    Lines { lines: Vec<String>, bytes: u64 },
+    /// The file isn't valid in the encoding it was being read in; the lines
+    /// that follow start over from the top in this one.
+    Restart(Encoding),
+    Failed(io::Error),
+}
+
+/// What `BackgroundLoader::poll` found since the last call.
+pub enum LoadProgress {
+    Lines(Vec<String>),
+    /// Every line so far was decoded wrongly: these replace them, read in
+    /// `encoding`.
+    Restarted { encoding: Encoding, lines: Vec<String> },
+    Finished,
+    Failed(io::Error),
+    Pending,
+}
+
+/// Reads the rest of a large file on a worker thread and hands over
+/// complete lines as they become available.
+pub struct BackgroundLoader {
+    receiver: Receiver<Message>,
+    total_bytes: u64,
+    loaded_bytes: u64,
+    /// Set from a `Restart` until the first lines read after it arrive.
+    restart: Option<Encoding>,
+}
+
+/// Split complete lines off the front of `pending`, leaving any unfinished
+/// last line in it.
+fn take_lines(pending: &mut Vec<u8>, encoding: Encoding) -> io::Result<Vec<String>> {
+    let Some(last_newline) = pending.iter().rposition(|&b| b == b'\n') else {
+        return Ok(Vec::new());
+    };
+
+    let rest = pending.split_off(last_newline + 1);
+    let complete = std::mem::replace(pending, rest);
+    complete[..complete.len() - 1]
+        .split(|&b| b == b'\n')
+        .map(|line| decode_line(line, encoding))
+        .collect()
+}
+
+fn decode_line(bytes: &[u8], encoding: Encoding) -> io::Result<String> {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    encoding.decode(bytes)
+}
+
+/// Read the start of `file` until there is at least one whole line to show.
+fn read_first_lines(file: &mut File, pending: &mut Vec<u8>, loaded_bytes: &mut u64, encoding: Encoding) -> io::Result<Vec<String>> {
+    let mut chunk = vec![0; INITIAL_CHUNK];
+    let mut lines = loop {
+        let read = file.read(&mut chunk)?;
+        *loaded_bytes += read as u64;
+        pending.extend_from_slice(&chunk[..read]);
+
+        let lines = take_lines(pending, encoding)?;
+        if !lines.is_empty() || read == 0 {
+            break lines;
+        }
+    };
+    if lines.is_empty() {
+        lines.push(decode_line(pending, encoding)?);
+        pending.clear();
+    }
+    Ok(lines)
+}
+
+/// Read the rest of `file`, sending complete lines as they are decoded and
+/// the last line at the end. Stops early, without an error, if the buffer
+/// was closed.
+fn send_lines(file: &mut File, pending: &mut Vec<u8>, encoding: Encoding, sender: &Sender<Message>) -> io::Result<()> {
+    let mut chunk = vec![0; CHUNK];
+    loop {
+        let read = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        pending.extend_from_slice(&chunk[..read]);
+
+        let lines = take_lines(pending, encoding)?;
+        // The buffer may have been closed; nobody is listening then
+        if sender.send(Message::Lines { lines, bytes: read as u64 }).is_err() {
+            return Ok(());
+        }
+    }
+
+    // Whatever follows the last newline is the final line
+    let line = decode_line(pending, encoding)?;
+    if !line.is_empty() {
+        let _ = sender.send(Message::Lines { lines: vec![line], bytes: 0 });
+    }
+    Ok(())
+}
+
+impl BackgroundLoader {
+    /// Read the first chunk of `file` now and start a thread for the rest.
+    /// Returns the lines from the first chunk alongside the loader.
+    /// `encoding` must be byte oriented (see `Encoding::is_byte_oriented`).
+    /// If the file turns out not to be valid `encoding`, it is read again
+    /// from the start as `fallback` when that is given.
+    pub fn start(
+        mut file: File,
+        total_bytes: u64,
+        encoding: Encoding,
+        fallback: Option<Encoding>,
+    ) -> io::Result<(Vec<String>, Self, Encoding)> {
+        let mut pending = Vec::new();
+        let mut loaded_bytes = 0;
+        let first_lines = match read_first_lines(&mut file, &mut pending, &mut loaded_bytes, encoding) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData && let Some(fallback) = fallback => {
+                file.rewind()?;
+                return BackgroundLoader::start(file, total_bytes, fallback, None);
+            },
+            Err(e) => return Err(e),
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut encoding, mut fallback) = (encoding, fallback);
+            loop {
+                let Err(e) = send_lines(&mut file, &mut pending, encoding, &sender) else { return };
+                match fallback.take() {
+                    Some(other) if e.kind() == io::ErrorKind::InvalidData && file.rewind().is_ok() => {
+                        encoding = other;
+                        pending.clear();
+                        if sender.send(Message::Restart(encoding)).is_err() {
+                            return;
+                        }
+                    },
+                    _ => {
+                        let _ = sender.send(Message::Failed(e));
+                        return;
+                    },
+                }
+            }
+        });
+
+        let loader = BackgroundLoader {
+            receiver,
+            total_bytes,
+            loaded_bytes,
+            restart: None,
+        };
+        Ok((first_lines, loader, encoding))
+    }
+
+    /// Collect everything the worker has produced so far without blocking.
+    pub fn poll(&mut self) -> LoadProgress {
+        let mut lines = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Message::Lines { lines: mut batch, bytes }) => {
+                    self.loaded_bytes += bytes;
+                    lines.append(&mut batch);
+                },
+                Ok(Message::Restart(encoding)) => {
+                    self.restart = Some(encoding);
+                    self.loaded_bytes = 0;
+                    lines.clear();
+                },
+                Ok(Message::Failed(e)) => return LoadProgress::Failed(e),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // Hand over the last lines first; `Finished` comes next poll
+                    if lines.is_empty() && self.restart.is_none() {
+                        self.loaded_bytes = self.total_bytes;
+                        return LoadProgress::Finished;
+                    }
+                    if lines.is_empty() {
+                        lines.push(String::new());
+                    }
+                    break;
+                }
+            }
+        }
+
+        // Hold a restart back until there is a line to replace the old ones
+        // with, so the buffer is never left empty
+        match self.restart {
+            Some(encoding) if !lines.is_empty() => {
+                self.restart = None;
+                LoadProgress::Restarted { encoding, lines }
+            },
+            _ if lines.is_empty() || self.restart.is_some() => LoadProgress::Pending,
+            _ => LoadProgress::Lines(lines),
+        }
+    }
+
+    /// Percentage of the file read so far.
+    pub fn percent(&self) -> u64 {
+        (self.loaded_bytes * 100).checked_div(self.total_bytes).unwrap_or(100).min(100)
+    }
+}
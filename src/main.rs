@@ -1,39 +1,75 @@
 mod terminal;
 mod editor;
 mod buffer;
+mod config;
+mod theme;
+mod syntax;
+mod rope;
+mod loader;
+mod hex;
+mod encoding;
+mod diff;
+mod layout;
+mod browser;
+mod finder;
+mod regex;
+mod search;
+mod replace;
+mod sandbox;
+mod prompt;
+mod goto;
+mod width;
+mod command;
+mod keymap;
+mod list;
 
+use goto::Goto;
 use std::io;
 use std::env;
 use std::path::Path;
+use std::process;
 
 fn main() -> io::Result<()> {
-    // Get command-line arguments
-    let args: Vec<String> = env::args().collect();
-    
-    let mut editor = if args.len() > 1 {
-        let filepath = &args[1]; // Required: full absolute path to file or save directory
+    // Get command-line arguments: options start with `--`, `+N` picks the
+    // line to start on, the rest are paths, optionally `file:line:col`
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(unknown) = args.iter().find(|a| a.starts_with("--") && *a != "--readonly") {
+        eprintln!("Unknown option: {}", unknown);
+        print_usage();
+        process::exit(2);
+    }
+    let read_only = args.iter().any(|a| a == "--readonly");
+    let start_line = args.iter()
+        .filter_map(|a| a.strip_prefix('+'))
+        .find_map(|n| n.parse::<usize>().ok())
+        .map(Goto::line_number);
+    let paths: Vec<(&str, Option<Goto>)> = args.iter()
+        .filter(|a| !a.starts_with("--") && !is_line_argument(a))
+        .map(|a| if Path::new(a).exists() { (a.as_str(), None) } else { goto::split_location(a) })
+        .collect();
 
+    // The first path is the file to open or create, or the directory to
+    // start a coding session in
+    let mut editor = if let Some(&(filepath, _)) = paths.first() {
         // Determine if this is a file path or a directory
         let path = Path::new(filepath);
 
         if path.is_file() {
             // openfile: file exists, open it
-            eprintln!("DEBUG: Opening existing file at: {}", filepath);
-
-            match editor::Editor::from_file(&filepath) {
+            match editor::Editor::from_file(filepath) {
                 Ok(mut editor) => {
                     // Extract directory for saving
                     if let Some(dir) = path.parent() {
-                        editor.set_save_directory(&dir.to_string_lossy().to_string());
+                        editor.set_save_directory(&dir.to_string_lossy());
                     }
                     editor
                 },
                 Err(e) => {
-                    eprintln!("Error opening file '{}': {}", filepath, e);
-                    eprintln!("Starting with empty buffer instead...");
-                    let mut editor = editor::Editor::new_with_filename(filepath)?;
+                    // Don't take over the path: the next save would clobber
+                    // the file we failed to read
+                    let mut editor = editor::Editor::from_unreadable_file(filepath, &e);
                     if let Some(dir) = path.parent() {
-                        editor.set_save_directory(&dir.to_string_lossy().to_string());
+                        editor.set_save_directory(&dir.to_string_lossy());
                     }
                     editor
                 }
@@ -41,33 +77,73 @@ fn main() -> io::Result<()> {
         } else if path.is_dir() {
             // startcoding: directory provided, no file specified
             eprintln!("Starting coding session in directory: {}", filepath);
-            editor::Editor::new_with_save_directory(&filepath)?
+            editor::Editor::new_with_save_directory(filepath)?
         } else {
             // Path doesn't exist yet - could be a new file
             eprintln!("Creating new file at: {}", filepath);
 
             // Validate parent directory exists
-            if let Some(parent_dir) = path.parent() {
-                if !parent_dir.as_os_str().is_empty() && !parent_dir.is_dir() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::NotFound,
-                        format!("Parent directory does not exist: {}", parent_dir.display())
-                    ));
-                }
+            if let Some(parent_dir) = path.parent()
+                && !parent_dir.as_os_str().is_empty()
+                && !parent_dir.is_dir()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Parent directory does not exist: {}", parent_dir.display())
+                ));
             }
 
             let mut editor = editor::Editor::new_with_filename(filepath)?;
             if let Some(dir) = path.parent() {
-                editor.set_save_directory(&dir.to_string_lossy().to_string());
+                editor.set_save_directory(&dir.to_string_lossy());
             }
             editor
         }
     } else {
-        eprintln!("Usage: text_editor <path>");
-        eprintln!("  path: Full path to file to open/create, or directory for coding mode");
+        print_usage();
         editor::Editor::new()
     };
-    
+
+    if let Some(goto) = paths.first().and_then(|&(_, goto)| goto).or(start_line) {
+        editor.go_to(goto);
+    }
+
+    // Further paths open in background buffers. Failures are shown once
+    // the editor is up, as anything printed now is hidden by the screen.
+    if paths.len() > 1 {
+        let mut failures = Vec::new();
+        for &(extra, goto) in &paths[1..] {
+            match editor.open_file(extra) {
+                Ok(()) => {
+                    if let Some(goto) = goto {
+                        editor.go_to(goto);
+                    }
+                },
+                Err(e) => failures.push(format!("{}: {}", extra, e)),
+            }
+        }
+        editor.select_buffer(0);
+        if !failures.is_empty() {
+            editor.show_message(format!("Could not open {}", failures.join("; ")));
+        }
+    }
+
+    if read_only {
+        editor.set_read_only(true);
+    }
+
     editor.run()?;
     Ok(())
 }
+
+fn print_usage() {
+    eprintln!("Usage: text_editor [--readonly] [+line] <path[:line[:col]]> [more files...]");
+    eprintln!("  path: Full path to file to open/create, or directory for coding mode");
+    eprintln!("  --readonly: Open without allowing edits (Ctrl-R toggles)");
+    eprintln!("  +line, path:line:col: Start with the cursor at that line and column");
+}
+
+/// `+N`: the line to start on.
+fn is_line_argument(arg: &str) -> bool {
+    arg.strip_prefix('+').is_some_and(|n| n.parse::<usize>().is_ok())
+}
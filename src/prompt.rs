@@ -0,0 +1,581 @@
+//! Prompts in the message bar: what a prompt asks for and how its answer
+//! is checked, the line being typed, earlier answers, and completion of
+//! file paths.
+
+use crate::config;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Most answers remembered for each kind of prompt.
+const MAX_HISTORY: usize = 100;
+
+/// History file in the config directory.
+const HISTORY_FILE: &str = "history";
+
+/// What a prompt asks for. Earlier answers are offered per kind.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    File,
+    Search,
+    Replace,
+    Name,
+    Theme,
+    Encoding,
+    Goto,
+    Command,
+}
+
+impl PromptKind {
+    /// Name used in the history file.
+    fn key(self) -> &'static str {
+        match self {
+            PromptKind::File => "file",
+            PromptKind::Search => "search",
+            PromptKind::Replace => "replace",
+            PromptKind::Name => "name",
+            PromptKind::Theme => "theme",
+            PromptKind::Encoding => "encoding",
+            PromptKind::Goto => "goto",
+            PromptKind::Command => "command",
+        }
+    }
+}
+
+/// Runs whenever the typed text changes.
+type ChangeHook<'a, C> = Box<dyn FnMut(&mut C, &str) + 'a>;
+
+/// Finds completions for the text typed so far: where the part being
+/// completed starts, and what it could be.
+type CompleteHook<'a, C> = Box<dyn Fn(&C, &str) -> (usize, Vec<String>) + 'a>;
+
+/// Accepts an answer or says what is wrong with it.
+type ValidateHook<'a, C> = Box<dyn Fn(&C, &str) -> Result<(), String> + 'a>;
+
+/// A question asked in the message bar. `C` is what the hooks get to look
+/// at and change, i.e. the editor: `on_change` runs whenever the typed
+/// text changes, for incremental behaviour such as previews, and
+/// `validate` can refuse an answer with a reason, leaving the prompt open.
+pub struct Prompt<'a, C> {
+    kind: PromptKind,
+    label: String,
+    allow_empty: bool,
+    initial: String,
+    complete: Option<CompleteHook<'a, C>>,
+    on_change: Option<ChangeHook<'a, C>>,
+    validate: Option<ValidateHook<'a, C>>,
+}
+
+impl<'a, C> Prompt<'a, C> {
+    pub fn new(kind: PromptKind, label: &str) -> Self {
+        Prompt {
+            kind,
+            label: label.to_string(),
+            allow_empty: false,
+            initial: String::new(),
+            complete: None,
+            on_change: None,
+            validate: None,
+        }
+    }
+
+    /// Accept an empty answer too.
+    pub fn allow_empty(mut self) -> Self {
+        self.allow_empty = true;
+        self
+    }
+
+    /// Start with `text` already typed.
+    pub fn initial(mut self, text: &str) -> Self {
+        self.initial = text.to_string();
+        self
+    }
+
+    /// Complete paths relative to `base` with Tab.
+    pub fn complete_paths(self, base: &Path) -> Self {
+        let base = base.to_path_buf();
+        self.complete_with(move |_, text| path_completions(&base, text))
+    }
+
+    /// Complete with Tab using `hook`.
+    pub fn complete_with(mut self, hook: impl Fn(&C, &str) -> (usize, Vec<String>) + 'a) -> Self {
+        self.complete = Some(Box::new(hook));
+        self
+    }
+
+    pub fn on_change(mut self, hook: impl FnMut(&mut C, &str) + 'a) -> Self {
+        self.on_change = Some(Box::new(hook));
+        self
+    }
+
+    pub fn validate(mut self, hook: impl Fn(&C, &str) -> Result<(), String> + 'a) -> Self {
+        self.validate = Some(Box::new(hook));
+        self
+    }
+
+    pub fn kind(&self) -> PromptKind {
+        self.kind
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn allows_empty(&self) -> bool {
+        self.allow_empty
+    }
+
+    pub fn initial_text(&self) -> &str {
+        &self.initial
+    }
+
+    /// Completions for `text`, if the prompt completes anything.
+    pub fn completions(&self, context: &C, text: &str) -> Option<(usize, Vec<String>)> {
+        self.complete.as_ref().map(|hook| hook(context, text))
+    }
+
+    /// Run the `on_change` hook for the text now typed.
+    pub fn changed(&mut self, context: &mut C, text: &str) {
+        if let Some(hook) = &mut self.on_change {
+            hook(context, text);
+        }
+    }
+
+    /// Whether `text` is an acceptable answer, and if not, why.
+    pub fn check(&self, context: &C, text: &str) -> Result<(), String> {
+        match &self.validate {
+            Some(hook) => hook(context, text),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Earlier answers to each kind of prompt, oldest first, kept in the
+/// config directory between sessions. Each line of the file is the kind, a
+/// tab, and the answer.
+#[derive(Default)]
+pub struct History {
+    answers: Vec<(String, Vec<String>)>,
+}
+
+impl History {
+    /// Read the history file; a missing or unreadable one gives an empty
+    /// history.
+    pub fn load() -> Self {
+        let mut history = History::default();
+        let text = config::config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(HISTORY_FILE)).ok())
+            .unwrap_or_default();
+        for line in text.lines() {
+            if let Some((kind, answer)) = line.split_once('\t') {
+                history.list_mut(kind).push(answer.to_string());
+            }
+        }
+        history
+    }
+
+    pub fn answers(&self, kind: PromptKind) -> &[String] {
+        self.answers.iter().find(|(k, _)| k == kind.key()).map_or(&[], |(_, list)| list)
+    }
+
+    /// Remember `answer` as the latest for `kind`, dropping any earlier
+    /// copy, and write the history file. Failing to write it only loses
+    /// the history.
+    pub fn add(&mut self, kind: PromptKind, answer: &str) {
+        if answer.trim().is_empty() {
+            return;
+        }
+        let list = self.list_mut(kind.key());
+        list.retain(|a| a != answer);
+        list.push(answer.to_string());
+        if list.len() > MAX_HISTORY {
+            list.drain(..list.len() - MAX_HISTORY);
+        }
+        let _ = self.save();
+    }
+
+    fn list_mut(&mut self, kind: &str) -> &mut Vec<String> {
+        let index = match self.answers.iter().position(|(k, _)| k == kind) {
+            Some(index) => index,
+            None => {
+                self.answers.push((kind.to_string(), Vec::new()));
+                self.answers.len() - 1
+            },
+        };
+        &mut self.answers[index].1
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let dir = config::config_dir().ok_or(io::ErrorKind::NotFound)?;
+        fs::create_dir_all(&dir)?;
+        let mut text = String::new();
+        for (kind, list) in &self.answers {
+            for answer in list {
+                text.push_str(&format!("{}\t{}\n", kind, answer));
+            }
+        }
+        fs::write(dir.join(HISTORY_FILE), text)
+    }
+}
+
+/// Going back through earlier answers with Up and Down, keeping what was
+/// being typed to come back to.
+pub struct HistoryWalk {
+    answers: Vec<String>,
+    /// Answer shown; `answers.len()` for the line being typed.
+    position: usize,
+    draft: String,
+}
+
+impl HistoryWalk {
+    pub fn new(answers: &[String]) -> Self {
+        HistoryWalk { answers: answers.to_vec(), position: answers.len(), draft: String::new() }
+    }
+
+    /// Show the previous (`-1`) or next (`1`) answer in `input`.
+    pub fn step(&mut self, input: &mut LineInput, delta: isize) {
+        let Some(position) = self.position.checked_add_signed(delta).filter(|&p| p <= self.answers.len()) else {
+            return;
+        };
+        if self.position == self.answers.len() {
+            self.draft = input.text().to_string();
+        }
+        self.position = position;
+        input.set(self.answers.get(position).unwrap_or(&self.draft));
+    }
+}
+
+/// Text typed at a prompt and the cursor within it.
+#[derive(Default)]
+pub struct LineInput {
+    text: String,
+    /// Byte offset of the cursor in `text`.
+    cursor: usize,
+}
+
+impl LineInput {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Characters before the cursor, i.e. its column on screen.
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.cursor].chars().count()
+    }
+
+    /// Replace the text, leaving the cursor at the end.
+    pub fn set(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    pub fn insert(&mut self, ch: char) {
+        self.text.insert(self.cursor, ch);
+        self.cursor += ch.len_utf8();
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(ch) = self.text[..self.cursor].chars().next_back() {
+            self.cursor -= ch.len_utf8();
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.text.len() {
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if let Some(ch) = self.text[..self.cursor].chars().next_back() {
+            self.cursor -= ch.len_utf8();
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(ch) = self.text[self.cursor..].chars().next() {
+            self.cursor += ch.len_utf8();
+        }
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    pub fn move_word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    pub fn move_word_right(&mut self) {
+        let after = &self.text[self.cursor..];
+        let rest = after.trim_start_matches(|c| !is_word_char(c)).trim_start_matches(is_word_char);
+        self.cursor = self.text.len() - rest.len();
+    }
+
+    /// Delete the word before the cursor, and anything between it and the
+    /// cursor.
+    pub fn delete_word_before(&mut self) {
+        let start = self.word_start();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn delete_to_start(&mut self) {
+        self.text.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    pub fn delete_to_end(&mut self) {
+        self.text.truncate(self.cursor);
+    }
+
+    /// Start of the word before the cursor, passing over anything else
+    /// first.
+    fn word_start(&self) -> usize {
+        let before = &self.text[..self.cursor];
+        before.trim_end_matches(|c| !is_word_char(c)).trim_end_matches(is_word_char).len()
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Tab completion at a prompt: the candidates listed when more than one
+/// fits, which one is picked, and what was typed before picking.
+#[derive(Default)]
+pub struct Completion {
+    candidates: Vec<String>,
+    picked: Option<usize>,
+    typed: String,
+    /// Where in `typed` the part being completed starts.
+    start: usize,
+}
+
+impl Completion {
+    /// Whether candidates are being listed.
+    pub fn is_open(&self) -> bool {
+        !self.candidates.is_empty()
+    }
+
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    pub fn picked(&self) -> Option<usize> {
+        self.picked
+    }
+
+    /// Characters typed before the part being completed, where the list of
+    /// candidates lines up.
+    pub fn name_column(&self) -> usize {
+        self.typed[..self.start].chars().count()
+    }
+
+    /// Complete the text in `input` from byte `start` on with one of
+    /// `candidates`: a single one is filled in, otherwise as much as they
+    /// all share and they are listed.
+    pub fn offer(&mut self, input: &mut LineInput, start: usize, candidates: Vec<String>) {
+        let typed = &input.text()[..start.min(input.text().len())];
+        match candidates.as_slice() {
+            [] => {},
+            [only] => input.set(&format!("{}{}", typed, only)),
+            _ => {
+                let text = format!("{}{}", typed, common_prefix(&candidates));
+                self.start = typed.len();
+                input.set(&text);
+                self.typed = text;
+                self.candidates = candidates;
+                self.picked = None;
+            },
+        }
+    }
+
+    /// Pick the next (`1`) or previous (`-1`) candidate and put it in
+    /// `input`.
+    pub fn step(&mut self, input: &mut LineInput, delta: isize) {
+        if !self.is_open() {
+            return;
+        }
+        let count = self.candidates.len() as isize;
+        let next = match self.picked {
+            Some(i) => (i as isize + delta).rem_euclid(count),
+            None if delta < 0 => count - 1,
+            None => 0,
+        } as usize;
+        self.picked = Some(next);
+        input.set(&format!("{}{}", &self.typed[..self.start], self.candidates[next]));
+    }
+
+    /// Stop listing candidates, keeping the one picked or, with `restore`,
+    /// going back to what was typed.
+    pub fn close(&mut self, input: &mut LineInput, restore: bool) {
+        if restore && self.is_open() {
+            input.set(&self.typed);
+        }
+        self.candidates.clear();
+        self.picked = None;
+    }
+}
+
+/// Completions of the path `typed`, relative to `base` unless absolute:
+/// where its last component starts, and the names in its directory that
+/// could finish it.
+pub fn path_completions(base: &Path, typed: &str) -> (usize, Vec<String>) {
+    (last_component_start(typed), path_candidates(base, typed))
+}
+
+/// Names in the directory of `typed` (a path relative to `base`, or
+/// absolute) that start with its last component, sorted, with directories
+/// ending in `/`. Hidden entries are only offered once a `.` is typed.
+fn path_candidates(base: &Path, typed: &str) -> Vec<String> {
+    let (dir, partial) = typed.split_at(last_component_start(typed));
+    let dir = if dir.is_empty() { base.to_path_buf() } else { base.join(dir) };
+
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(partial) || (name.starts_with('.') && !partial.starts_with('.')) {
+                return None;
+            }
+            // Follows links, so a link to a directory completes like one
+            Some(if entry.path().is_dir() { name + "/" } else { name })
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// The longest start all of `names` share.
+fn common_prefix(names: &[String]) -> &str {
+    let Some(first) = names.first() else { return "" };
+    names[1..].iter().fold(first.as_str(), |acc, name| {
+        let len = acc.char_indices()
+            .zip(name.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map(|((i, a), _)| i + a.len_utf8())
+            .unwrap_or(0);
+        &acc[..len]
+    })
+}
+
+fn last_component_start(typed: &str) -> usize {
+    typed.rfind(['/', '\\']).map_or(0, |i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` typed with the cursor moved back `left` characters.
+    fn typed(text: &str, left: usize) -> LineInput {
+        let mut input = LineInput::default();
+        text.chars().for_each(|ch| input.insert(ch));
+        (0..left).for_each(|_| input.move_left());
+        input
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut input = typed("héllo", 2);
+        input.insert('X');
+        assert_eq!((input.text(), input.cursor_column()), ("hélXlo", 4));
+        input.backspace();
+        input.backspace();
+        assert_eq!((input.text(), input.cursor_column()), ("hélo", 2));
+        input.delete();
+        assert_eq!(input.text(), "héo");
+        input.move_end();
+        input.delete();
+        input.move_right();
+        assert_eq!((input.text(), input.cursor_column()), ("héo", 3));
+        input.move_home();
+        input.backspace();
+        input.move_left();
+        assert_eq!((input.text(), input.cursor_column()), ("héo", 0));
+    }
+
+    #[test]
+    fn deletes_words_and_to_either_end() {
+        let mut input = typed("cp src/main.rs  ", 0);
+        input.delete_word_before();
+        assert_eq!(input.text(), "cp src/main.");
+        input.delete_word_before();
+        assert_eq!(input.text(), "cp src/");
+
+        let mut input = typed("one two three", 5);
+        input.delete_to_end();
+        assert_eq!(input.text(), "one two ");
+        let mut input = typed("one two three", 5);
+        input.delete_to_start();
+        assert_eq!((input.text(), input.cursor_column()), ("three", 0));
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let mut input = typed("let x_1 = (a, b);", 0);
+        input.move_word_left();
+        assert_eq!(input.cursor_column(), 14);
+        input.move_word_left();
+        input.move_word_left();
+        assert_eq!(input.cursor_column(), 4);
+        input.move_word_right();
+        assert_eq!(input.cursor_column(), 7);
+        input.move_word_right();
+        assert_eq!(input.cursor_column(), 12);
+        input.move_home();
+        input.move_word_left();
+        assert_eq!(input.cursor_column(), 0);
+    }
+
+    #[test]
+    fn walks_back_through_history_and_returns_to_the_draft() {
+        let answers = [String::from("first"), String::from("second")];
+        let mut walk = HistoryWalk::new(&answers);
+        let mut input = typed("draft", 0);
+
+        walk.step(&mut input, 1);
+        assert_eq!(input.text(), "draft");
+        walk.step(&mut input, -1);
+        assert_eq!(input.text(), "second");
+        walk.step(&mut input, -1);
+        walk.step(&mut input, -1);
+        assert_eq!(input.text(), "first");
+        walk.step(&mut input, 1);
+        walk.step(&mut input, 1);
+        assert_eq!((input.text(), input.cursor_column()), ("draft", 5));
+
+        let mut empty = HistoryWalk::new(&[]);
+        empty.step(&mut input, -1);
+        assert_eq!(input.text(), "draft");
+    }
+
+    #[test]
+    fn completes_the_shared_start_then_cycles_candidates() {
+        let mut input = typed("open src/ma", 0);
+        let mut completion = Completion::default();
+        completion.offer(&mut input, 9, vec![String::from("main.rs"), String::from("mailbox.rs")]);
+        assert_eq!((input.text(), completion.name_column()), ("open src/mai", 9));
+
+        completion.step(&mut input, -1);
+        assert_eq!(input.text(), "open src/mailbox.rs");
+        completion.step(&mut input, 1);
+        assert_eq!(input.text(), "open src/main.rs");
+        completion.close(&mut input, true);
+        assert_eq!(input.text(), "open src/mai");
+        assert!(!completion.is_open());
+
+        completion.offer(&mut input, 9, vec![String::from("main.rs")]);
+        assert_eq!(input.text(), "open src/main.rs");
+        assert!(!completion.is_open());
+    }
+}
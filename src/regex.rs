@@ -0,0 +1,634 @@
+//! A small regular expression engine for searching text line by line.
+//!
+//! Patterns compile to a program for a Pike VM, which runs every possible
+//! match in lock step instead of backtracking, so matching time stays
+//! linear in the length of the line. Supported syntax: literals, `.`,
+//! classes like `[a-z_]` and `[^0-9]`, `\d \w \s` and their negations
+//! `\D \W \S`, anchors `^ $ \b \B`, groups `(...)` and `(?:...)`,
+//! alternation `|`, and the quantifiers `* + ? {n} {n,} {n,m}`, each lazy
+//! when followed by `?`.
+
+/// Repetition counts above this are refused.
+const MAX_REPEAT: usize = 1000;
+
+/// Compiled programs above this many instructions are refused.
+const MAX_PROGRAM: usize = 10_000;
+
+#[derive(Clone, Debug)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn new(ranges: &[(char, char)], negated: bool) -> Self {
+        Class { ranges: ranges.to_vec(), negated }
+    }
+
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        let contains = |c: char| self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        let found = contains(c)
+            || (ignore_case && (contains(to_lower(c)) || c.to_uppercase().next().is_some_and(contains)));
+        found != self.negated
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+#[derive(Clone, Copy, Debug)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn holds(self, text: &str, pos: usize) -> bool {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let boundary = || is_word(text[..pos].chars().next_back()) != is_word(text[pos..].chars().next());
+        match self {
+            Assertion::LineStart => pos == 0,
+            Assertion::LineEnd => pos == text.len(),
+            Assertion::WordBoundary => boundary(),
+            Assertion::NotWordBoundary => !boundary(),
+        }
+    }
+}
+
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// A group, with its capture number unless it is `(?:...)`.
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize>, greedy: bool },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.eat('|') {
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 { branches.remove(0) } else { Node::Alternate(branches) })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            items.push(self.repeat()?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.remove(0),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Node, String> {
+        let mut node = self.atom()?;
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.counts() {
+                    Some(counts) => counts,
+                    // Not a valid count, so a plain brace
+                    None => break,
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                },
+                _ => break,
+            };
+            if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+                return Err(format!("repetition count above {}", MAX_REPEAT));
+            }
+            if max.is_some_and(|max| max < min) {
+                return Err(String::from("repetition range is backwards"));
+            }
+            let greedy = !self.eat('?');
+            node = Node::Repeat { node: Box::new(node), min, max, greedy };
+        }
+        Ok(node)
+    }
+
+    /// `{n}`, `{n,}` or `{n,m}` at the current position. Consumes it only if
+    /// it is well formed.
+    fn counts(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let close = rest.find('}')?;
+        let inner = &rest[..close];
+        let number = |s: &str| if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) { None } else { s.parse().ok() };
+
+        let counts = match inner.split_once(',') {
+            None => {
+                let n = number(inner)?;
+                (n, Some(n))
+            },
+            Some((min, "")) => (number(min)?, None),
+            Some((min, max)) => (number(min)?, Some(number(max)?)),
+        };
+        self.pos += inner.chars().count() + 2;
+        Some(counts)
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.next().ok_or("unexpected end of pattern")?;
+        Ok(match c {
+            '(' => {
+                let capture = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(String::from("only (?:...) groups are supported"));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let inner = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(String::from("missing )"));
+                }
+                Node::Group(Box::new(inner), capture)
+            },
+            '[' => Node::Class(self.class()?),
+            '.' => Node::Any,
+            '^' => Node::Assert(Assertion::LineStart),
+            '$' => Node::Assert(Assertion::LineEnd),
+            '*' | '+' | '?' => return Err(format!("nothing before {} to repeat", c)),
+            '\\' => {
+                let c = self.next().ok_or("pattern ends with \\")?;
+                match c {
+                    'd' => Node::Class(Class::new(DIGIT, false)),
+                    'D' => Node::Class(Class::new(DIGIT, true)),
+                    'w' => Node::Class(Class::new(WORD, false)),
+                    'W' => Node::Class(Class::new(WORD, true)),
+                    's' => Node::Class(Class::new(SPACE, false)),
+                    'S' => Node::Class(Class::new(SPACE, true)),
+                    'b' => Node::Assert(Assertion::WordBoundary),
+                    'B' => Node::Assert(Assertion::NotWordBoundary),
+                    _ => Node::Char(escaped_char(c)?),
+                }
+            },
+            c => Node::Char(c),
+        })
+    }
+
+    /// The rest of a `[...]` class after the opening bracket.
+    fn class(&mut self) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+
+        loop {
+            let c = self.next().ok_or("missing ]")?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let start = if c == '\\' {
+                let c = self.next().ok_or("missing ]")?;
+                match c {
+                    'd' => { ranges.extend_from_slice(DIGIT); continue },
+                    'w' => { ranges.extend_from_slice(WORD); continue },
+                    's' => { ranges.extend_from_slice(SPACE); continue },
+                    'D' | 'W' | 'S' => return Err(format!("\\{} is not supported inside [...]", c)),
+                    _ => escaped_char(c)?,
+                }
+            } else {
+                c
+            };
+
+            // A range, unless the dash is last in the class
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let end = match self.next().ok_or("missing ]")? {
+                    '\\' => escaped_char(self.next().ok_or("missing ]")?)?,
+                    c => c,
+                };
+                if end < start {
+                    return Err(format!("range {}-{} is backwards", start, end));
+                }
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+        Ok(Class { ranges, negated })
+    }
+}
+
+/// The character written as `\c`.
+fn escaped_char(c: char) -> Result<char, String> {
+    match c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        c if c.is_ascii_digit() => Err(String::from("backreferences are not supported")),
+        c if c.is_alphanumeric() => Err(format!("unknown escape \\{}", c)),
+        c => Ok(c),
+    }
+}
+
+fn to_lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    /// Try both, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    /// Record the position in a capture slot.
+    Save(usize),
+    Match,
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM {
+        return Err(String::from("pattern is too large"));
+    }
+
+    match node {
+        Node::Empty => {},
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Assert(assertion) => program.push(Inst::Assert(*assertion)),
+        Node::Group(inner, None) => compile(inner, program)?,
+        Node::Group(inner, Some(index)) => {
+            program.push(Inst::Save(2 * index));
+            compile(inner, program)?;
+            program.push(Inst::Save(2 * index + 1));
+        },
+        Node::Concat(items) => {
+            for item in items {
+                compile(item, program)?;
+            }
+        },
+        Node::Alternate(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 < branches.len() {
+                    let split = program.len();
+                    program.push(Inst::Match);
+                    compile(branch, program)?;
+                    jumps.push(program.len());
+                    program.push(Inst::Match);
+                    program[split] = Inst::Split(split + 1, program.len());
+                } else {
+                    compile(branch, program)?;
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        },
+        Node::Repeat { node, min, max, greedy } => {
+            let choice = |body: usize, skip: usize| if *greedy { Inst::Split(body, skip) } else { Inst::Split(skip, body) };
+
+            for _ in 0..*min {
+                compile(node, program)?;
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Match);
+                    compile(node, program)?;
+                    program.push(Inst::Jump(split));
+                    program[split] = choice(split + 1, program.len());
+                },
+                Some(max) => {
+                    // Each optional copy may skip straight past the rest
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Match);
+                        compile(node, program)?;
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = choice(split + 1, end);
+                    }
+                },
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Threads waiting at one position, in priority order, each with its
+/// capture slots.
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    /// Generation in which each instruction was last added, so every
+    /// instruction runs at most once per position.
+    seen: Vec<usize>,
+    generation: usize,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Threads { list: Vec::new(), seen: vec![0; size], generation: 1 }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.generation += 1;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize,
+    ignore_case: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, String> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, groups: 0 };
+        let node = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(String::from("unmatched )"));
+        }
+
+        let mut program = Vec::new();
+        compile(&node, &mut program)?;
+        program.push(Inst::Match);
+        Ok(Regex { program, groups: parser.groups, ignore_case })
+    }
+
+    /// Byte range of the first match in `text` starting at or after `start`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        let slots = self.captures_at(text, start)?;
+        Some((slots[0]?, slots[1]?))
+    }
+
+    /// Like `find_at`, but with the start and end of every group as well:
+    /// slots `2n` and `2n + 1` belong to group `n`, group 0 being the whole
+    /// match. Groups that took no part in the match are `None`.
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let slots = 2 * (self.groups + 1);
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            // A new attempt starting here ranks below all earlier ones
+            if matched.is_none() {
+                let mut caps = vec![None; slots];
+                caps[0] = Some(pos);
+                self.add_thread(&mut current, 0, caps, text, pos);
+            }
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+
+            let ch = text[pos..].chars().next();
+            let next_pos = pos + ch.map_or(0, char::len_utf8);
+            for (pc, mut caps) in current.list.drain(..) {
+                let advance = match &self.program[pc] {
+                    Inst::Match => {
+                        caps[1] = Some(pos);
+                        matched = Some(caps);
+                        // Lower priority threads can't win any more
+                        break;
+                    },
+                    Inst::Char(c) => ch.is_some_and(|ch| ch == *c || (self.ignore_case && to_lower(ch) == to_lower(*c))),
+                    Inst::Any => ch.is_some_and(|ch| ch != '\n'),
+                    Inst::Class(class) => ch.is_some_and(|ch| class.matches(ch, self.ignore_case)),
+                    _ => false,
+                };
+                if advance {
+                    self.add_thread(&mut next, pc + 1, caps, text, next_pos);
+                }
+            }
+
+            if ch.is_none() {
+                break;
+            }
+            pos = next_pos;
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        matched
+    }
+
+    /// Add the thread at `pc`, following jumps, splits, saves and
+    /// assertions through to the instructions that consume a character.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, caps: Vec<Option<usize>>, text: &str, pos: usize) {
+        let mut stack = vec![(pc, caps)];
+        while let Some((pc, mut caps)) = stack.pop() {
+            if threads.seen[pc] == threads.generation {
+                continue;
+            }
+            threads.seen[pc] = threads.generation;
+
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push((*to, caps)),
+                Inst::Split(first, second) => {
+                    // Pushed in reverse so `first` is explored first
+                    stack.push((*second, caps.clone()));
+                    stack.push((*first, caps));
+                },
+                Inst::Save(slot) => {
+                    caps[*slot] = Some(pos);
+                    stack.push((pc + 1, caps));
+                },
+                Inst::Assert(assertion) => {
+                    if assertion.holds(text, pos) {
+                        stack.push((pc + 1, caps));
+                    }
+                },
+                _ => threads.list.push((pc, caps)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first match of `pattern` in `text`, as text.
+    fn find<'a>(pattern: &str, text: &'a str) -> Option<&'a str> {
+        let (start, end) = Regex::new(pattern, false).unwrap().find_at(text, 0)?;
+        Some(&text[start..end])
+    }
+
+    fn error(pattern: &str) -> String {
+        Regex::new(pattern, false).err().unwrap_or_else(|| panic!("{:?} compiled", pattern))
+    }
+
+    #[test]
+    fn literals_and_dot() {
+        assert_eq!(find("abc", "xxabcxx"), Some("abc"));
+        assert_eq!(find("a.c", "abc a-c"), Some("abc"));
+        assert_eq!(find("abc", "ab"), None);
+        assert_eq!(find(r"a\.c", "abc a.c"), Some("a.c"));
+        assert_eq!(find(r"\t", "a\tb"), Some("\t"));
+    }
+
+    #[test]
+    fn leftmost_match_wins_then_first_alternative() {
+        assert_eq!(find("b|ab", "xab"), Some("ab"));
+        assert_eq!(find("a|ab", "ab"), Some("a"));
+        assert_eq!(find("ab|a", "ab"), Some("ab"));
+    }
+
+    #[test]
+    fn quantifiers_are_greedy_unless_lazy() {
+        assert_eq!(find("a+", "baaab"), Some("aaa"));
+        assert_eq!(find("a+?", "baaab"), Some("a"));
+        assert_eq!(find("<.*>", "<a><b>"), Some("<a><b>"));
+        assert_eq!(find("<.*?>", "<a><b>"), Some("<a>"));
+        assert_eq!(find("colou?r", "color"), Some("color"));
+        assert_eq!(find(r"\d{2,3}", "1 12345"), Some("123"));
+        assert_eq!(find(r"\d{2}", "1 12345"), Some("12"));
+        assert_eq!(find(r"\d{2,}", "1 12345"), Some("12345"));
+    }
+
+    #[test]
+    fn malformed_counts_are_plain_braces() {
+        assert_eq!(find("a{", "a{"), Some("a{"));
+        assert_eq!(find("a{x}", "a{x}"), Some("a{x}"));
+        assert_eq!(find("a{,2}", "a{,2}"), Some("a{,2}"));
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(find("[a-c]+", "xxcabz"), Some("cab"));
+        assert_eq!(find("[^0-9]+", "12ab3"), Some("ab"));
+        assert_eq!(find(r"[\d_]+", "ab1_2c"), Some("1_2"));
+        assert_eq!(find("[]a]+", "x]a]"), Some("]a]"));
+        assert_eq!(find("[a-]+", "x-a-"), Some("-a-"));
+        assert_eq!(find(r"\w+", "  foo_1 "), Some("foo_1"));
+        assert_eq!(find(r"\W+", "foo, bar"), Some(", "));
+        assert_eq!(find(r"\s+", "a \t b"), Some(" \t "));
+        assert_eq!(find(r"\S+", "  ab "), Some("ab"));
+        assert_eq!(find(r"\D+", "12ab3"), Some("ab"));
+        assert_eq!(find("[é-ë]", "caféine"), Some("é"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(find("^ab", "abab"), Some("ab"));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("b$", "bab"), Some("b"));
+        assert_eq!(find("a$", "ab"), None);
+        assert_eq!(find(r"\bcat\b", "concat cat"), Some("cat"));
+        assert_eq!(find(r"\bcat\b", "concat"), None);
+        assert_eq!(find(r"\Bcat", "concat"), Some("cat"));
+        assert_eq!(find(r"\bé", "xé é"), Some("é"));
+
+        let regex = Regex::new("^a", false).unwrap();
+        assert_eq!(regex.find_at("aa", 1), None);
+    }
+
+    #[test]
+    fn groups_capture_their_text() {
+        let regex = Regex::new(r"(\w+)@(\w+)(x)?", false).unwrap();
+        let text = "mail bob@example now";
+        let slots = regex.captures_at(text, 0).unwrap();
+        assert_eq!(slots.len(), 8);
+        assert_eq!((slots[2], slots[3]), (Some(5), Some(8)));
+        assert_eq!((slots[4], slots[5]), (Some(9), Some(16)));
+        assert_eq!((slots[6], slots[7]), (None, None));
+
+        let regex = Regex::new("(?:ab)+(c)", false).unwrap();
+        assert_eq!(regex.captures_at("ababc", 0).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn case_folding() {
+        let regex = Regex::new("straße", true).unwrap();
+        assert_eq!(regex.find_at("STRAßE", 0), Some((0, 7)));
+        let regex = Regex::new("[a-z]+", true).unwrap();
+        assert_eq!(regex.find_at("12ABc", 0), Some((2, 5)));
+        let regex = Regex::new("ÉTÉ", true).unwrap();
+        assert_eq!(regex.find_at("un été", 0), Some((3, 8)));
+        assert_eq!(find("abc", "ABC"), None);
+    }
+
+    #[test]
+    fn empty_matches() {
+        assert_eq!(find("a*", "bbb"), Some(""));
+        assert_eq!(find("", "abc"), Some(""));
+        assert_eq!(find("^$", ""), Some(""));
+
+        let regex = Regex::new("x*", false).unwrap();
+        assert_eq!(regex.find_at("abc", 3), Some((3, 3)));
+        assert_eq!(regex.find_at("aé", 1), Some((1, 1)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error("(ab"), "missing )");
+        assert_eq!(error("ab)"), "unmatched )");
+        assert_eq!(error("[ab"), "missing ]");
+        assert_eq!(error("*a"), "nothing before * to repeat");
+        assert_eq!(error("a|+"), "nothing before + to repeat");
+        assert_eq!(error("ab\\"), "pattern ends with \\");
+        assert_eq!(error(r"\q"), "unknown escape \\q");
+        assert_eq!(error(r"(a)\1"), "backreferences are not supported");
+        assert_eq!(error("(?=a)"), "only (?:...) groups are supported");
+        assert_eq!(error("[z-a]"), "range z-a is backwards");
+        assert_eq!(error("a{3,2}"), "repetition range is backwards");
+        assert_eq!(error("a{1001}"), "repetition count above 1000");
+        assert_eq!(error(r"[\W]"), "\\W is not supported inside [...]");
+        assert_eq!(error("(a{1000}){1000}"), "pattern is too large");
+    }
+
+    #[test]
+    fn long_lines_match_without_backtracking() {
+        let text = "a".repeat(5000);
+        assert_eq!(find("(a*)*b", &text), None);
+        assert_eq!(find("(a|aa)+$", &text).map(str::len), Some(5000));
+    }
+}
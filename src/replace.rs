@@ -0,0 +1,522 @@
+//! Project-wide replace: working out the new text of the lines holding the
+//! chosen search hits, choosing which hits to replace with a diff of each
+//! file, and writing the changed files all or nothing.
+
+use crate::diff;
+use crate::editor::Editor;
+use crate::encoding::{self, Encoding};
+use crate::list::{ListKey, ListNav};
+use crate::prompt::{Prompt, PromptKind};
+use crate::search::{self, Pattern, SearchHit};
+use crate::terminal::Terminal;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A file that isn't open, as read from disk for a replace. Its lines are
+/// split as the search splits them, with each line's ending kept, so it
+/// can be written back unchanged apart from the replaced lines.
+pub struct FileText {
+    encoding: Encoding,
+    /// Each line and its ending: `\n`, `\r\n`, or nothing on a last line
+    /// without one.
+    lines: Vec<(String, &'static str)>,
+}
+
+impl FileText {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let (text, encoding) = encoding::decode_file(&bytes)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::InvalidData, "binary file")))?;
+        let lines = text.split_inclusive('\n')
+            .map(|line| {
+                if let Some(line) = line.strip_suffix("\r\n") {
+                    (line.to_string(), "\r\n")
+                } else if let Some(line) = line.strip_suffix('\n') {
+                    (line.to_string(), "\n")
+                } else {
+                    (line.to_string(), "")
+                }
+            })
+            .collect();
+        Ok(FileText { encoding, lines })
+    }
+
+    pub fn line(&self, row: usize) -> Option<&str> {
+        self.lines.get(row).map(|(line, _)| line.as_str())
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|(line, _)| line.as_str())
+    }
+
+    /// Put in the new text of the lines in `changes`, as `(row, text)`.
+    /// Their line endings stay as they were.
+    pub fn apply(&mut self, changes: Vec<(usize, String)>) {
+        for (row, text) in changes {
+            if let Some((line, _)) = self.lines.get_mut(row) {
+                *line = text;
+            }
+        }
+    }
+
+    /// The file's bytes, in the encoding it was read in.
+    pub fn encoded(&self) -> io::Result<Vec<u8>> {
+        let text: String = self.lines.iter().flat_map(|(line, ending)| [line.as_str(), ending]).collect();
+        self.encoding.encode(&text)
+    }
+}
+
+/// New text for each line of one file that holds any of `hits`, as
+/// `(row, text)`, with every hit replaced. `line_at` gives the file's
+/// current lines. Hits on lines that no longer read as they did when the
+/// search ran are left alone; their number is returned as well.
+pub fn plan<'a>(
+    pattern: &Pattern,
+    replacement: &str,
+    hits: &[&SearchHit],
+    line_at: impl Fn(usize) -> Option<&'a str>,
+) -> (Vec<(usize, String)>, usize) {
+    let mut hits = hits.to_vec();
+    hits.sort_by_key(|hit| (hit.line, hit.start));
+
+    let mut changes = Vec::new();
+    let mut stale = 0;
+    for same_line in hits.chunk_by(|a, b| a.line == b.line) {
+        let first = same_line[0];
+        if line_at(first.line) != Some(first.text.as_str()) {
+            stale += same_line.len();
+            continue;
+        }
+
+        let mut text = String::with_capacity(first.text.len());
+        let mut copied = 0;
+        for hit in same_line {
+            text.push_str(&first.text[copied..hit.start]);
+            text.push_str(&pattern.expand(&first.text, hit.start, replacement));
+            copied = hit.end;
+        }
+        text.push_str(&first.text[copied..]);
+        changes.push((first.line, text));
+    }
+    (changes, stale)
+}
+
+/// Write every `(path, contents)` pair. Everything is first written to a
+/// temporary file beside its target, and only once all of them are ready
+/// are they renamed over the targets, so an error while preparing leaves
+/// every file untouched and no file is ever seen half written. Each
+/// original is kept until every rename has succeeded; if one fails, those
+/// already renamed are put back. The error names any file that couldn't
+/// be put back, and where its original was left.
+pub fn write_all(files: &[(PathBuf, Vec<u8>)]) -> io::Result<()> {
+    let mut staged: Vec<(PathBuf, &Path)> = Vec::with_capacity(files.len());
+
+    for (path, contents) in files {
+        let temp = temp_path(path, "tmp");
+        let written = fs::write(&temp, contents).and_then(|()| {
+            // Keep the original's permissions
+            let permissions = fs::metadata(path)?.permissions();
+            fs::set_permissions(&temp, permissions)
+        });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            for (temp, _) in &staged {
+                let _ = fs::remove_file(temp);
+            }
+            return Err(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)));
+        }
+        staged.push((temp, path));
+    }
+
+    // Originals of the files renamed over so far
+    let mut backups: Vec<(&Path, PathBuf)> = Vec::with_capacity(staged.len());
+    let mut failure = None;
+    for (temp, path) in &staged {
+        let backup = temp_path(path, "orig");
+        let renamed = keep_original(path, &backup).and_then(|()| {
+            fs::rename(temp, path).inspect_err(|_| {
+                let _ = fs::remove_file(&backup);
+            })
+        });
+        match renamed {
+            Ok(()) => backups.push((path, backup)),
+            Err(e) => {
+                failure = Some(io::Error::new(e.kind(), format!("{}: {}", path.display(), e)));
+                break;
+            },
+        }
+    }
+
+    let Some(error) = failure else {
+        for (_, backup) in &backups {
+            let _ = fs::remove_file(backup);
+        }
+        return Ok(());
+    };
+    for (temp, _) in &staged {
+        let _ = fs::remove_file(temp);
+    }
+    let stuck: Vec<String> = backups.iter()
+        .filter(|(path, backup)| fs::rename(backup, path).is_err())
+        .map(|(path, backup)| format!("{} (original kept as {})", path.display(), backup.display()))
+        .collect();
+    if stuck.is_empty() {
+        return Err(error);
+    }
+    Err(io::Error::new(error.kind(), format!("{}; already written and not put back: {}", error, stuck.join(", "))))
+}
+
+/// Keep the file at `path` as `backup` without moving it: a hard link if
+/// the file system has them, a copy otherwise.
+fn keep_original(path: &Path, backup: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(backup);
+    fs::hard_link(path, backup).or_else(|_| fs::copy(path, backup).map(|_| ()))
+}
+
+/// Hidden file beside `path`, ending in `extension`, so renaming it over
+/// `path` stays within one file system.
+fn temp_path(path: &Path, extension: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.text_editor-{}.{}", name, process::id(), extension))
+}
+
+/// Where a project-wide replace makes its changes to one file.
+enum ReplaceTarget {
+    /// The file's open buffer, by index in the buffer list.
+    Open(usize),
+    /// The file read from disk, to be written back.
+    Closed(FileText),
+}
+
+impl Editor {
+    /// Ask for the replacement text, then let the user choose which of the
+    /// search's `hits` to replace, previewing each file's changes as a
+    /// diff. Returns true once the replace was carried out or failed, false
+    /// if the user backed out.
+    pub fn review_replace(&mut self, root: &Path, pattern: &Pattern, query: &str, hits: &[SearchHit]) -> bool {
+        let Some(replacement) = self.run_prompt(Prompt::new(PromptKind::Replace, &format!("Replace '{}' with: ", query)).allow_empty()) else {
+            return false;
+        };
+
+        let (items, marks): (Vec<String>, Vec<(usize, usize)>) = hits.iter().map(search::hit_item).unzip();
+        // Room for the "[x] " in front
+        let marks: Vec<(usize, usize)> = marks.iter().map(|&(from, to)| (from + 4, to + 4)).collect();
+        let mut included = vec![true; hits.len()];
+        let mut list = ListNav::new(self.screen_rows as usize);
+        list.set_len(hits.len());
+
+        loop {
+            let selected = list.selected();
+            let shown: Vec<String> = items.iter()
+                .zip(&included)
+                .map(|(item, &on)| format!("[{}] {}", if on { 'x' } else { ' ' }, item))
+                .collect();
+            self.draw_marked_list(&shown, &marks, selected, list.top());
+
+            let count = included.iter().filter(|&&on| on).count();
+            let status = format!(" Replace '{}' with '{}' - {} of {} matches", query, replacement, count, hits.len());
+            let _ = Terminal::move_cursor(self.screen_rows, 0);
+            self.draw_overlay_bars(&status, "Space include/exclude | a whole file | d diff | Enter replace | Esc back");
+
+            let Ok(byte) = self.read_byte() else { return false };
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape | ListKey::Byte(b'q') => return false,
+                ListKey::Byte(b' ') => {
+                    included[selected] = !included[selected];
+                    list.step(1);
+                },
+                ListKey::Byte(b'a') => {
+                    let file = &hits[selected].path;
+                    let on = !hits.iter().zip(&included).any(|(hit, &on)| on && hit.path == *file);
+                    for (hit, flag) in hits.iter().zip(included.iter_mut()) {
+                        if hit.path == *file {
+                            *flag = on;
+                        }
+                    }
+                },
+                ListKey::Byte(b'd') => self.show_replace_diff(root, pattern, &replacement, hits, &included, &hits[selected].path),
+                ListKey::Enter => {
+                    let description = format!("replace '{}' with '{}'", query, replacement);
+                    self.apply_replace(root, pattern, &replacement, hits, &included, &description);
+                    return true;
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// The file at `path` as a replace sees it: its open buffer if it has
+    /// one, else read from disk.
+    fn replace_target(&self, path: &Path) -> io::Result<ReplaceTarget> {
+        if let Some(index) = self.buffer_index_of(path) {
+            return Ok(ReplaceTarget::Open(index));
+        }
+        Ok(ReplaceTarget::Closed(FileText::read(path)?))
+    }
+
+    /// Unified diff of what replacing the included hits would do to `file`.
+    fn show_replace_diff(&mut self, root: &Path, pattern: &Pattern, replacement: &str, hits: &[SearchHit], included: &[bool], file: &str) {
+        let chosen: Vec<&SearchHit> = hits.iter()
+            .zip(included)
+            .filter(|(hit, on)| **on && hit.path == file)
+            .map(|(hit, _)| hit)
+            .collect();
+
+        let target = match self.replace_target(&root.join(file)) {
+            Ok(target) => target,
+            Err(e) => {
+                self.show_text_view(&format!("Replace in {}", file), &[format!("Cannot read {}: {}", file, e)]);
+                return;
+            },
+        };
+        let old: Vec<&str> = match &target {
+            ReplaceTarget::Open(index) => {
+                let buffer = self.buffer_at(*index);
+                (0..buffer.line_count()).filter_map(|row| buffer.get_line(row)).collect()
+            },
+            ReplaceTarget::Closed(file) => file.lines().collect(),
+        };
+        let (changes, stale) = plan(pattern, replacement, &chosen, |row| old.get(row).copied());
+        let mut new: Vec<&str> = old.clone();
+        for (row, text) in &changes {
+            new[*row] = text;
+        }
+
+        let ops = diff::diff_lines(&old, &new);
+        let mut lines = diff::unified(&format!("a/{}", file), &format!("b/{}", file), &ops, 3);
+        if lines.is_empty() {
+            lines.push(String::from("No changes: no matches in this file are included"));
+        }
+        if stale > 0 {
+            lines.insert(0, format!("{} matches skipped: their lines changed since the search", stale));
+        }
+        let title = format!("Replace in {}", file);
+        self.show_text_view(&title, &lines);
+    }
+
+    /// Replace the included hits. Open buffers are changed in memory, with
+    /// an undo record; other files are written all or nothing. If any file
+    /// can't be prepared, nothing is changed at all.
+    fn apply_replace(&mut self, root: &Path, pattern: &Pattern, replacement: &str, hits: &[SearchHit], included: &[bool], description: &str) {
+        let chosen: Vec<&SearchHit> = hits.iter().zip(included).filter(|(_, on)| **on).map(|(hit, _)| hit).collect();
+        let mut writes = Vec::new();
+        let mut buffer_changes = Vec::new();
+        let mut replaced = 0;
+        let mut stale = 0;
+        let mut failed = Vec::new();
+
+        // Hits arrive grouped by file
+        for file_hits in chosen.chunk_by(|a, b| a.path == b.path) {
+            let file = &file_hits[0].path;
+            let path = root.join(file);
+            let target = match self.replace_target(&path) {
+                Ok(target) => target,
+                Err(e) => {
+                    failed.push(format!("{}: {}", file, e));
+                    continue;
+                },
+            };
+
+            match target {
+                // Saved along with the other files, so they can't be left
+                // out of step with the buffer
+                ReplaceTarget::Open(index) => {
+                    let buffer = self.buffer_at(index);
+                    let (changes, skipped) = plan(pattern, replacement, file_hits, |row| buffer.get_line(row));
+                    replaced += file_hits.len() - skipped;
+                    stale += skipped;
+                    if changes.is_empty() {
+                        continue;
+                    }
+                    match buffer.encoded_with(&changes) {
+                        Ok(bytes) => {
+                            buffer_changes.push((index, changes, writes.len()));
+                            writes.push((path, bytes));
+                        },
+                        Err(e) => failed.push(format!("{}: {}", file, e)),
+                    }
+                },
+                ReplaceTarget::Closed(mut text) => {
+                    if fs::OpenOptions::new().write(true).open(&path).is_err() {
+                        failed.push(format!("{}: not writable", file));
+                        continue;
+                    }
+                    let (changes, skipped) = plan(pattern, replacement, file_hits, |row| text.line(row));
+                    replaced += file_hits.len() - skipped;
+                    stale += skipped;
+                    if changes.is_empty() {
+                        continue;
+                    }
+                    text.apply(changes);
+                    match text.encoded() {
+                        Ok(bytes) => writes.push((path, bytes)),
+                        Err(e) => failed.push(format!("{}: {}", file, e)),
+                    }
+                },
+            }
+        }
+
+        if !failed.is_empty() {
+            self.show_message(format!("Nothing replaced: {}", failed.join("; ")));
+            return;
+        }
+        if let Err(e) = write_all(&writes) {
+            self.show_message(format!("Nothing replaced: {}", e));
+            return;
+        }
+
+        let changed_buffers = buffer_changes.len();
+        for (index, changes, write) in buffer_changes {
+            let buffer = self.buffer_at_mut(index);
+            buffer.replace_lines(changes, description);
+            buffer.mark_saved(&writes[write].1);
+            self.clamp_views(index);
+        }
+
+        let mut message = format!("Replaced {} matches in {} files", replaced, writes.len());
+        if changed_buffers > 0 {
+            message.push_str(&format!(" ({} open; Ctrl-Z undoes)", changed_buffers));
+        }
+        if stale > 0 {
+            message.push_str(&format!(", {} skipped as their lines changed", stale));
+        }
+        self.show_message(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits(pattern: &Pattern, lines: &[&str]) -> Vec<SearchHit> {
+        lines.iter().enumerate()
+            .flat_map(|(line, text)| pattern.find_all(text).into_iter().map(move |(start, end)| SearchHit {
+                path: String::from("file"),
+                line,
+                start,
+                end,
+                text: text.to_string(),
+            }))
+            .collect()
+    }
+
+    /// An empty directory of its own for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text_editor-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn plan_replaces_every_chosen_hit_on_a_line() {
+        let pattern = Pattern::parse("cat").unwrap();
+        let lines = ["a cat and a cat", "no match", "cat"];
+        let found = hits(&pattern, &lines);
+        let chosen: Vec<&SearchHit> = found.iter().collect();
+        let (changes, stale) = plan(&pattern, "dog", &chosen, |row| lines.get(row).copied());
+        assert_eq!(changes, [(0, String::from("a dog and a dog")), (2, String::from("dog"))]);
+        assert_eq!(stale, 0);
+    }
+
+    #[test]
+    fn plan_leaves_hits_that_werent_chosen() {
+        let pattern = Pattern::parse("cat").unwrap();
+        let lines = ["cat cat cat"];
+        let found = hits(&pattern, &lines);
+        // Out of order, to check they are sorted
+        let chosen = [&found[2], &found[0]];
+        let (changes, _) = plan(&pattern, "dog", &chosen, |row| lines.get(row).copied());
+        assert_eq!(changes, [(0, String::from("dog cat dog"))]);
+    }
+
+    #[test]
+    fn plan_expands_regex_groups() {
+        let pattern = Pattern::parse(r"/(\w+)=(\w+)/").unwrap();
+        let lines = ["a=b, c=d"];
+        let found = hits(&pattern, &lines);
+        let chosen: Vec<&SearchHit> = found.iter().collect();
+        let (changes, _) = plan(&pattern, "$2=$1", &chosen, |row| lines.get(row).copied());
+        assert_eq!(changes, [(0, String::from("b=a, d=c"))]);
+    }
+
+    #[test]
+    fn plan_skips_lines_changed_since_the_search() {
+        let pattern = Pattern::parse("cat").unwrap();
+        let found = hits(&pattern, &["cat cat", "cat"]);
+        let chosen: Vec<&SearchHit> = found.iter().collect();
+        let now = ["cat cat!", "cat"];
+        let (changes, stale) = plan(&pattern, "dog", &chosen, |row| now.get(row).copied());
+        assert_eq!(changes, [(1, String::from("dog"))]);
+        assert_eq!(stale, 2);
+
+        let (changes, stale) = plan(&pattern, "dog", &chosen, |_| None);
+        assert!(changes.is_empty());
+        assert_eq!(stale, 3);
+    }
+
+    #[test]
+    fn file_text_keeps_line_endings_and_encoding() {
+        let dir = temp_dir("replace-file-text");
+        let path = dir.join("mixed.txt");
+        let bytes = b"caf\xe9\r\nplain\nlast".to_vec();
+        fs::write(&path, &bytes).unwrap();
+
+        let mut text = FileText::read(&path).unwrap();
+        assert_eq!(text.lines().collect::<Vec<_>>(), ["café", "plain", "last"]);
+        assert_eq!(text.line(0), Some("café"));
+        assert_eq!(text.encoded().unwrap(), bytes);
+
+        text.apply(vec![(0, String::from("thé")), (2, String::from("end")), (9, String::from("x"))]);
+        assert_eq!(text.encoded().unwrap(), b"th\xe9\r\nplain\nend");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_all_writes_every_file() {
+        let dir = temp_dir("replace-write-all");
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&a, "old a").unwrap();
+        fs::write(&b, "old b").unwrap();
+
+        write_all(&[(a.clone(), b"new a".to_vec()), (b.clone(), b"new b".to_vec())]).unwrap();
+        assert_eq!(fs::read(&a).unwrap(), b"new a");
+        assert_eq!(fs::read(&b).unwrap(), b"new b");
+        // No temporary files or originals are left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_all_changes_nothing_if_a_file_cant_be_prepared() {
+        let dir = temp_dir("replace-write-fail");
+        let a = dir.join("a.txt");
+        fs::write(&a, "old a").unwrap();
+        let missing = dir.join("gone").join("b.txt");
+
+        let error = write_all(&[(a.clone(), b"new a".to_vec()), (missing, b"new b".to_vec())]).unwrap_err();
+        assert!(error.to_string().contains("b.txt"), "{}", error);
+        assert_eq!(fs::read(&a).unwrap(), b"old a");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_all_puts_back_files_already_written_when_a_rename_fails() {
+        let dir = temp_dir("replace-rollback");
+        let a = dir.join("a.txt");
+        fs::write(&a, "old a").unwrap();
+        // A directory can be prepared for, but not kept as an original
+        let b = dir.join("b.txt");
+        fs::create_dir(&b).unwrap();
+
+        assert!(write_all(&[(a.clone(), b"new a".to_vec()), (b.clone(), b"new b".to_vec())]).is_err());
+        assert_eq!(fs::read(&a).unwrap(), b"old a");
+        assert!(b.is_dir());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
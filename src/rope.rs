@@ -0,0 +1,315 @@
+//! Balanced sequence storage for buffer lines.
+//!
+//! A `Rope` is an implicit treap: a binary tree ordered by position, where
+//! every node knows the size of its subtree. Lookups, inserts and removals at
+//! any index are O(log n) expected, instead of the O(n) shifting a `Vec` does
+//! when a line is added near the top of a large file.
+
+use std::cmp::Ordering;
+
+struct Node<T> {
+    value: T,
+    priority: u32,
+    size: usize,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+type Link<T> = Option<Box<Node<T>>>;
+
+fn size<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+impl<T> Node<T> {
+    fn new(value: T, priority: u32) -> Box<Self> {
+        Box::new(Node { value, priority, size: 1, left: None, right: None })
+    }
+
+    fn update_size(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+/// Split `link` into its first `at` elements and the rest.
+fn split<T>(link: Link<T>, at: usize) -> (Link<T>, Link<T>) {
+    let Some(mut node) = link else { return (None, None) };
+
+    let left_size = size(&node.left);
+    if at <= left_size {
+        let (l, r) = split(node.left.take(), at);
+        node.left = r;
+        node.update_size();
+        (l, Some(node))
+    } else {
+        let (l, r) = split(node.right.take(), at - left_size - 1);
+        node.right = l;
+        node.update_size();
+        (Some(node), r)
+    }
+}
+
+/// Concatenate two trees, every element of `a` before every element of `b`.
+fn merge<T>(a: Link<T>, b: Link<T>) -> Link<T> {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(mut a), Some(mut b)) => {
+            if a.priority >= b.priority {
+                a.right = merge(a.right.take(), Some(b));
+                a.update_size();
+                Some(a)
+            } else {
+                b.left = merge(Some(a), b.left.take());
+                b.update_size();
+                Some(b)
+            }
+        }
+    }
+}
+
+pub struct Rope<T> {
+    root: Link<T>,
+    seed: u32,
+}
+
+impl<T> Rope<T> {
+    pub fn new() -> Self {
+        Rope { root: None, seed: 0x9E37_79B9 }
+    }
+
+    /// Build a perfectly balanced tree in O(n).
+    pub fn from_vec(values: Vec<T>) -> Self {
+        fn build<T>(values: &mut std::vec::IntoIter<T>, count: usize, depth: u32) -> Link<T> {
+            if count == 0 {
+                return None;
+            }
+            let left_count = count / 2;
+            let left = build(values, left_count, depth + 1);
+            let value = values.next().expect("count matches the remaining values");
+            let right = build(values, count - left_count - 1, depth + 1);
+
+            // Shallower nodes get higher priorities, keeping the heap order
+            let mut node = Node::new(value, u32::MAX - depth);
+            node.left = left;
+            node.right = right;
+            node.update_size();
+            Some(node)
+        }
+
+        let count = values.len();
+        let mut iter = values.into_iter();
+        let root = build(&mut iter, count, 0);
+        Rope { root, seed: 0x9E37_79B9 }
+    }
+
+    fn next_priority(&mut self) -> u32 {
+        // xorshift32; good enough to keep the tree balanced
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn get(&self, mut index: usize) -> Option<&T> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let left_size = size(&node.left);
+            match index.cmp(&left_size) {
+                Ordering::Less => link = &node.left,
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    link = &node.right;
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, mut index: usize) -> Option<&mut T> {
+        let mut link = &mut self.root;
+        while let Some(node) = link {
+            let left_size = size(&node.left);
+            match index.cmp(&left_size) {
+                Ordering::Less => link = &mut node.left,
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    link = &mut node.right;
+                }
+            }
+        }
+        None
+    }
+
+    /// Insert `value` so it ends up at `index`. Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len(), "insert index out of bounds");
+        let node = Node::new(value, self.next_priority());
+        let (left, right) = split(self.root.take(), index);
+        self.root = merge(merge(left, Some(node)), right);
+    }
+
+    pub fn push(&mut self, value: T) {
+        let len = self.len();
+        self.insert(len, value);
+    }
+
+    /// Remove and return the element at `index`. Panics if out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len(), "remove index out of bounds");
+        let (left, rest) = split(self.root.take(), index);
+        let (middle, right) = split(rest, 1);
+        self.root = merge(left, right);
+        middle.expect("index is in bounds").value
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(&self.root);
+        iter
+    }
+}
+
+impl<T> Default for Rope<T> {
+    fn default() -> Self {
+        Rope::new()
+    }
+}
+
+/// In-order iterator; holds at most O(log n) pending nodes.
+pub struct Iter<'a, T> {
+    stack: Vec<&'a Node<T>>,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn push_left(&mut self, mut link: &'a Link<T>) {
+        while let Some(node) = link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        Some(&node.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the sizes and heap order below `link`, returning its depth.
+    fn check<T>(link: &Link<T>) -> usize {
+        let Some(node) = link else { return 0 };
+        assert_eq!(node.size, 1 + size(&node.left) + size(&node.right));
+        for child in [&node.left, &node.right].into_iter().flatten() {
+            assert!(child.priority <= node.priority);
+        }
+        1 + check(&node.left).max(check(&node.right))
+    }
+
+    fn contents(rope: &Rope<u32>) -> Vec<u32> {
+        rope.iter().copied().collect()
+    }
+
+    #[test]
+    fn matches_a_vec_through_random_edits() {
+        let mut rope = Rope::new();
+        let mut reference: Vec<u32> = Vec::new();
+        let mut seed = 12345u32;
+        let mut random = |below: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % below
+        };
+
+        for step in 0..5000 {
+            if reference.is_empty() || random(3) > 0 {
+                let index = random(reference.len() + 1);
+                rope.insert(index, step);
+                reference.insert(index, step);
+            } else {
+                let index = random(reference.len());
+                assert_eq!(rope.remove(index), reference.remove(index));
+            }
+            assert_eq!(rope.len(), reference.len());
+        }
+
+        assert_eq!(contents(&rope), reference);
+        for (i, value) in reference.iter().enumerate() {
+            assert_eq!(rope.get(i), Some(value));
+        }
+        assert_eq!(rope.get(reference.len()), None);
+        assert!(check(&rope.root) < 64, "tree is badly unbalanced");
+    }
+
+    #[test]
+    fn edits_at_both_ends() {
+        let mut rope = Rope::new();
+        rope.insert(0, 2);
+        rope.insert(0, 1);
+        rope.insert(2, 3);
+        rope.push(4);
+        assert_eq!(contents(&rope), [1, 2, 3, 4]);
+        assert_eq!(rope.remove(0), 1);
+        assert_eq!(rope.remove(2), 4);
+        assert_eq!(contents(&rope), [2, 3]);
+        *rope.get_mut(1).unwrap() = 30;
+        assert_eq!(contents(&rope), [2, 30]);
+        check(&rope.root);
+    }
+
+    #[test]
+    fn empty_rope() {
+        let mut rope: Rope<u32> = Rope::new();
+        assert_eq!(rope.len(), 0);
+        assert_eq!(rope.get(0), None);
+        assert_eq!(rope.get_mut(0), None);
+        assert_eq!(rope.iter().next(), None);
+        assert_eq!(Rope::<u32>::from_vec(Vec::new()).len(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "remove index out of bounds")]
+    fn remove_past_the_end_panics() {
+        let mut rope = Rope::from_vec(vec![1]);
+        rope.remove(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "insert index out of bounds")]
+    fn insert_past_the_end_panics() {
+        let mut rope = Rope::from_vec(vec![1]);
+        rope.insert(2, 2);
+    }
+
+    #[test]
+    fn from_vec_keeps_order_and_balance() {
+        let values: Vec<u32> = (0..1000).collect();
+        let mut rope = Rope::from_vec(values.clone());
+        assert_eq!(contents(&rope), values);
+        assert_eq!(check(&rope.root), 10);
+
+        // Still a valid treap to edit
+        rope.insert(500, 9999);
+        assert_eq!(rope.get(500), Some(&9999));
+        assert_eq!(rope.remove(0), 0);
+        assert_eq!(rope.len(), 1000);
+        check(&rope.root);
+    }
+}
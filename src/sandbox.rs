@@ -0,0 +1,226 @@
+//! Where files may be saved: confinement to a root directory, valid file
+//! names and, optionally, a list of allowed extensions.
+//!
+//! Configured in the `[save]` section of config.ini.
+
+use crate::config;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Longest file name most file systems accept, in bytes.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+enum SaveRoot {
+    /// The directory the editor was started for, once known. Until then
+    /// saving isn't confined.
+    Session(Option<PathBuf>),
+    Fixed(PathBuf),
+    Anywhere,
+}
+
+pub struct SavePolicy {
+    root: SaveRoot,
+    /// Lower-case, without the dot. Empty allows any extension.
+    extensions: Vec<String>,
+}
+
+impl SavePolicy {
+    pub fn load() -> Self {
+        let root = match config::setting("save", "root") {
+            Some(value) if value.trim().eq_ignore_ascii_case("any") => SaveRoot::Anywhere,
+            Some(value) if !value.trim().is_empty() && !value.trim().eq_ignore_ascii_case("session") => {
+                SaveRoot::Fixed(PathBuf::from(value.trim()))
+            },
+            _ => SaveRoot::Session(None),
+        };
+        let extensions = config::setting("save", "extensions")
+            .map(|value| {
+                value.split(',')
+                    .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                    .filter(|ext| !ext.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        SavePolicy { root, extensions }
+    }
+
+    /// The directory the editor was started for, which confines saving
+    /// unless config.ini says otherwise.
+    pub fn set_session_directory(&mut self, directory: &Path) {
+        if let SaveRoot::Session(root) = &mut self.root {
+            // The parent of a bare file name is empty: the working directory
+            let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+            *root = Some(directory.to_path_buf());
+        }
+    }
+
+    /// Directory saving is confined to, if any.
+    pub fn root(&self) -> Option<&Path> {
+        match &self.root {
+            SaveRoot::Session(root) => root.as_deref(),
+            SaveRoot::Fixed(root) => Some(root),
+            SaveRoot::Anywhere => None,
+        }
+    }
+
+    /// Check that `path` may be written, returning where it really leads:
+    /// an absolute path with links and `..` resolved. The error explains
+    /// why a path is refused.
+    pub fn check(&self, path: &Path) -> io::Result<PathBuf> {
+        let refuse = |reason: String| io::Error::new(io::ErrorKind::PermissionDenied, reason);
+
+        let name = path.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(|| refuse(format!("{} does not end in a file name", path.display())))?;
+        validate_file_name(&name).map_err(refuse)?;
+
+        if !self.extensions.is_empty() {
+            let extension = Path::new(&name).extension().map(|e| e.to_string_lossy().to_lowercase());
+            if !extension.as_ref().is_some_and(|e| self.extensions.contains(e)) {
+                let allowed: Vec<String> = self.extensions.iter().map(|e| format!(".{}", e)).collect();
+                let found = extension.map_or(String::from("no extension"), |e| format!(".{}", e));
+                return Err(refuse(format!("only {} files may be saved, not {}", allowed.join(" / "), found)));
+            }
+        }
+
+        let target = resolve(path)?;
+        if let Some(root) = self.root() {
+            let root = fs::canonicalize(root)
+                .map_err(|e| refuse(format!("save root {} is unavailable: {}", root.display(), e)))?;
+            if !target.starts_with(&root) {
+                return Err(refuse(format!("{} is outside {}", target.display(), root.display())));
+            }
+        }
+        Ok(target)
+    }
+}
+
+/// Why `name` can't be used as a file name, if it can't.
+fn validate_file_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("'{}' is not a file name", name));
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!("file name is longer than {} bytes", MAX_NAME_LENGTH));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(String::from("file name contains control characters"));
+    }
+    if let Some(c) = name.chars().find(|c| r#"<>:"/\|?*"#.contains(*c)) {
+        return Err(format!("file name contains '{}'", c));
+    }
+    if name.ends_with(' ') || name.ends_with('.') {
+        return Err(String::from("file name ends with a space or dot"));
+    }
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        return Err(format!("{} is a reserved device name", stem));
+    }
+    Ok(())
+}
+
+/// Absolute form of `path` with every link and `..` resolved, whether or
+/// not it exists yet. The existing part is canonicalized; the rest can't
+/// contain links, so its `..` are resolved by name.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let path = if path.is_absolute() { path.to_path_buf() } else { std::env::current_dir()?.join(path) };
+
+    // Deepest ancestor that exists; a dangling link counts, so it is
+    // refused by canonicalize rather than written through
+    let mut existing = path.as_path();
+    while fs::symlink_metadata(existing).is_err() {
+        existing = existing.parent().unwrap_or(Path::new("/"));
+    }
+    let mut resolved = fs::canonicalize(existing).map_err(|e| {
+        io::Error::new(e.kind(), format!("cannot resolve {}: {}", existing.display(), e))
+    })?;
+
+    for component in path.strip_prefix(existing).unwrap_or(Path::new("")).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            },
+            Component::Normal(part) => resolved.push(part),
+            _ => {},
+        }
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text_editor-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    fn confined_to(root: &Path) -> SavePolicy {
+        SavePolicy { root: SaveRoot::Fixed(root.to_path_buf()), extensions: Vec::new() }
+    }
+
+    #[test]
+    fn allows_files_inside_the_root() {
+        let root = temp_dir("sandbox-inside");
+        fs::create_dir(root.join("sub")).unwrap();
+        let policy = confined_to(&root);
+        assert_eq!(policy.check(&root.join("a.txt")).unwrap(), root.join("a.txt"));
+        assert_eq!(policy.check(&root.join("sub/../b.txt")).unwrap(), root.join("b.txt"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refuses_climbing_out_with_dot_dot() {
+        let root = temp_dir("sandbox-dotdot");
+        let policy = confined_to(&root);
+        assert!(policy.check(&root.join("../outside.txt")).is_err());
+        assert!(policy.check(&root.join("missing/../../outside.txt")).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn allows_missing_directories_under_the_root() {
+        let root = temp_dir("sandbox-missing");
+        let policy = confined_to(&root);
+        assert_eq!(policy.check(&root.join("new/dir/c.txt")).unwrap(), root.join("new/dir/c.txt"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_links_out_of_the_root() {
+        let root = temp_dir("sandbox-link");
+        let outside = temp_dir("sandbox-link-outside");
+        std::os::unix::fs::symlink(&outside, root.join("out")).unwrap();
+        std::os::unix::fs::symlink(outside.join("gone"), root.join("dangling")).unwrap();
+        let policy = confined_to(&root);
+        assert!(policy.check(&root.join("out/d.txt")).is_err());
+        assert!(policy.check(&root.join("dangling")).is_err());
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn checks_names_and_extensions() {
+        let root = temp_dir("sandbox-names");
+        let policy = SavePolicy { root: SaveRoot::Fixed(root.clone()), extensions: vec![String::from("mommy")] };
+        assert!(policy.check(&root.join("ok.MOMMY")).is_ok());
+        assert!(policy.check(&root.join("no.txt")).is_err());
+        assert!(policy.check(&root.join("CON.mommy")).is_err());
+        assert!(policy.check(&root.join("trailing.mommy ")).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+//! Searching every text file under a directory on a background thread,
+//! and the overlay listing the matches as they come in.
+
+use crate::editor::{Editor, LOADING_REFRESH};
+use crate::encoding;
+use crate::finder::FileIndex;
+use crate::list::{ListKey, ListNav};
+use crate::prompt::{Prompt, PromptKind};
+use crate::regex::Regex;
+use crate::terminal::Terminal;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+
+/// Files bigger than this are skipped.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The search stops after this many matches.
+pub const MAX_HITS: usize = 10_000;
+
+/// What to look for on each line.
+#[derive(Clone)]
+pub enum Pattern {
+    Literal { text: String, ignore_case: bool },
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// `/regex/` (or `/regex/i` to ignore case) is a regular expression;
+    /// anything else is searched for literally, ignoring case unless it
+    /// contains an upper-case letter.
+    pub fn parse(query: &str) -> Result<Self, String> {
+        if let Some(rest) = query.strip_prefix('/')
+            && let Some(end) = rest.rfind('/')
+            && matches!(&rest[end + 1..], "" | "i")
+        {
+            if end == 0 {
+                return Err(String::from("empty pattern"));
+            }
+            return Regex::new(&rest[..end], &rest[end + 1..] == "i").map(Pattern::Regex);
+        }
+
+        if query.is_empty() {
+            return Err(String::from("empty pattern"));
+        }
+        Ok(Pattern::Literal {
+            text: query.to_string(),
+            ignore_case: !query.chars().any(char::is_uppercase),
+        })
+    }
+
+    /// Byte range of the first match in `line` at or after `start`.
+    pub fn find_at(&self, line: &str, start: usize) -> Option<(usize, usize)> {
+        match self {
+            Pattern::Literal { text, ignore_case: false } => {
+                line[start..].find(text.as_str()).map(|i| (start + i, start + i + text.len()))
+            },
+            Pattern::Literal { text, ignore_case: true } => {
+                // Only ASCII letters are folded, so a match starting on a
+                // character boundary also ends on one
+                let (hay, needle) = (line.as_bytes(), text.as_bytes());
+                (start..=hay.len().checked_sub(needle.len())?)
+                    .find(|&i| line.is_char_boundary(i) && hay[i..i + needle.len()].eq_ignore_ascii_case(needle))
+                    .map(|i| (i, i + needle.len()))
+            },
+            Pattern::Regex(regex) => regex.find_at(line, start),
+        }
+    }
+
+    /// Text to put in place of the match at byte `start` of `line`. For a
+    /// regex, `$0` to `$9` in `replacement` stand for the match and its
+    /// groups, and `$$` for a dollar sign.
+    pub fn expand(&self, line: &str, start: usize, replacement: &str) -> String {
+        let Pattern::Regex(regex) = self else { return replacement.to_string() };
+        let Some(slots) = regex.captures_at(line, start) else { return replacement.to_string() };
+
+        let mut out = String::with_capacity(replacement.len());
+        let mut chars = replacement.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek().copied()) {
+                ('$', Some('$')) => {
+                    chars.next();
+                    out.push('$');
+                },
+                ('$', Some(d @ '0'..='9')) => {
+                    chars.next();
+                    let group = d as usize - '0' as usize;
+                    if let (Some(Some(from)), Some(Some(to))) = (slots.get(2 * group), slots.get(2 * group + 1)) {
+                        out.push_str(&line[*from..*to]);
+                    }
+                },
+                _ => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// Byte ranges of the non-overlapping matches in `line`. An empty match
+    /// right where the previous match ended is left out: in `aab`, `/a*/`
+    /// finds `aa` and the empty match at the end, but none before `b`.
+    pub fn find_all(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut found: Vec<(usize, usize)> = Vec::new();
+        let mut start = 0;
+        while start <= line.len() {
+            let Some((from, to)) = self.find_at(line, start) else { break };
+            if from < to || found.last().is_none_or(|&(_, end)| end != from) {
+                found.push((from, to));
+            }
+            // After an empty match, move on a character to avoid looping
+            start = if to > from {
+                to
+            } else {
+                to + line[to..].chars().next().map_or(1, char::len_utf8)
+            };
+        }
+        found
+    }
+}
+
+pub struct SearchHit {
+    /// Path relative to the search root, with `/` separators.
+    pub path: String,
+    /// Line number, from 0.
+    pub line: usize,
+    /// Byte range of the match within `text`.
+    pub start: usize,
+    pub end: usize,
+    /// The whole line.
+    pub text: String,
+}
+
+enum Message {
+    Hit(SearchHit),
+    Done { files: usize, truncated: bool },
+}
+
+/// A search running on a worker thread. Matches arrive in file order and
+/// can be collected with `poll` while the search goes on. Dropping it stops
+/// the worker.
+pub struct ProjectSearch {
+    root: PathBuf,
+    receiver: Receiver<Message>,
+    cancelled: Arc<AtomicBool>,
+    /// Files searched and whether `MAX_HITS` cut the search short, once done.
+    finished: Option<(usize, bool)>,
+}
+
+impl ProjectSearch {
+    /// Search the files the finder would list under `root` (so `.gitignore`
+    /// is respected), skipping binary and very large ones.
+    pub fn start(root: &Path, pattern: Pattern) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&cancelled);
+        let search_root = root.to_path_buf();
+
+        thread::spawn(move || {
+            let index = FileIndex::build(&search_root);
+            let mut hits = 0;
+            let mut files = 0;
+
+            for path in index.files() {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                let Some(text) = read_text(&search_root.join(path)) else { continue };
+                files += 1;
+
+                for (number, line) in text.lines().enumerate() {
+                    for (start, end) in pattern.find_all(line) {
+                        let hit = SearchHit { path: path.clone(), line: number, start, end, text: line.to_string() };
+                        if sender.send(Message::Hit(hit)).is_err() {
+                            return;
+                        }
+                        hits += 1;
+                        if hits >= MAX_HITS {
+                            let _ = sender.send(Message::Done { files, truncated: true });
+                            return;
+                        }
+                    }
+                }
+            }
+            let _ = sender.send(Message::Done { files, truncated: false });
+        });
+
+        ProjectSearch { root: root.to_path_buf(), receiver, cancelled, finished: None }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Matches found since the last call.
+    pub fn poll(&mut self) -> Vec<SearchHit> {
+        let mut hits = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Message::Hit(hit)) => hits.push(hit),
+                Ok(Message::Done { files, truncated }) => self.finished = Some((files, truncated)),
+                Err(TryRecvError::Empty) => break,
+                // The worker only hangs up without a `Done` if it panicked
+                Err(TryRecvError::Disconnected) => {
+                    self.finished.get_or_insert((0, false));
+                    break;
+                },
+            }
+        }
+        hits
+    }
+
+    /// Number of files searched and whether the search stopped early at
+    /// `MAX_HITS`, once it has finished.
+    pub fn finished(&self) -> Option<(usize, bool)> {
+        self.finished
+    }
+}
+
+impl Drop for ProjectSearch {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Contents of a file worth searching: not too big, not binary. The text
+/// is decoded as opening the file would, so hits line up with the lines a
+/// replace later reads back.
+fn read_text(path: &Path) -> Option<String> {
+    if fs::metadata(path).ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    let (text, _) = encoding::decode_file(&bytes)?.ok()?;
+    Some(text)
+}
+
+impl Editor {
+    /// Search every file under the project root for a literal string or a
+    /// `/regex/`, listing matches as they are found. Enter opens the file
+    /// at the selected match.
+    ///
+    /// With `replace`, the replace is started as soon as the search is done.
+    pub fn project_search(&mut self, given: Option<&str>, mut replace: bool) {
+        let Some(root) = self.project_root() else {
+            self.show_message(String::from("No directory to search"));
+            return;
+        };
+        let prompt = Prompt::new(PromptKind::Search, "Search files (/regex/ for a regex): ")
+            .validate(|_: &Editor, query: &str| Pattern::parse(query).map(|_| ()));
+        let Some(query) = self.answer(given, prompt, "Search cancelled") else { return };
+        let pattern = match Pattern::parse(&query) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                self.show_message(format!("Bad search pattern: {}", e));
+                return;
+            },
+        };
+
+        let mut search = ProjectSearch::start(&root, pattern.clone());
+        let mut hits: Vec<SearchHit> = Vec::new();
+        let mut items: Vec<String> = Vec::new();
+        let mut marks = Vec::new();
+        let mut list = ListNav::new(self.screen_rows as usize);
+
+        loop {
+            for hit in search.poll() {
+                let (item, mark) = hit_item(&hit);
+                items.push(item);
+                marks.push(mark);
+                hits.push(hit);
+            }
+
+            if replace && search.finished().is_some() {
+                replace = false;
+                if !hits.is_empty() && self.review_replace(search.root(), &pattern, &query, &hits) {
+                    break;
+                }
+            }
+
+            list.set_len(items.len());
+            if items.is_empty() {
+                let note = if search.finished().is_some() { "  (no matches)" } else { "  (searching...)" };
+                self.draw_list(&[String::from(note)], usize::MAX, 0);
+            } else {
+                self.draw_marked_list(&items, &marks, list.selected(), list.top());
+            }
+
+            let progress = match search.finished() {
+                None => String::from(", searching..."),
+                Some((files, false)) => format!(" in {} files", files),
+                Some((files, true)) => format!(" in {} files (stopped at {})", files, MAX_HITS),
+            };
+            let status = format!(" Search '{}' - {} matches{}", query, hits.len(), progress);
+            let _ = Terminal::move_cursor(self.screen_rows, 0);
+            self.draw_overlay_bars(&status, "Up/Down to choose, Enter to open, r to replace, Esc to close");
+
+            // Keep redrawing while results come in
+            let byte = if search.finished().is_some() {
+                self.read_byte().ok()
+            } else {
+                match self.input.recv_timeout(LOADING_REFRESH) {
+                    Ok(byte) => Some(byte),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => None,
+                }
+            };
+            let Some(byte) = byte else { break };
+
+            match self.list_key(byte) {
+                key if list.handle(&key) => {},
+                ListKey::Escape | ListKey::Byte(b'q') => break,
+                // Only once the full list is known
+                ListKey::Byte(b'r') if search.finished().is_some() && !hits.is_empty() => {
+                    let replaced = self.review_replace(search.root(), &pattern, &query, &hits);
+                    if replaced {
+                        break;
+                    }
+                },
+                ListKey::Enter => {
+                    let Some(hit) = hits.get(list.selected()) else { continue };
+                    let path = search.root().join(&hit.path);
+                    match self.open_file(&path.to_string_lossy()) {
+                        Ok(()) => self.jump_to(hit.line, hit.start),
+                        Err(e) => {
+                            self.show_message(format!("Could not open {}: {}", path.display(), e));
+                        },
+                    }
+                    break;
+                },
+                _ => {}
+            }
+        }
+
+        let _ = Terminal::show_cursor();
+    }
+}
+
+/// The line of a search hit without its indentation, starting a little
+/// before the match if the match is far along the line. Also returns the
+/// range of character positions the match takes up in it.
+fn snippet(hit: &SearchHit) -> (String, usize, usize) {
+    const LEAD: usize = 40;
+
+    let indent = hit.text.len() - hit.text.trim_start().len();
+    let mut start = indent.min(hit.start);
+    let mut text = String::new();
+    if hit.start - start > LEAD {
+        start = hit.start - LEAD / 2;
+        while !hit.text.is_char_boundary(start) {
+            start -= 1;
+        }
+        text.push('…');
+    }
+
+    let from = text.chars().count() + hit.text[start..hit.start].chars().count();
+    let to = from + hit.text[hit.start..hit.end].chars().count();
+    text.push_str(&hit.text[start..]);
+    (text, from, to)
+}
+
+/// List entry for a search hit, `file:line: text`, and the range of
+/// character positions the match takes up in it.
+pub fn hit_item(hit: &SearchHit) -> (String, (usize, usize)) {
+    let location = format!("{}:{}: ", hit.path, hit.line + 1);
+    let offset = location.chars().count();
+    let (text, from, to) = snippet(hit);
+    (location + &text, (offset + from, offset + to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_all(query: &str, line: &str) -> Vec<(usize, usize)> {
+        Pattern::parse(query).unwrap().find_all(line)
+    }
+
+    #[test]
+    fn literal_queries_ignore_case_unless_they_have_capitals() {
+        assert_eq!(find_all("foo", "Foo foo FOO"), [(0, 3), (4, 7), (8, 11)]);
+        assert_eq!(find_all("Foo", "Foo foo FOO"), [(0, 3)]);
+        assert_eq!(find_all("é", "aé é"), [(1, 3), (4, 6)]);
+    }
+
+    #[test]
+    fn slashes_make_a_regex() {
+        assert_eq!(find_all("/a+/", "caaab a"), [(1, 4), (6, 7)]);
+        assert_eq!(find_all("/A/i", "a A"), [(0, 1), (2, 3)]);
+        assert_eq!(find_all("/A/", "a A"), [(2, 3)]);
+        // Not a regex unless it ends in `/` or `/i`
+        assert_eq!(find_all("/a/x", "/a/x"), [(0, 4)]);
+        assert!(Pattern::parse("//").is_err());
+        assert!(Pattern::parse("").is_err());
+        assert!(Pattern::parse("/(/").is_err());
+    }
+
+    #[test]
+    fn find_all_moves_on_after_empty_matches() {
+        assert_eq!(find_all("/x*/", "ab"), [(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(find_all("/x*/", "éa"), [(0, 0), (2, 2), (3, 3)]);
+        assert_eq!(find_all("/a*/", "aab"), [(0, 2), (3, 3)]);
+        assert_eq!(find_all("/$/", ""), [(0, 0)]);
+    }
+
+    #[test]
+    fn matches_dont_overlap() {
+        assert_eq!(find_all("aa", "aaaaa"), [(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn expand_fills_in_groups() {
+        let pattern = Pattern::parse(r"/(\w+)=(\w+)/").unwrap();
+        assert_eq!(pattern.expand("x a=b", 2, "$2=$1"), "b=a");
+        assert_eq!(pattern.expand("x a=b", 2, "[$0] $$1 $3"), "[a=b] $1 ");
+        let literal = Pattern::parse("a=b").unwrap();
+        assert_eq!(literal.expand("a=b", 0, "$1"), "$1");
+    }
+}
@@ -0,0 +1,540 @@
+use crate::config;
+use crate::rope::Rope;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Highlight {
+    Normal,
+    Keyword,
+    Type,
+    String,
+    Comment,
+    Number,
+}
+
+/// Lexer state carried from the end of one line into the next, for
+/// constructs that can span lines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineState {
+    Normal,
+    InBlockComment,
+    InString(char),
+}
+
+/// The grammar of one file type.
+#[derive(Clone, Debug)]
+pub struct Syntax {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub keywords: Vec<String>,
+    pub types: Vec<String>,
+    pub line_comment: Option<String>,
+    pub block_comment: Option<(String, String)>,
+    pub string_delimiters: Vec<char>,
+    /// Whether an unterminated string continues onto the next line.
+    pub multiline_strings: bool,
+    pub numbers: bool,
+    /// A line whose first word is one of these, or that ends with one of
+    /// these, indents the line after it.
+    pub indent_after: Vec<String>,
+    /// A line starting with one of these closes a block and is dedented.
+    pub dedent_words: Vec<String>,
+    pub indent_width: usize,
+    /// Words offered by completion. Falls back to keywords and types.
+    pub completions: Vec<String>,
+}
+
+fn words(list: &str) -> Vec<String> {
+    list.split_whitespace().map(|w| w.to_string()).collect()
+}
+
+impl Syntax {
+    fn builtin() -> Vec<Syntax> {
+        vec![
+            Syntax::mommy(),
+            Syntax {
+                name: String::from("Rust"),
+                extensions: words("rs"),
+                keywords: words("as async await break const continue crate dyn else enum extern false fn for \
+                                 if impl in let loop match mod move mut pub ref return self Self static struct \
+                                 super trait true type unsafe use where while"),
+                types: words("i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize f32 f64 bool char str \
+                              String Vec Option Result Box"),
+                line_comment: Some(String::from("//")),
+                block_comment: Some((String::from("/*"), String::from("*/"))),
+                string_delimiters: vec!['"'],
+                multiline_strings: true,
+                numbers: true,
+                indent_after: words("{ ( ["),
+                dedent_words: words("} ) ]"),
+                indent_width: 4,
+                completions: words(""),
+            },
+            Syntax {
+                name: String::from("C"),
+                extensions: words("c h cpp hpp cc cxx"),
+                keywords: words("auto break case class const continue default delete do else enum extern for \
+                                 goto if inline namespace new private protected public register return sizeof \
+                                 static struct switch template this typedef union using virtual volatile while \
+                                 #include #define #ifdef #ifndef #endif"),
+                types: words("int long short char float double void unsigned signed bool size_t"),
+                line_comment: Some(String::from("//")),
+                block_comment: Some((String::from("/*"), String::from("*/"))),
+                string_delimiters: vec!['"', '\''],
+                multiline_strings: false,
+                numbers: true,
+                indent_after: words("{ ( ["),
+                dedent_words: words("} ) ]"),
+                indent_width: 4,
+                completions: words(""),
+            },
+            Syntax {
+                name: String::from("Python"),
+                extensions: words("py pyw"),
+                keywords: words("and as assert async await break class continue def del elif else except \
+                                 False finally for from global if import in is lambda None nonlocal not or \
+                                 pass raise return True try while with yield"),
+                types: words("int float str bool list dict tuple set bytes object"),
+                line_comment: Some(String::from("#")),
+                block_comment: None,
+                string_delimiters: vec!['"', '\''],
+                multiline_strings: false,
+                numbers: true,
+                indent_after: words(":"),
+                dedent_words: words(""),
+                indent_width: 4,
+                completions: words(""),
+            },
+            Syntax {
+                name: String::from("JavaScript"),
+                extensions: words("js mjs cjs ts jsx tsx"),
+                keywords: words("async await break case catch class const continue default delete do else \
+                                 export extends false finally for function if import in instanceof let new \
+                                 null return super switch this throw true try typeof undefined var void while \
+                                 yield"),
+                types: words("Array Boolean Number Object Promise String Map Set"),
+                line_comment: Some(String::from("//")),
+                block_comment: Some((String::from("/*"), String::from("*/"))),
+                string_delimiters: vec!['"', '\'', '`'],
+                multiline_strings: false,
+                numbers: true,
+                indent_after: words("{ ( ["),
+                dedent_words: words("} ) ]"),
+                indent_width: 4,
+                completions: words(""),
+            },
+        ]
+    }
+
+    /// The MommySuite scripting language used by `.mommy` files.
+    fn mommy() -> Syntax {
+        let keywords = words("mommy please thank you if elif else then while for in to step do done end \
+                              chore return give make let set ask say and or not true false nothing");
+        let types = words("number text list truth");
+        let builtins = words("say ask length append remove random sleep");
+
+        let mut completions: Vec<String> = keywords.iter().chain(&types).chain(&builtins).cloned().collect();
+        completions.sort();
+        completions.dedup();
+
+        Syntax {
+            name: String::from("Mommy"),
+            extensions: words("mommy"),
+            keywords,
+            types,
+            line_comment: Some(String::from("#")),
+            block_comment: Some((String::from("#{"), String::from("}#"))),
+            string_delimiters: vec!['"'],
+            multiline_strings: false,
+            numbers: true,
+            indent_after: words("if elif else while for chore then do"),
+            dedent_words: words("end done elif else"),
+            indent_width: 4,
+            completions,
+        }
+    }
+
+    /// Build a syntax from a `syntax.ini` section:
+    ///
+    /// ```ini
+    /// [Lua]
+    /// extensions = lua
+    /// keywords = local function end if then else return
+    /// line_comment = --
+    /// block_comment = --[[ ]]
+    /// strings = " '
+    /// multiline_strings = false
+    /// numbers = true
+    /// indent_after = then do function
+    /// dedent_words = end else elseif
+    /// indent_width = 2
+    /// completions = print pairs ipairs
+    /// ```
+    fn from_section(section: &config::Section) -> Syntax {
+        let flag = |key: &str, default: bool| {
+            section.get(key).map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(default)
+        };
+
+        Syntax {
+            name: section.name.clone(),
+            extensions: words(&section.get("extensions").unwrap_or("").replace(',', " ")),
+            keywords: words(section.get("keywords").unwrap_or("")),
+            types: words(section.get("types").unwrap_or("")),
+            line_comment: section.get("line_comment").filter(|c| !c.is_empty()).map(|c| c.to_string()),
+            block_comment: section.get("block_comment").and_then(|c| {
+                let mut parts = c.split_whitespace();
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            }),
+            string_delimiters: section.get("strings")
+                .map(|s| s.chars().filter(|c| !c.is_whitespace()).collect())
+                .unwrap_or_else(|| vec!['"']),
+            multiline_strings: flag("multiline_strings", false),
+            numbers: flag("numbers", true),
+            indent_after: words(section.get("indent_after").unwrap_or("")),
+            dedent_words: words(section.get("dedent_words").unwrap_or("")),
+            indent_width: section.get("indent_width").and_then(|w| w.parse().ok()).unwrap_or(4),
+            completions: words(section.get("completions").unwrap_or("")),
+        }
+    }
+
+    /// All known syntaxes. Definitions in `syntax.ini` come first so they can
+    /// take over an extension from a built-in.
+    pub fn all() -> Vec<Syntax> {
+        let mut all: Vec<Syntax> = config::load_ini("syntax.ini")
+            .iter()
+            .filter(|s| !s.name.is_empty())
+            .map(Syntax::from_section)
+            .collect();
+        all.extend(Syntax::builtin());
+        all
+    }
+
+    /// Pick the syntax for a file from its extension.
+    pub fn for_path(path: &Path) -> Option<Syntax> {
+        let ext = path.extension()?.to_str()?;
+        Syntax::all()
+            .into_iter()
+            .find(|s| s.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+    }
+
+    fn starts_with_word(text: &str, word: &str) -> bool {
+        // "end" must not match "endless", but "}" should match "});"
+        text.strip_prefix(word).is_some_and(|rest| {
+            !word.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                || !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        })
+    }
+
+    /// Leading whitespace of `line`.
+    pub fn indentation(line: &str) -> &str {
+        &line[..line.len() - line.trim_start().len()]
+    }
+
+    /// Indentation for a new line opened after `line`.
+    pub fn indent_after_line(&self, line: &str) -> String {
+        let mut indent = Syntax::indentation(line).to_string();
+        let trimmed = line.trim();
+
+        let opens_block = self.indent_after.iter().any(|w| {
+            Syntax::starts_with_word(trimmed, w)
+                || trimmed.strip_suffix(w.as_str()).is_some_and(|before| {
+                    // A trailing word must be a whole word too
+                    !w.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                        || !before.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                })
+        });
+        if opens_block {
+            indent.push_str(&" ".repeat(self.indent_width));
+        }
+        indent
+    }
+
+    /// Whether `line` closes a block and should sit one level left of the
+    /// line before it.
+    pub fn is_dedent_line(&self, line: &str) -> bool {
+        let trimmed = line.trim_start();
+        self.dedent_words.iter().any(|w| Syntax::starts_with_word(trimmed, w))
+    }
+
+    /// How many bytes at the end of `line`'s indentation make up one level.
+    pub fn dedent_width(&self, line: &str) -> usize {
+        let indent = Syntax::indentation(line);
+        if indent.ends_with('\t') {
+            1
+        } else {
+            indent.len().min(self.indent_width)
+        }
+    }
+
+    /// Completion candidates that extend `prefix`.
+    pub fn completions_for(&self, prefix: &str) -> Vec<&str> {
+        let source: Box<dyn Iterator<Item = &String>> = if self.completions.is_empty() {
+            Box::new(self.keywords.iter().chain(&self.types))
+        } else {
+            Box::new(self.completions.iter())
+        };
+
+        let mut matches: Vec<&str> = source
+            .map(|w| w.as_str())
+            .filter(|w| w.starts_with(prefix) && *w != prefix)
+            .collect();
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    fn is_separator(c: char) -> bool {
+        c.is_whitespace() || c == '\0' || ",.()+-/*=~%<>[]{};:&|!^?".contains(c)
+    }
+
+    /// Highlight one line given the state left by the previous line.
+    /// Returns one `Highlight` per byte of `line` and the state at its end.
+    pub fn highlight_line(&self, line: &str, start: LineState) -> (Vec<Highlight>, LineState) {
+        let bytes = line.as_bytes();
+        let mut hl = vec![Highlight::Normal; bytes.len()];
+        let mut state = start;
+        let mut i = 0;
+        let mut prev_separator = true;
+
+        while i < bytes.len() {
+            let rest = &line[i..];
+            let ch = rest.chars().next().expect("i is on a char boundary");
+
+            match state {
+                LineState::InBlockComment => {
+                    let (_, end) = self.block_comment.as_ref().expect("only entered with a block comment");
+                    match rest.find(end.as_str()) {
+                        Some(pos) => {
+                            let stop = i + pos + end.len();
+                            hl[i..stop].fill(Highlight::Comment);
+                            i = stop;
+                            state = LineState::Normal;
+                            prev_separator = true;
+                        },
+                        None => {
+                            hl[i..].fill(Highlight::Comment);
+                            i = bytes.len();
+                        }
+                    }
+                    continue;
+                },
+                LineState::InString(delim) => {
+                    let len = ch.len_utf8();
+                    hl[i..i + len].fill(Highlight::String);
+                    if ch == '\\' && i + len < bytes.len() {
+                        let next_len = line[i + len..].chars().next().map_or(0, |c| c.len_utf8());
+                        hl[i + len..i + len + next_len].fill(Highlight::String);
+                        i += len + next_len;
+                        continue;
+                    }
+                    if ch == delim {
+                        state = LineState::Normal;
+                    }
+                    i += len;
+                    prev_separator = true;
+                    continue;
+                },
+                LineState::Normal => {}
+            }
+
+            // Block comments first, in case the line comment marker is a prefix of it
+            if let Some((start, _)) = &self.block_comment
+                && rest.starts_with(start.as_str())
+            {
+                hl[i..i + start.len()].fill(Highlight::Comment);
+                i += start.len();
+                state = LineState::InBlockComment;
+                continue;
+            }
+
+            if let Some(start) = &self.line_comment
+                && rest.starts_with(start.as_str())
+            {
+                hl[i..].fill(Highlight::Comment);
+                break;
+            }
+
+            if self.string_delimiters.contains(&ch) {
+                hl[i] = Highlight::String;
+                i += ch.len_utf8();
+                state = LineState::InString(ch);
+                continue;
+            }
+
+            if self.numbers && prev_separator && ch.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .unwrap_or(rest.len());
+                hl[i..i + len].fill(Highlight::Number);
+                i += len;
+                prev_separator = false;
+                continue;
+            }
+
+            if prev_separator {
+                let word_len = rest.find(Syntax::is_separator).unwrap_or(rest.len());
+                let word = &rest[..word_len];
+                let kind = if self.keywords.iter().any(|k| k == word) {
+                    Some(Highlight::Keyword)
+                } else if self.types.iter().any(|t| t == word) {
+                    Some(Highlight::Type)
+                } else {
+                    None
+                };
+
+                if let Some(kind) = kind.filter(|_| word_len > 0) {
+                    hl[i..i + word_len].fill(kind);
+                    i += word_len;
+                    prev_separator = false;
+                    continue;
+                }
+            }
+
+            prev_separator = Syntax::is_separator(ch);
+            i += ch.len_utf8();
+        }
+
+        // Single-line strings don't leak into the next line
+        if let LineState::InString(_) = state
+            && !self.multiline_strings
+        {
+            state = LineState::Normal;
+        }
+
+        (hl, state)
+    }
+}
+
+struct LineHighlight {
+    tokens: Vec<Highlight>,
+    start_state: LineState,
+    end_state: LineState,
+    dirty: bool,
+}
+
+impl LineHighlight {
+    fn dirty() -> Self {
+        LineHighlight {
+            tokens: Vec::new(),
+            start_state: LineState::Normal,
+            end_state: LineState::Normal,
+            dirty: true,
+        }
+    }
+}
+
+/// Per-line highlight cache for one buffer.
+///
+/// Edits only mark lines dirty; `update` re-lexes dirty lines plus any line
+/// whose incoming state changed (e.g. an opened block comment), and stops as
+/// soon as the states line up again, so an edit costs a few lines rather than
+/// the whole file.
+pub struct Highlighter {
+    syntax: Option<Syntax>,
+    lines: Rope<LineHighlight>,
+    /// Every line before this one is up to date.
+    first_dirty: usize,
+    dirty_count: usize,
+}
+
+impl Highlighter {
+    pub fn new(syntax: Option<Syntax>, line_count: usize) -> Self {
+        Highlighter {
+            syntax,
+            lines: Rope::from_vec((0..line_count).map(|_| LineHighlight::dirty()).collect()),
+            first_dirty: 0,
+            dirty_count: line_count,
+        }
+    }
+
+    pub fn syntax(&self) -> Option<&Syntax> {
+        self.syntax.as_ref()
+    }
+
+    pub fn set_syntax(&mut self, syntax: Option<Syntax>) {
+        let line_count = self.lines.len();
+        *self = Highlighter::new(syntax, line_count);
+    }
+
+    pub fn line_changed(&mut self, row: usize) {
+        if let Some(line) = self.lines.get_mut(row) {
+            if !line.dirty {
+                line.dirty = true;
+                self.dirty_count += 1;
+            }
+            self.first_dirty = self.first_dirty.min(row);
+        }
+    }
+
+    pub fn line_inserted(&mut self, row: usize) {
+        let row = row.min(self.lines.len());
+        self.lines.insert(row, LineHighlight::dirty());
+        self.dirty_count += 1;
+        self.first_dirty = self.first_dirty.min(row);
+    }
+
+    pub fn line_removed(&mut self, row: usize) {
+        if row < self.lines.len() {
+            if self.lines.remove(row).dirty {
+                self.dirty_count -= 1;
+            }
+            // The line that moved up now follows a different line
+            self.line_changed(row);
+            self.first_dirty = self.first_dirty.min(row);
+        }
+    }
+
+    /// Bring highlighting up to date for every line before `up_to`.
+    pub fn update<'a>(&mut self, up_to: usize, line_at: impl Fn(usize) -> Option<&'a str>) {
+        let Some(syntax) = &self.syntax else { return };
+        let up_to = up_to.min(self.lines.len());
+
+        let mut row = self.first_dirty;
+        while row < up_to {
+            let start_state = match row {
+                0 => LineState::Normal,
+                _ => self.lines.get(row - 1).map_or(LineState::Normal, |l| l.end_state),
+            };
+            let Some(entry) = self.lines.get_mut(row) else { break };
+
+            if entry.dirty || entry.start_state != start_state {
+                if entry.dirty {
+                    self.dirty_count -= 1;
+                }
+                let (tokens, end_state) = syntax.highlight_line(line_at(row).unwrap_or(""), start_state);
+                *entry = LineHighlight { tokens, start_state, end_state, dirty: false };
+            } else if self.dirty_count == 0 {
+                // Same input as last time and no edits below: the rest of
+                // the file is already correct.
+                row = self.lines.len();
+                break;
+            }
+            row += 1;
+        }
+
+        self.first_dirty = row;
+    }
+
+    /// Highlighting for a line, if it is up to date.
+    pub fn line(&self, row: usize) -> Option<&[Highlight]> {
+        self.syntax.as_ref()?;
+        self.lines.get(row)
+            .filter(|l| !l.dirty && row < self.first_dirty)
+            .map(|l| l.tokens.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mommy_files_get_the_built_in_mode() {
+        let syntax = Syntax::for_path(Path::new("x.mommy")).unwrap();
+        assert_eq!(syntax.name, "Mommy");
+        assert!(syntax.keywords.iter().any(|k| k == "chore"));
+        assert_eq!(syntax.line_comment.as_deref(), Some("#"));
+        assert_eq!(syntax.block_comment, Some((String::from("#{"), String::from("}#"))));
+        assert_eq!(syntax.string_delimiters, vec!['"']);
+    }
+}
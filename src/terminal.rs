@@ -1,11 +1,32 @@
-use std::io::{self, Write, stdout};
+use std::io::{self, BufRead, Write, stdin, stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+#[cfg(windows)]
+use std::time::Instant;
 
 #[cfg(windows)]
 use std::os::windows::io::AsRawHandle;
 
+pub const RESET_COLORS: &str = "\x1b[0m";
+pub const CLEAR_LINE: &str = "\x1b[2K";
+pub const DEFAULT_FG: &str = "\x1b[39m";
+const FOCUS_REPORTING_ON: &str = "\x1b[?1004h";
+const FOCUS_REPORTING_OFF: &str = "\x1b[?1004l";
+// Button presses reported in the SGR format, ESC[<b;x;yM
+const MOUSE_REPORTING_ON: &str = "\x1b[?1000h\x1b[?1006h";
+const MOUSE_REPORTING_OFF: &str = "\x1b[?1006l\x1b[?1000l";
+
+/// How long the input reader waits for a key before checking whether it
+/// has been parked.
+const INPUT_POLL: Duration = Duration::from_millis(50);
+
 pub struct Terminal {
     #[cfg(windows)]
     original_mode: u32,
+    mouse_reporting: bool,
 }
 
 impl Terminal {
@@ -13,8 +34,12 @@ impl Terminal {
         let mut terminal = Terminal {
             #[cfg(windows)]
             original_mode: 0,
+            mouse_reporting: false,
         };
         terminal.enable_raw_mode()?;
+        // Ask for ESC[I / ESC[O when the terminal window gains or loses focus
+        print!("{}", FOCUS_REPORTING_ON);
+        stdout().flush()?;
         Ok(terminal)
     }
 
@@ -29,9 +54,11 @@ impl Terminal {
             }
         }
         
-        // Enable virtual terminal processing for ANSI escape sequences
+        // Enable virtual terminal processing for ANSI escape sequences. With
+        // processed input off, Ctrl-C arrives as a key instead of ending the
+        // process, so it can be bound.
         let mut mode = self.original_mode;
-        mode &= !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT);
+        mode &= !(ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT);
         mode |= ENABLE_VIRTUAL_TERMINAL_INPUT;
         
         unsafe {
@@ -60,6 +87,76 @@ impl Terminal {
         Ok(())
     }
 
+    /// Pass stdin on byte by byte from a background thread, so the editor can
+    /// keep redrawing (e.g. load progress) while no key is pressed. The
+    /// gate stops the thread from reading while another program uses the
+    /// terminal.
+    pub fn spawn_input_reader() -> (Receiver<u8>, InputGate) {
+        let (sender, receiver) = mpsc::channel();
+        let gate = InputGate { parked: Arc::new(AtomicBool::new(false)), reading: Arc::new(Mutex::new(())) };
+        let reader = gate.clone();
+        thread::spawn(move || {
+            loop {
+                if reader.parked.load(Ordering::SeqCst) {
+                    thread::sleep(INPUT_POLL);
+                    continue;
+                }
+                // Only read while holding the lock, and only once a key is
+                // waiting, so parking never leaves a read in progress. All
+                // that stdin buffered is taken: polling can't see it, so the
+                // rest of an escape sequence would wait for the next key.
+                let read = {
+                    let _reading = reader.reading.lock().unwrap_or_else(PoisonError::into_inner);
+                    if reader.parked.load(Ordering::SeqCst) || !input_ready(INPUT_POLL) {
+                        continue;
+                    }
+                    let mut input = stdin().lock();
+                    let bytes = input.fill_buf().map(<[u8]>::to_vec);
+                    input.consume(bytes.as_ref().map_or(0, Vec::len));
+                    bytes
+                };
+                let Ok(bytes) = read else { break };
+                if bytes.is_empty() || bytes.into_iter().any(|byte| sender.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        (receiver, gate)
+    }
+
+    /// Have mouse clicks reported as escape sequences. This takes over the
+    /// terminal's own text selection, so it is only done on request.
+    pub fn enable_mouse_reporting(&mut self) -> io::Result<()> {
+        self.mouse_reporting = true;
+        print!("{}", MOUSE_REPORTING_ON);
+        stdout().flush()
+    }
+
+    /// Hand the terminal back in its normal mode, echoing and reading whole
+    /// lines, for another program to use until `resume`.
+    pub fn suspend(&self) -> io::Result<()> {
+        #[cfg(windows)]
+        {
+            let handle = io::stdin().as_raw_handle();
+            unsafe {
+                SetConsoleMode(handle as *mut _, self.original_mode);
+            }
+        }
+        print!("{}{}", FOCUS_REPORTING_OFF, MOUSE_REPORTING_OFF);
+        let _ = Terminal::show_cursor();
+        Terminal::reset_colors()
+    }
+
+    /// Take the terminal back after `suspend`.
+    pub fn resume(&mut self) -> io::Result<()> {
+        self.enable_raw_mode()?;
+        print!("{}", FOCUS_REPORTING_ON);
+        if self.mouse_reporting {
+            print!("{}", MOUSE_REPORTING_ON);
+        }
+        stdout().flush()
+    }
+
     pub fn clear_screen() -> io::Result<()> {
         print!("\x1b[2J");
         stdout().flush()
@@ -81,7 +178,7 @@ impl Terminal {
     }
 
     pub fn clear_line() -> io::Result<()> {
-        print!("\x1b[2K");
+        print!("{}", CLEAR_LINE);
         stdout().flush()
     }
 
@@ -110,19 +207,107 @@ impl Terminal {
     }
 
     pub fn set_fg_color(r: u8, g: u8, b: u8) -> io::Result<()> {
-        print!("\x1b[38;2;{};{};{}m", r, g, b);
+        print!("{}", Terminal::fg_color_code(r, g, b));
         stdout().flush()
     }
 
     pub fn set_bg_color(r: u8, g: u8, b: u8) -> io::Result<()> {
-        print!("\x1b[48;2;{};{};{}m", r, g, b);
+        print!("{}", Terminal::bg_color_code(r, g, b));
         stdout().flush()
     }
 
     pub fn reset_colors() -> io::Result<()> {
-        print!("\x1b[0m");
+        print!("{}", RESET_COLORS);
         stdout().flush()
     }
+
+    // The *_code variants return the escape sequence instead of printing it,
+    // so a whole row can be composed and written in one go.
+    pub fn fg_color_code(r: u8, g: u8, b: u8) -> String {
+        format!("\x1b[38;2;{};{};{}m", r, g, b)
+    }
+
+    pub fn bg_color_code(r: u8, g: u8, b: u8) -> String {
+        format!("\x1b[48;2;{};{};{}m", r, g, b)
+    }
+
+    pub fn move_cursor_code(row: usize, col: usize) -> String {
+        format!("\x1b[{};{}H", row + 1, col + 1)
+    }
+}
+
+/// Lets the editor keep the input reader off stdin.
+#[derive(Clone)]
+pub struct InputGate {
+    parked: Arc<AtomicBool>,
+    /// Held by the reader while it reads.
+    reading: Arc<Mutex<()>>,
+}
+
+/// While this lives the input reader leaves stdin alone.
+pub struct ParkedInput<'a> {
+    gate: &'a InputGate,
+    _reading: MutexGuard<'a, ()>,
+}
+
+impl InputGate {
+    /// Stop the reader from taking any more input, waiting for a read in
+    /// progress to finish.
+    pub fn park(&self) -> ParkedInput<'_> {
+        self.parked.store(true, Ordering::SeqCst);
+        let reading = self.reading.lock().unwrap_or_else(PoisonError::into_inner);
+        ParkedInput { gate: self, _reading: reading }
+    }
+}
+
+impl Drop for ParkedInput<'_> {
+    fn drop(&mut self) {
+        self.gate.parked.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Whether a byte can be read from stdin without blocking, waiting up to
+/// `timeout` for one.
+#[cfg(unix)]
+fn input_ready(timeout: Duration) -> bool {
+    let mut fd = PollFd { fd: 0, events: POLLIN, revents: 0 };
+    let ready = unsafe { poll(&mut fd, 1, timeout.as_millis() as i32) };
+    // Errors and hang-ups are left for the read to report
+    ready != 0
+}
+
+/// The console handle is also signalled for key releases, focus and mouse
+/// records, which give a read nothing to return, so it would block (with
+/// the input gate held) until the next key press. Those records are dropped
+/// here and only a typed character counts.
+#[cfg(windows)]
+fn input_ready(timeout: Duration) -> bool {
+    let handle = io::stdin().as_raw_handle() as HANDLE;
+    let deadline = Instant::now() + timeout;
+    loop {
+        let wait = deadline.saturating_duration_since(Instant::now()).as_millis() as u32;
+        if unsafe { WaitForSingleObject(handle, wait) } == WAIT_TIMEOUT {
+            return false;
+        }
+        let mut record = INPUT_RECORD {
+            EventType: 0,
+            KeyEvent: KEY_EVENT_RECORD { bKeyDown: 0, wRepeatCount: 0, wVirtualKeyCode: 0, wVirtualScanCode: 0, UnicodeChar: 0, dwControlKeyState: 0 },
+        };
+        let mut count = 0;
+        // Not a console (e.g. redirected): leave it to the read
+        if unsafe { PeekConsoleInputW(handle, &mut record, 1, &mut count) } == 0 || count == 0 {
+            return true;
+        }
+        if record.EventType == KEY_EVENT && record.KeyEvent.bKeyDown != 0 && record.KeyEvent.UnicodeChar != 0 {
+            return true;
+        }
+        unsafe { ReadConsoleInputW(handle, &mut record, 1, &mut count) };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn input_ready(_timeout: Duration) -> bool {
+    true
 }
 
 impl Drop for Terminal {
@@ -134,6 +319,7 @@ impl Drop for Terminal {
                 SetConsoleMode(handle as *mut _, self.original_mode);
             }
         }
+        print!("{}{}", FOCUS_REPORTING_OFF, MOUSE_REPORTING_OFF);
         let _ = Terminal::show_cursor();
         let _ = Terminal::reset_colors();
     }
@@ -148,9 +334,15 @@ const ENABLE_ECHO_INPUT: u32 = 0x0004;
 #[cfg(windows)]
 const ENABLE_LINE_INPUT: u32 = 0x0002;
 #[cfg(windows)]
+const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+#[cfg(windows)]
 const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
 #[cfg(windows)]
 const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+#[cfg(windows)]
+const WAIT_TIMEOUT: u32 = 0x102;
+#[cfg(windows)]
+const KEY_EVENT: u16 = 0x0001;
 
 #[cfg(windows)]
 #[repr(C)]
@@ -181,11 +373,57 @@ struct CONSOLE_SCREEN_BUFFER_INFO {
     dwMaximumWindowSize: COORD,
 }
 
+#[cfg(windows)]
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types)]
+struct KEY_EVENT_RECORD {
+    bKeyDown: i32,
+    wRepeatCount: u16,
+    wVirtualKeyCode: u16,
+    wVirtualScanCode: u16,
+    UnicodeChar: u16,
+    dwControlKeyState: u32,
+}
+
+/// Only the key event member of the record's union is read; it is as large
+/// as the biggest of the others.
+#[cfg(windows)]
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types)]
+struct INPUT_RECORD {
+    EventType: u16,
+    KeyEvent: KEY_EVENT_RECORD,
+}
+
 #[cfg(windows)]
 #[allow(non_snake_case)]
 unsafe extern "system" {
     fn GetConsoleMode(hConsoleHandle: HANDLE, lpMode: *mut u32) -> i32;
     fn SetConsoleMode(hConsoleHandle: HANDLE, dwMode: u32) -> i32;
     fn GetConsoleScreenBufferInfo(hConsoleHandle: HANDLE, lpConsoleScreenBufferInfo: *mut CONSOLE_SCREEN_BUFFER_INFO) -> i32;
+    fn WaitForSingleObject(hHandle: HANDLE, dwMilliseconds: u32) -> u32;
+    fn PeekConsoleInputW(hConsoleInput: HANDLE, lpBuffer: *mut INPUT_RECORD, nLength: u32, lpNumberOfEventsRead: *mut u32) -> i32;
+    fn ReadConsoleInputW(hConsoleInput: HANDLE, lpBuffer: *mut INPUT_RECORD, nLength: u32, lpNumberOfEventsRead: *mut u32) -> i32;
 }
 
+// Unix API declarations
+#[cfg(unix)]
+const POLLIN: i16 = 0x1;
+
+#[cfg(unix)]
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+#[cfg(all(unix, target_os = "macos"))]
+type NfdsT = std::ffi::c_uint;
+#[cfg(all(unix, not(target_os = "macos")))]
+type NfdsT = std::ffi::c_ulong;
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn poll(fds: *mut PollFd, nfds: NfdsT, timeout: i32) -> i32;
+}
@@ -0,0 +1,223 @@
+use crate::config;
+use crate::syntax::Highlight;
+use crate::terminal::Terminal;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    /// Parse `#rrggbb` or `r,g,b`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(hex) = value.strip_prefix('#') {
+            // Checked up front so slicing can't split a character, and
+            // because `from_str_radix` would take a leading `+`
+            if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Color::rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+
+        let mut parts = value.split(',').map(|p| p.trim().parse::<u8>());
+        let color = Color::rgb(parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(color)
+    }
+
+    pub fn fg(self) -> String {
+        Terminal::fg_color_code(self.r, self.g, self.b)
+    }
+
+    pub fn bg(self) -> String {
+        Terminal::bg_color_code(self.r, self.g, self.b)
+    }
+}
+
+/// Colors for every part of the UI. `None` means "leave the terminal's own
+/// default color alone".
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub text_fg: Option<Color>,
+    pub text_bg: Option<Color>,
+    pub gutter_fg: Color,
+    pub gutter_bg: Option<Color>,
+    pub current_line_bg: Color,
+    pub current_line_number_fg: Color,
+    pub status_bar_fg: Color,
+    pub status_bar_bg: Color,
+    pub message_bar_fg: Option<Color>,
+    pub message_bar_bg: Option<Color>,
+    pub selection_bg: Color,
+    pub search_match_fg: Color,
+    pub search_match_bg: Color,
+    pub syntax_keyword: Color,
+    pub syntax_type: Color,
+    pub syntax_string: Color,
+    pub syntax_comment: Color,
+    pub syntax_number: Color,
+    pub diff_added_fg: Color,
+    pub diff_removed_fg: Color,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme {
+            name: String::from("dark"),
+            text_fg: None,
+            text_bg: None,
+            gutter_fg: Color::rgb(110, 110, 110),
+            gutter_bg: None,
+            current_line_bg: Color::rgb(38, 38, 38),
+            current_line_number_fg: Color::rgb(220, 220, 220),
+            status_bar_fg: Color::rgb(0, 0, 0),
+            status_bar_bg: Color::rgb(238, 238, 238),
+            message_bar_fg: None,
+            message_bar_bg: None,
+            selection_bg: Color::rgb(68, 71, 90),
+            search_match_fg: Color::rgb(0, 0, 0),
+            search_match_bg: Color::rgb(241, 196, 15),
+            syntax_keyword: Color::rgb(198, 120, 221),
+            syntax_type: Color::rgb(229, 192, 123),
+            syntax_string: Color::rgb(152, 195, 121),
+            syntax_comment: Color::rgb(106, 115, 125),
+            syntax_number: Color::rgb(209, 154, 102),
+            diff_added_fg: Color::rgb(152, 195, 121),
+            diff_removed_fg: Color::rgb(224, 108, 117),
+        }
+    }
+
+    pub fn light() -> Self {
+        Theme {
+            name: String::from("light"),
+            text_fg: Some(Color::rgb(36, 41, 46)),
+            text_bg: Some(Color::rgb(255, 255, 255)),
+            gutter_fg: Color::rgb(150, 150, 150),
+            gutter_bg: Some(Color::rgb(245, 245, 245)),
+            current_line_bg: Color::rgb(240, 244, 250),
+            current_line_number_fg: Color::rgb(36, 41, 46),
+            status_bar_fg: Color::rgb(255, 255, 255),
+            status_bar_bg: Color::rgb(3, 102, 214),
+            message_bar_fg: Some(Color::rgb(36, 41, 46)),
+            message_bar_bg: Some(Color::rgb(255, 255, 255)),
+            selection_bg: Color::rgb(200, 225, 255),
+            search_match_fg: Color::rgb(36, 41, 46),
+            search_match_bg: Color::rgb(255, 223, 93),
+            syntax_keyword: Color::rgb(215, 58, 73),
+            syntax_type: Color::rgb(111, 66, 193),
+            syntax_string: Color::rgb(3, 47, 98),
+            syntax_comment: Color::rgb(106, 115, 125),
+            syntax_number: Color::rgb(0, 92, 197),
+            diff_added_fg: Color::rgb(34, 134, 58),
+            diff_removed_fg: Color::rgb(203, 36, 49),
+        }
+    }
+
+    /// Foreground for a syntax token; `None` means the default text color.
+    pub fn highlight_color(&self, hl: Highlight) -> Option<Color> {
+        match hl {
+            Highlight::Normal => self.text_fg,
+            Highlight::Keyword => Some(self.syntax_keyword),
+            Highlight::Type => Some(self.syntax_type),
+            Highlight::String => Some(self.syntax_string),
+            Highlight::Comment => Some(self.syntax_comment),
+            Highlight::Number => Some(self.syntax_number),
+        }
+    }
+
+    /// Apply `key = value` entries on top of this theme. Unknown keys and
+    /// malformed colors are ignored so a typo doesn't cost the whole theme.
+    fn apply(&mut self, entries: &[(String, String)]) {
+        for (key, value) in entries {
+            // Optional colors accept "default" to fall back to the terminal's color
+            let optional = if value.eq_ignore_ascii_case("default") {
+                Some(None)
+            } else {
+                Color::parse(value).map(Some)
+            };
+
+            match key.as_str() {
+                "text_fg" => if let Some(c) = optional { self.text_fg = c },
+                "text_bg" => if let Some(c) = optional { self.text_bg = c },
+                "gutter_bg" => if let Some(c) = optional { self.gutter_bg = c },
+                "message_bar_fg" => if let Some(c) = optional { self.message_bar_fg = c },
+                "message_bar_bg" => if let Some(c) = optional { self.message_bar_bg = c },
+                _ => {
+                    let Some(color) = Color::parse(value) else { continue };
+                    match key.as_str() {
+                        "gutter_fg" => self.gutter_fg = color,
+                        "current_line_bg" => self.current_line_bg = color,
+                        "current_line_number_fg" => self.current_line_number_fg = color,
+                        "status_bar_fg" => self.status_bar_fg = color,
+                        "status_bar_bg" => self.status_bar_bg = color,
+                        "selection_bg" => self.selection_bg = color,
+                        "search_match_fg" => self.search_match_fg = color,
+                        "search_match_bg" => self.search_match_bg = color,
+                        "keyword" => self.syntax_keyword = color,
+                        "type" => self.syntax_type = color,
+                        "string" => self.syntax_string = color,
+                        "comment" => self.syntax_comment = color,
+                        "number" => self.syntax_number = color,
+                        "diff_added_fg" => self.diff_added_fg = color,
+                        "diff_removed_fg" => self.diff_removed_fg = color,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// All themes available to the editor: the built-ins followed by any defined
+/// in `themes.ini`.
+///
+/// Each `[name]` section of `themes.ini` defines one theme. It starts from the
+/// built-in named by its `base` key (`dark` if absent) and overrides the colors
+/// it lists. A section named like a built-in replaces that built-in.
+pub struct ThemeSet {
+    themes: Vec<Theme>,
+}
+
+impl ThemeSet {
+    pub fn load() -> Self {
+        let mut set = ThemeSet { themes: vec![Theme::dark(), Theme::light()] };
+
+        for section in config::load_ini("themes.ini") {
+            if section.name.is_empty() {
+                continue;
+            }
+
+            let mut theme = section.get("base")
+                .and_then(|base| set.get(base).cloned())
+                .unwrap_or_else(Theme::dark);
+            theme.name = section.name.clone();
+            theme.apply(&section.entries);
+
+            match set.themes.iter_mut().find(|t| t.name == theme.name) {
+                Some(existing) => *existing = theme,
+                None => set.themes.push(theme),
+            }
+        }
+
+        set
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Theme> {
+        self.themes.iter().find(|t| t.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.themes.iter().map(|t| t.name.as_str()).collect()
+    }
+}
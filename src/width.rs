@@ -0,0 +1,62 @@
+//! How many terminal columns text takes up: tabs reach to the next tab
+//! stop, East Asian wide characters and most emoji take two columns and
+//! combining marks none.
+
+pub const TAB_WIDTH: usize = 4;
+
+/// Columns `ch` takes up, tabs aside.
+pub fn char_width(ch: char) -> usize {
+    match ch as u32 {
+        // Combining diacritics and zero-width spaces and joiners
+        0x0300..=0x036f | 0x200b..=0x200f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// Columns `ch` takes up when it starts at display column `column`.
+pub fn advance(ch: char, column: usize) -> usize {
+    if ch == '\t' { TAB_WIDTH - column % TAB_WIDTH } else { char_width(ch) }
+}
+
+/// Display column at which byte `index` of `line` is shown.
+pub fn column_of(line: &str, index: usize) -> usize {
+    line.get(..index).unwrap_or(line).chars().fold(0, |column, ch| column + advance(ch, column))
+}
+
+/// Columns `text` takes up when it starts at column 0.
+pub fn text_width(text: &str) -> usize {
+    column_of(text, text.len())
+}
+
+/// The longest start of `text` that fits in `columns`.
+pub fn truncate(text: &str, columns: usize) -> &str {
+    &text[..index_at_column(text, columns)]
+}
+
+/// Byte index of the character shown at display `column` of `line`, or the
+/// end of the line if it is shorter than that.
+pub fn index_at_column(line: &str, column: usize) -> usize {
+    let mut start = 0;
+    for (i, ch) in line.char_indices() {
+        let end = start + advance(ch, start);
+        if end > column {
+            return i;
+        }
+        start = end;
+    }
+    line.len()
+}